@@ -1,6 +1,8 @@
-use midomoji_clone::dictionary::*;
-use midomoji_clone::token::Token;
-use midomoji_clone::lattice::Lattice;
+use midomoji_clone::dictionary::feature_builder::split_feature;
+use midomoji_clone::tokenizer::{Tokenizer, Morpheme, Worker};
+use midomoji_clone::formatter::Formatter;
+use midomoji_clone::error::{Error, Result};
+use midomoji_clone::util::exit_with_error;
 
 use std::env;
 use std::env::Args;
@@ -10,20 +12,28 @@ use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::BufWriter;
-use memmap::*;
+
+/// 解析結果の出力形式
+enum OutputFormat {
+    Template(Formatter), // テンプレートに従った形式(デフォルトはMeCabと同じ "表層形\t素性"、文の終わりにEOS)
+    Wakati,              // 1文1行で表層形を空白区切り
+    Jsonl,               // 1文1行のJSON
+}
 
 fn main() {
     let options = parse_args(env::args());
     // 辞書構築
-    let dict_file: File = File::open(options.get("dict").unwrap()).unwrap();
-    let mmap: Mmap = unsafe {
-        MmapOptions::new().map(&dict_file).ok().unwrap()
-    };
-    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap);
+    let mut tokenizer = Tokenizer::new(options.get("dict").unwrap()).unwrap_or_else(|e| exit_with_error(&e));
+    // ユーザー辞書
+    if let Some(paths) = options.get("user_dict") {
+        for path in paths.split(',') {
+            tokenizer.add_user_dict(path).unwrap_or_else(|e| exit_with_error(&e));
+        }
+    }
 
     // reader
-    let mut reader: BufReader<Box<Read>> = if let Some(input) = options.get("input") {
-        BufReader::new(Box::new(File::open(input).ok().unwrap()))
+    let mut reader: BufReader<Box<dyn Read>> = if let Some(input) = options.get("input") {
+        BufReader::new(Box::new(File::open(input).unwrap_or_else(|e| exit_with_error(&Error::Io(e)))))
     } else {
         BufReader::new(Box::new(io::stdin()))
     };
@@ -31,20 +41,135 @@ fn main() {
     // writer
     let mut writer = BufWriter::new(io::stdout());
 
-    analyze(dict_set, &mut reader, &mut writer);
+    // N-Best
+    let nbest: Option<usize> = options.get("nbest").map(|n| {
+        match n.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => {
+                eprintln!("--nbest には1以上の整数を指定してください: {}", n);
+                std::process::exit(1);
+            },
+        }
+    });
+
+    // 出力形式
+    let templates = ["node_format", "unk_format", "bos_format", "eos_format"];
+    let has_template = templates.iter().any(|k| options.contains_key(*k));
+    let format = match (options.get("output").map(|f| f.as_str()), has_template) {
+        (None, _) | (Some("mecab"), _) => OutputFormat::Template(build_formatter(&options).unwrap_or_else(|e| exit_with_error(&e))),
+        (Some(_), true) => {
+            eprintln!("--node-format などのテンプレートは --output mecab の場合のみ指定できます。");
+            std::process::exit(1);
+        },
+        (Some("wakati"), _) => OutputFormat::Wakati,
+        (Some("jsonl"), _)  => OutputFormat::Jsonl,
+        (Some(f), _) => {
+            eprintln!("不明な出力形式: {}", f);
+            std::process::exit(1);
+        },
+    };
+
+    if let Err(e) = analyze(&tokenizer, nbest, &format, &mut reader, &mut writer) {
+        exit_with_error(&e);
+    }
+}
+
+/// --node-format, --unk-format, --bos-format, --eos-format からFormatterを生成する
+/// 指定がないテンプレートはMeCabのデフォルトと同じ
+fn build_formatter(options: &HashMap<String, String>) -> Result<Formatter> {
+    let mut formatter = match options.get("node_format") {
+        Some(node_format) => Formatter::new(node_format)?,
+        None              => Formatter::mecab(),
+    };
+    if let Some(unk_format) = options.get("unk_format") {
+        formatter.set_unk_format(unk_format)?;
+    }
+    if let Some(bos_format) = options.get("bos_format") {
+        formatter.set_bos_format(bos_format)?;
+    }
+    if let Some(eos_format) = options.get("eos_format") {
+        formatter.set_eos_format(eos_format)?;
+    }
+    Ok(formatter)
 }
 
-fn analyze<R: Read, W: Write>(dict_set: DictionarySet<Token>, reader: &mut BufReader<R>, writer: &mut W) {
+/// 1行を1文として解析し、解析結果を出力する
+/// nbestが指定された場合はコストの小さい順にn個の解析結果を出力する
+fn analyze<R: Read, W: Write>(tokenizer: &Tokenizer, nbest: Option<usize>, format: &OutputFormat, reader: &mut BufReader<R>, writer: &mut W) -> Result<()> {
     let mut buf = String::new();
-    while reader.read_line(&mut buf).unwrap() > 0 {
-        let mut lattice = Lattice::build(&dict_set, buf.as_bytes());
-        lattice.analyze(&dict_set);
-        for node in lattice.get_result().iter().rev() {
-            writer.write(node.surface).unwrap();
-            writer.write(b"\n").unwrap();
+    // ラティス構造の領域は行をまたいで使いまわす
+    let mut worker = Worker::new();
+    while reader.read_line(&mut buf)? > 0 {
+        {
+            let line = buf.trim_end_matches(&['\n', '\r'][..]);
+            if let Some(n) = nbest {
+                for (_cost, morphemes) in tokenizer.tokenize_nbest_with(&mut worker, line, n) {
+                    write_result(line, &morphemes, format, writer)?;
+                }
+            } else {
+                write_result(line, &tokenizer.tokenize_with(&mut worker, line), format, writer)?;
+            }
         }
         buf.clear();
     }
+    writer.flush()?;
+    Ok(())
+}
+
+/// 解析結果を出力する
+///
+/// # Arguments
+///
+/// * `text`      - 解析した文字列
+/// * `morphemes` - 解析結果
+/// * `format`    - 出力形式
+fn write_result<W: Write>(text: &str, morphemes: &[Morpheme], format: &OutputFormat, writer: &mut W) -> io::Result<()> {
+    match format {
+        OutputFormat::Template(formatter) => formatter.write(text, morphemes, writer),
+        OutputFormat::Wakati => {
+            for (i, m) in morphemes.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b" ")?;
+                }
+                writer.write_all(m.surface.as_bytes())?;
+            }
+            writer.write_all(b"\n")
+        },
+        OutputFormat::Jsonl => {
+            write!(writer, "{{\"text\":{},\"tokens\":[", json_string(text))?;
+            for (i, m) in morphemes.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                let feature: Vec<String> = split_feature(m.feature).iter().map(|f| json_string(f)).collect();
+                write!(
+                    writer,
+                    "{{\"surface\":{},\"byte_start\":{},\"byte_end\":{},\"char_start\":{},\"char_end\":{},\"left_id\":{},\"right_id\":{},\"cost\":{},\"dict_id\":{},\"feature\":[{}]}}",
+                    json_string(m.surface), m.byte_start, m.byte_end, m.char_start, m.char_end, m.left_id, m.right_id, m.cost, m.dict_id, feature.join(","),
+                )?;
+            }
+            writer.write_all(b"]}\n")
+        },
+    }
+}
+
+/// 文字列をJSONの文字列リテラルにする
+fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
 }
 
 fn parse_args(mut args: Args) -> HashMap<String, String> {
@@ -59,10 +184,24 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
             if arg == "-h" || arg == "--help" {
                 eprintln!("{}", include_str!("../resources/analyze.txt"));
                 std::process::exit(1);
-            } else if options.get("dict") == None {
-                options.insert("dict".to_string(), arg);
             } else if arg == "-i" || arg == "--input" {
                 key = Some("input".to_string());
+            } else if arg == "-u" || arg == "--user-dict" {
+                key = Some("user_dict".to_string());
+            } else if arg == "-n" || arg == "--nbest" {
+                key = Some("nbest".to_string());
+            } else if arg == "-O" || arg == "--output" {
+                key = Some("output".to_string());
+            } else if arg == "--node-format" {
+                key = Some("node_format".to_string());
+            } else if arg == "--unk-format" {
+                key = Some("unk_format".to_string());
+            } else if arg == "--bos-format" {
+                key = Some("bos_format".to_string());
+            } else if arg == "--eos-format" {
+                key = Some("eos_format".to_string());
+            } else if !options.contains_key("dict") {
+                options.insert("dict".to_string(), arg);
             } else {
                 eprintln!("不明なオプション: {}", arg);
                 std::process::exit(1);
@@ -71,7 +210,7 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     }
     let required_opts = ["dict"];
     for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
-        if options.get(*k) == None {
+        if !options.contains_key(*k) {
             eprintln!("{}", include_str!("../resources/analyze.txt"));
             std::process::exit(1);
         }
@@ -1,20 +1,43 @@
 extern crate csv;
 
-use midomoji_clone::dictionary::trie::Trie;
+use midomoji_clone::dictionary::{DictionaryParts, DictionarySet};
+use midomoji_clone::dictionary::trie::{Trie, CharCodes, DoubleArrayParts};
+use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::feature_builder::FeatureBuilder;
+use midomoji_clone::dictionary::char_def::CharDefBuilder;
+use midomoji_clone::dictionary::lex_reader::{read_csv, parse_record};
+use midomoji_clone::error::Result;
+use midomoji_clone::lattice::Lattice;
 use midomoji_clone::token::Token;
 use midomoji_clone::util::*;
 
 use std::env;
 use std::env::Args;
-use std::fs::File;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::time::Duration;
+use memmap::*;
 
 fn main() {
     let options = parse_args(env::args());
     let sub_command = options.get("sub_command").unwrap();
-    let lex         = options.get("lex").unwrap();
-    if sub_command == "double_array" {
-        build_double_array(lex);
+    let result = if sub_command == "double_array" {
+        let threads = options.get("threads").map(|n| n.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("--threads には整数を指定してください: {}", n);
+            std::process::exit(1);
+        }));
+        build_double_array(options.get("lex").unwrap(), threads)
+    } else if sub_command == "char_double_array" {
+        bench_char_double_array(options.get("lex").unwrap(), options.get("corpus").unwrap())
+    } else if sub_command == "lattice" {
+        bench_lattice(options.get("dict").unwrap(), options.get("corpus").unwrap())
+    } else {
+        bench_analyze(options.get("dict").unwrap(), options.get("corpus").unwrap())
+    };
+    if let Err(e) = result {
+        exit_with_error(&e);
     }
 }
 
@@ -24,6 +47,10 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     if let Some(sub_command) = args.next() {
         if sub_command == "double_array" {
             parse_args_double_array(args, &mut options);
+        } else if sub_command == "char_double_array" {
+            parse_args_char_double_array(args, &mut options);
+        } else if sub_command == "lattice" || sub_command == "analyze" {
+            parse_args_corpus(&sub_command, args, &mut options);
         } else {
             eprintln!("不明なサブコマンド: {}", sub_command);
             std::process::exit(1);
@@ -46,7 +73,9 @@ fn parse_args_double_array(args: Args, options: &mut HashMap<String, String>) {
             if arg == "-h" || arg == "--help" {
                 eprintln!("{}", include_str!("../resources/bench.txt"));
                 std::process::exit(1);
-            } else if options.get("lex") == None {
+            } else if arg == "--threads" {
+                key = Some("threads".to_string());
+            } else if !options.contains_key("lex") {
                 options.insert("lex".to_string(), arg);
             } else {
                 eprintln!("不明なオプション: {}", arg);
@@ -56,40 +85,279 @@ fn parse_args_double_array(args: Args, options: &mut HashMap<String, String>) {
     }
     let required_opts = ["lex"];
     for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
-        if options.get(*k) == None {
+        if !options.contains_key(*k) {
             eprintln!("{}", include_str!("../resources/bench.txt"));
             std::process::exit(1);
         }
     }
 }
 
+fn parse_args_char_double_array(args: Args, options: &mut HashMap<String, String>) {
+    options.insert("sub_command".to_string(), "char_double_array".to_string());
+    for arg in args {
+        if arg == "-h" || arg == "--help" {
+            eprintln!("{}", include_str!("../resources/bench.txt"));
+            std::process::exit(1);
+        } else if !options.contains_key("lex") {
+            options.insert("lex".to_string(), arg);
+        } else if !options.contains_key("corpus") {
+            options.insert("corpus".to_string(), arg);
+        } else {
+            eprintln!("不明なオプション: {}", arg);
+            std::process::exit(1);
+        }
+    }
+    let required_opts = ["lex", "corpus"];
+    for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
+        if !options.contains_key(*k) {
+            eprintln!("{}", include_str!("../resources/bench.txt"));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// <DICT_PATH> <CORPUS_PATH> を引数にとるサブコマンドの引数を解析する
+fn parse_args_corpus(sub_command: &str, args: Args, options: &mut HashMap<String, String>) {
+    options.insert("sub_command".to_string(), sub_command.to_string());
+    for arg in args {
+        if arg == "-h" || arg == "--help" {
+            eprintln!("{}", include_str!("../resources/bench.txt"));
+            std::process::exit(1);
+        } else if !options.contains_key("dict") {
+            options.insert("dict".to_string(), arg);
+        } else if !options.contains_key("corpus") {
+            options.insert("corpus".to_string(), arg);
+        } else {
+            eprintln!("不明なオプション: {}", arg);
+            std::process::exit(1);
+        }
+    }
+    let required_opts = ["dict", "corpus"];
+    for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
+        if !options.contains_key(*k) {
+            eprintln!("{}", include_str!("../resources/bench.txt"));
+            std::process::exit(1);
+        }
+    }
+}
 
-fn build_double_array(lex: &str) {
+/// トライ木の構築とダブル配列への変換にかかる時間を表示する
+/// threadsを指定した場合は、Trie::to_double_array_parallelで変換する
+fn build_double_array(lex: &str, threads: Option<usize>) -> Result<()> {
     let mut timer = Timer::new();
     // 形態素辞書構築
     timer.start();
-    let mut trie: Trie<Token> = Trie::new();
-    {
-        let mut lex_reader = csv::Reader::from_reader(File::open(lex).ok().unwrap());
-        for result in lex_reader.records() {
-            let record = result.ok().unwrap();
-            let lex = &record[0];
-            let token = Token {
-                left_id : record[1].parse::<u16>().ok().unwrap(),
-                right_id: record[2].parse::<u16>().ok().unwrap(),
-                cost    : record[3].parse::<i16>().ok().unwrap(),
-            };
-            trie.set(lex, token);
-        }
-    }
+    let trie = read_trie(lex)?;
     println!("build trie complete");
     timer.stop();
     timer.print();
 
     timer.reset();
     timer.start();
-    trie.to_double_array();
-    println!("build double_array complete");
+    let (base_arr, ..) = match threads {
+        Some(threads) => trie.to_double_array_parallel(threads)?,
+        None          => trie.to_double_array()?,
+    };
+    println!("build double_array complete ({} nodes)", base_arr.len());
     timer.stop();
     timer.print();
+    Ok(())
+}
+
+/// バイト単位のダブル配列(Trie::to_double_array)と文字単位のダブル配列(Trie::to_char_double_array)の
+/// 構築時間・サイズと、コーパスの各文字の位置からの共通接頭辞検索にかかる時間を比較する
+fn bench_char_double_array(lex: &str, corpus: &str) -> Result<()> {
+    let lines = read_corpus(corpus)?;
+    let bytes: usize = lines.iter().map(|line| line.len()).sum();
+    println!("{} sentences, {} bytes", lines.len(), bytes);
+
+    let mut timer = Timer::new();
+    let trie = read_trie(lex)?;
+    timer.start();
+    let byte_parts = trie.to_double_array()?;
+    timer.stop();
+    println!("[byte] build: {:?}", timer.duration());
+    let byte_buf = write_double_array(&byte_parts, None)?;
+
+    let trie = read_trie(lex)?;
+    timer.reset();
+    timer.start();
+    let (char_parts, char_codes) = trie.to_char_double_array()?;
+    timer.stop();
+    println!("[char] build: {:?}", timer.duration());
+    let char_buf = write_double_array(&char_parts, Some(&char_codes))?;
+
+    let byte_dict: DictionarySet<Token> = DictionarySet::new(&byte_buf)?;
+    let char_dict: DictionarySet<Token> = DictionarySet::new(&char_buf)?;
+    let mut found = Vec::new();
+    for (name, dict_set) in [("byte", &byte_dict), ("char", &char_dict)].iter() {
+        let double_array = dict_set.double_array();
+        let size = double_array.base_arr().len() * 8 + (double_array.char_codes().len() + double_array.code_chars().len()) * 4;
+        println!("[{}] size: {} nodes, {} bytes (base + check + char codes)", name, double_array.base_arr().len(), size);
+
+        // 各文字の位置から共通接頭辞検索を行う
+        timer.reset();
+        timer.start();
+        let mut matches = 0;
+        for line in lines.iter() {
+            for (pos, _) in line.char_indices() {
+                matches += dict_set.common_prefix_iter(&line.as_bytes()[pos..]).count();
+            }
+        }
+        timer.stop();
+        print_throughput(&format!("[{}] common prefix search", name), lines.len(), bytes, timer.duration());
+        println!("[{}] matches: {}", name, matches);
+        found.push((matches, timer.duration()));
+    }
+
+    // 両者の検索結果が同じであることの簡易的な確認
+    assert_eq!(found[0].0, found[1].0);
+    println!("speedup: {:.2}x", as_secs(found[0].1) / as_secs(found[1].1));
+    Ok(())
+}
+
+/// 形態素辞書ファイルを読み込んでトライ木を構築する。素性IDは行番号にする
+fn read_trie(lex: &str) -> Result<Trie<Token>> {
+    let mut trie: Trie<Token> = Trie::new();
+    let mut feature_id = 0;
+    read_csv(lex, |record| {
+        let (lex, left_id, right_id, cost) = parse_record(record)?;
+        let token = Token::new(left_id, right_id, cost, feature_id);
+        feature_id += 1;
+        trie.set(lex, token);
+        Ok(())
+    })?;
+    Ok(trie)
+}
+
+/// ダブル配列だけの辞書をメモリ上に書き込む。DictionarySet::newで読み込めるように8バイト境界に揃える
+fn write_double_array(parts: &DoubleArrayParts<Token>, char_codes: Option<&CharCodes>) -> Result<AlignedBytes> {
+    let (base_arr, check_arr, data_arr, data_offsets) = parts;
+    let parts = DictionaryParts {
+        base_arr, check_arr, data_arr, data_offsets, char_codes,
+        matrix: &MatrixBuilder::new(1, 1), features: &FeatureBuilder::new(), char_def: &CharDefBuilder::new(),
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes)?;
+    Ok(AlignedBytes::new(&bytes))
+}
+
+/// コーパスの全行を解析して、文ごとにラティス構造を生成する場合と
+/// ひとつのラティス構造を使いまわす場合(Lattice::clear_and_build)の時間を比較する
+fn bench_lattice(dict: &str, corpus: &str) -> Result<()> {
+    let mmap = map_file(dict)?;
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap)?;
+    let lines = read_corpus(corpus)?;
+    let bytes: usize = lines.iter().map(|line| line.len()).sum();
+    println!("{} sentences, {} bytes", lines.len(), bytes);
+
+    // 文ごとにラティス構造を生成する
+    let mut timer = Timer::new();
+    timer.start();
+    let mut nodes_new = 0;
+    for line in lines.iter() {
+        let mut lattice = Lattice::build(&dict_set, line.as_bytes());
+        lattice.analyze(&dict_set);
+        nodes_new += lattice.get_result().len();
+    }
+    timer.stop();
+    let elapsed_new = timer.duration();
+    print_throughput("Lattice::build", lines.len(), bytes, elapsed_new);
+
+    // ラティス構造を使いまわす
+    timer.reset();
+    timer.start();
+    let mut nodes_reuse = 0;
+    let mut lattice = Lattice::new(0);
+    for line in lines.iter() {
+        lattice.clear_and_build(&dict_set, &[], line.as_bytes());
+        lattice.analyze(&dict_set);
+        nodes_reuse += lattice.get_result().len();
+    }
+    timer.stop();
+    let elapsed_reuse = timer.duration();
+    print_throughput("Lattice::clear_and_build", lines.len(), bytes, elapsed_reuse);
+
+    // 両者の解析結果が同じであることの簡易的な確認
+    assert_eq!(nodes_new, nodes_reuse);
+    println!("speedup: {:.2}x", as_secs(elapsed_new) / as_secs(elapsed_reuse));
+    Ok(())
+}
+
+/// コーパスの全行を解析して、スループットと
+/// ラティス構造の構築(Lattice::clear_and_build)・解析(Lattice::analyze)・解析結果の取り出しのそれぞれにかかった時間を表示する
+/// あわせてラティス構造の大きさ(1バイトあたりのノード数)を表示する
+fn bench_analyze(dict: &str, corpus: &str) -> Result<()> {
+    let mmap = map_file(dict)?;
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap)?;
+    let lines = read_corpus(corpus)?;
+    let bytes: usize = lines.iter().map(|line| line.len()).sum();
+    println!("{} sentences, {} bytes", lines.len(), bytes);
+
+    let mut build_timer   = Timer::new();
+    let mut analyze_timer = Timer::new();
+    let mut extract_timer = Timer::new();
+    let mut nodes     = 0; // ノード数の合計(BOS, EOSを含む)
+    let mut max_nodes = 0; // 1文のノード数の最大
+    let mut morphemes = 0; // 解析結果の形態素数の合計
+    let mut lattice = Lattice::new(0);
+    let mut result: Vec<(&[u8], &str)> = Vec::new();
+    for line in lines.iter() {
+        build_timer.start();
+        lattice.clear_and_build(&dict_set, &[], line.as_bytes());
+        build_timer.stop();
+
+        analyze_timer.start();
+        lattice.analyze(&dict_set);
+        analyze_timer.stop();
+
+        // 解析結果の表層形と素性文字列を文字列の先頭から順に取り出す
+        extract_timer.start();
+        result.clear();
+        result.extend(lattice.get_result().iter().rev().filter(|node| node.len > 0).map(|node| {
+            (node.surface(line.as_bytes()), node.feature(&dict_set, &[]))
+        }));
+        morphemes += result.len();
+        extract_timer.stop();
+
+        let n = lattice.node_count();
+        nodes += n;
+        if n > max_nodes {
+            max_nodes = n;
+        }
+    }
+    let total = build_timer.duration() + analyze_timer.duration() + extract_timer.duration();
+    print_throughput("total  ", lines.len(), bytes, total);
+    println!("build  : {:?}", build_timer.duration());
+    println!("analyze: {:?}", analyze_timer.duration());
+    println!("extract: {:?}", extract_timer.duration());
+    println!("morphemes: {} ({:.2} per sentence)", morphemes, morphemes as f64 / lines.len() as f64);
+    println!(
+        "nodes: {} ({:.2} per byte, {:.2} per sentence, max {} per sentence)",
+        nodes, nodes as f64 / bytes as f64, nodes as f64 / lines.len() as f64, max_nodes,
+    );
+    Ok(())
+}
+
+fn map_file(path: &str) -> Result<Mmap> {
+    let file = File::open(path)?;
+    Ok(unsafe { MmapOptions::new().map(&file)? })
+}
+
+/// 1行1文のコーパスを読み込む
+fn read_corpus(path: &str) -> Result<Vec<String>> {
+    let lines = BufReader::new(File::open(path)?).lines().collect::<std::io::Result<Vec<String>>>()?;
+    Ok(lines)
+}
+
+fn print_throughput(name: &str, sentences: usize, bytes: usize, elapsed: Duration) {
+    let secs = as_secs(elapsed);
+    println!(
+        "{}: {:?} ({:.0} sentences/sec, {:.2} MB/sec)",
+        name, elapsed, sentences as f64 / secs, bytes as f64 / secs / 1_000_000.0,
+    );
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
 }
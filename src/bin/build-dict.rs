@@ -2,23 +2,94 @@ extern crate csv;
 
 use midomoji_clone::dictionary::*;
 use midomoji_clone::dictionary::trie::Trie;
-use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::double_array_builder::DoubleArrayBuilder;
+use midomoji_clone::dictionary::external_sort::ExternalSorter;
+use midomoji_clone::dictionary::matrix_builder::{MatrixBuilder, MatrixEncoding};
+use midomoji_clone::dictionary::feature_builder::*;
+use midomoji_clone::dictionary::char_def::CharDefBuilder;
+use midomoji_clone::dictionary::lex_reader::{read_csv, parse_record};
+use midomoji_clone::error::{Error, Result};
 use midomoji_clone::token::Token;
 use midomoji_clone::util::*;
 
 use std::env;
 use std::env::Args;
-use std::io::prelude::*;
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
 use std::collections::HashMap;
 
+/// matrix.defを読み込むスレッド数のデフォルト
+const DEFAULT_THREADS: usize = 4;
+/// --streaming でメモリ上で並べ替える単語数のデフォルト
+const DEFAULT_SORT_CHUNK: usize = 1_000_000;
+
+/// 辞書の構築方法
+struct BuildOptions<'a> {
+    unknown  : Option<(&'a str, &'a str)>, // 未知語の定義ファイル(char.def, unk.def)のパス
+    threads  : usize,                      // matrix.defを読み込む(parallelの場合はダブル配列を構築する)スレッド数
+    encoding : MatrixEncoding,             // 辞書ファイルでの連接コスト表の形式
+    char_unit: bool,                       // trueなら文字単位、falseならバイト単位のダブル配列を構築する
+    streaming: Option<usize>,              // 指定した場合、トライ木を作らずに、単語をこの数ずつ並べ替えながらダブル配列を構築する
+    parallel : bool,                       // trueならダブル配列を複数のスレッドで構築する
+}
+
 fn main() {
     let options = parse_args(env::args());
     let lex    = options.get("lex").unwrap();
     let matrix = options.get("matrix").unwrap();
     let output = options.get("output").unwrap();
-    build(lex, matrix, output);
+    let unknown = match (options.get("char_def"), options.get("unk_def")) {
+        (Some(char_def), Some(unk_def)) => Some((char_def.as_str(), unk_def.as_str())),
+        (None, None) => None,
+        _ => {
+            eprintln!("--char-def と --unk-def は両方指定してください。");
+            std::process::exit(1);
+        },
+    };
+    let threads = match options.get("threads").map(|n| n.parse::<usize>()) {
+        None => DEFAULT_THREADS,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            eprintln!("--threads には1以上の整数を指定してください: {}", options.get("threads").unwrap());
+            std::process::exit(1);
+        },
+    };
+    let encoding = match options.get("matrix_encoding") {
+        None => MatrixEncoding::Dense,
+        Some(name) => MatrixEncoding::from_name(name).unwrap_or_else(|| {
+            eprintln!("不明な連接コスト表の形式: {}", name);
+            std::process::exit(1);
+        }),
+    };
+    let char_unit = match options.get("key_unit").map(|name| name.as_str()) {
+        None | Some("byte") => false,
+        Some("char") => true,
+        Some(name) => {
+            eprintln!("不明なダブル配列の遷移の単位: {}", name);
+            std::process::exit(1);
+        },
+    };
+    let streaming = match options.get("sort_chunk").map(|n| n.parse::<usize>()) {
+        None if options.contains_key("streaming") => Some(DEFAULT_SORT_CHUNK),
+        None => None,
+        Some(Ok(n)) if n > 0 => Some(n),
+        Some(_) => {
+            eprintln!("--sort-chunk には1以上の整数を指定してください: {}", options.get("sort_chunk").unwrap());
+            std::process::exit(1);
+        },
+    };
+    let parallel = options.contains_key("parallel");
+    if streaming.is_some() && char_unit {
+        eprintln!("--streaming と --key-unit char は同時に指定できません。");
+        std::process::exit(1);
+    }
+    if parallel && (streaming.is_some() || char_unit) {
+        eprintln!("--parallel は --streaming, --key-unit char と同時に指定できません。");
+        std::process::exit(1);
+    }
+    let build_options = BuildOptions { unknown, threads, encoding, char_unit, streaming, parallel };
+    if let Err(e) = build(lex, matrix, output, &build_options) {
+        exit_with_error(&e);
+    }
 }
 
 fn parse_args(mut args: Args) -> HashMap<String, String> {
@@ -33,11 +104,27 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
             if arg == "-h" || arg == "--help" {
                 eprintln!("{}", include_str!("../resources/build-dict.txt"));
                 std::process::exit(1);
-            } else if options.get("lex") == None {
+            } else if arg == "--char-def" {
+                key = Some("char_def".to_string());
+            } else if arg == "--unk-def" {
+                key = Some("unk_def".to_string());
+            } else if arg == "--threads" {
+                key = Some("threads".to_string());
+            } else if arg == "--matrix-encoding" {
+                key = Some("matrix_encoding".to_string());
+            } else if arg == "--key-unit" {
+                key = Some("key_unit".to_string());
+            } else if arg == "--streaming" {
+                options.insert("streaming".to_string(), String::new());
+            } else if arg == "--parallel" {
+                options.insert("parallel".to_string(), String::new());
+            } else if arg == "--sort-chunk" {
+                key = Some("sort_chunk".to_string());
+            } else if !options.contains_key("lex") {
                 options.insert("lex".to_string(), arg);
-            } else if options.get("matrix") == None {
+            } else if !options.contains_key("matrix") {
                 options.insert("matrix".to_string(), arg);
-            } else if options.get("output") == None {
+            } else if !options.contains_key("output") {
                 options.insert("output".to_string(), arg);
             } else {
                 eprintln!("不明なオプション: {}", arg);
@@ -47,7 +134,7 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     }
     let required_opts = ["lex", "matrix", "output"];
     for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
-        if options.get(*k) == None {
+        if !options.contains_key(*k) {
             eprintln!("{}", include_str!("../resources/build-dict.txt"));
             std::process::exit(1);
         }
@@ -55,80 +142,132 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     options
 }
 
-fn build(lex: &str, matrix: &str, output: &str) {
+/// 辞書を構築する
+///
+/// # Arguments
+///
+/// * `lex`     - 形態素辞書ファイル(lex.csv)のパス
+/// * `matrix`  - 連接コスト表(matrix.def)のパス
+/// * `output`  - 出力する辞書ファイルのパス
+/// * `options` - 辞書の構築方法
+fn build(lex: &str, matrix: &str, output: &str, options: &BuildOptions) -> Result<()> {
     let mut timer = Timer::new();
-    // Err(Error::new(ErrorKind::InvalidData, "invalid format. left_max, right_max not found."));
-    let mut matrix_builder = MatrixBuilder::new(0, 0);
-    // header読み込み
-    timer.start();
-    {
-        let matrix_reader: BufReader<File> = BufReader::new(File::open(&matrix).ok().unwrap());
-        for result in matrix_reader.lines() {
-            let line: String = result.ok().unwrap();
-            let mut record = line.trim().split_whitespace();
-            let left_max: usize  = record.next().unwrap().parse::<usize>().ok().unwrap();
-            let right_max: usize = record.next().unwrap().parse::<usize>().ok().unwrap();
-            matrix_builder = MatrixBuilder::new(left_max, right_max);
-            break;
-        }
-    }
-    println!("build header complete");
-    timer.stop();
-    timer.print();
-
     // matrix構築
-    timer.reset();
     timer.start();
-    {
-        let matrix_reader: BufReader<File> = BufReader::new(File::open(&matrix).ok().unwrap());
-        let mut lines = matrix_reader.lines();
-        lines.next();
-        for result in lines {
-            let line: String = result.ok().unwrap();
-            let mut record = line.trim().split_whitespace();
-            let left_id: usize  = record.next().unwrap().parse::<usize>().ok().unwrap();
-            let right_id: usize = record.next().unwrap().parse::<usize>().ok().unwrap();
-            let cost: i16       = record.next().unwrap().parse::<i16>().ok().unwrap();
-            matrix_builder.set(left_id, right_id, cost);
-        }
-    }
+    let mut matrix_builder = MatrixBuilder::from_matrix_def_bytes(fs::read(matrix)?, options.threads)?;
+    matrix_builder.set_encoding(options.encoding);
     println!("build matrix complete");
     timer.stop();
     timer.print();
 
     // 形態素辞書構築
-    timer.reset();
-    timer.start();
-    let mut trie: Trie<Token> = Trie::new();
-    {
-        let mut lex_reader = csv::Reader::from_reader(File::open(lex).ok().unwrap());
-        for result in lex_reader.records() {
-            let record = result.ok().unwrap();
-            let lex = &record[0];
-            let token = Token {
-                left_id : record[1].parse::<u16>().ok().unwrap(),
-                right_id: record[2].parse::<u16>().ok().unwrap(),
-                cost    : record[3].parse::<i16>().ok().unwrap(),
-            };
-            trie.set(lex, token);
-        }
-    }
-    println!("build trie complete");
-    timer.stop();
-    timer.print();
+    let mut features = FeatureBuilder::new();
+    let ((base_arr, check_arr, data_arr, data_offsets), char_codes) = if let Some(chunk_size) = options.streaming {
+        timer.reset();
+        timer.start();
+        let mut sorter: ExternalSorter<Token> = ExternalSorter::new(chunk_size);
+        read_lex(lex, &matrix_builder, &mut features, |surface, token| sorter.push(surface, token))?;
+        println!("sort lex complete");
+        timer.stop();
+        timer.print();
+
+        timer.reset();
+        timer.start();
+        let mut builder: DoubleArrayBuilder<Token> = DoubleArrayBuilder::new();
+        sorter.finish(|surface, token| builder.push(surface, token))?;
+        let double_array = builder.finish()?;
+        println!("build double_array complete");
+        timer.stop();
+        timer.print();
+        (double_array, None)
+    } else {
+        timer.reset();
+        timer.start();
+        let mut trie: Trie<Token> = Trie::new();
+        read_lex(lex, &matrix_builder, &mut features, |surface, token| {
+            trie.set(surface, token);
+            Ok(())
+        })?;
+        println!("build trie complete");
+        timer.stop();
+        timer.print();
 
+        timer.reset();
+        timer.start();
+        let double_array = if options.char_unit {
+            let (double_array, char_codes) = trie.to_char_double_array()?;
+            (double_array, Some(char_codes))
+        } else if options.parallel {
+            (trie.to_double_array_parallel(options.threads)?, None)
+        } else {
+            (trie.to_double_array()?, None)
+        };
+        println!("build double_array complete");
+        timer.stop();
+        timer.print();
+        double_array
+    };
+
+    // 未知語定義構築
     timer.reset();
     timer.start();
-    let (base_arr, check_arr, data_arr) = trie.to_double_array();
-    println!("build double_array complete");
+    let mut char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+    if let Some((char_def_path, unk_def_path)) = options.unknown {
+        // char.defはコメントがutf8でない場合もあるので、lossyで読み込む
+        let text = fs::read(char_def_path)?;
+        char_def = CharDefBuilder::from_char_def(&String::from_utf8_lossy(&text))?;
+        read_csv(unk_def_path, |record| {
+            let (name, left_id, right_id, cost) = parse_record(record)?;
+            check_context_ids(&matrix_builder, left_id, right_id)?;
+            let token = Token::new(left_id, right_id, cost, features.push(&join_feature(record.iter().skip(4))));
+            char_def.add_unknown(name, token)
+        })?;
+    } else {
+        char_def.add_unknown("DEFAULT", Token::unknown())?;
+    }
+    println!("build char_def complete");
     timer.stop();
     timer.print();
 
     // 辞書の書き込み
     timer.reset();
     timer.start();
-    DictionarySet::serialize(&base_arr, &check_arr, &data_arr, matrix_builder, &output).ok().unwrap();
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: char_codes.as_ref(),
+        matrix: &matrix_builder, features: &features, char_def: &char_def,
+    };
+    DictionarySet::serialize(&parts, output)?;
     println!("serialize dictionary complete");
     timer.stop();
     timer.print();
+    Ok(())
+}
+
+/// 形態素辞書ファイルを読み込んで、単語ごとに表層形とトークンをfに渡す
+/// 素性文字列はfeaturesに追加する
+fn read_lex<F>(lex: &str, matrix_builder: &MatrixBuilder, features: &mut FeatureBuilder, mut f: F) -> Result<()> where F: FnMut(&str, Token) -> Result<()> {
+    read_csv(lex, |record| {
+        let (lex, left_id, right_id, cost) = parse_record(record)?;
+        check_context_ids(matrix_builder, left_id, right_id)?;
+        let token = Token::new(left_id, right_id, cost, features.push(&join_feature(record.iter().skip(4))));
+        f(lex, token)
+    })
+}
+
+/// 文脈IDが連接コスト表の範囲内かを検証する
+/// matrix.defの1列目は左側の単語の右文脈ID、2列目は右側の単語の左文脈ID
+///
+/// # Arguments
+///
+/// * `matrix_builder` - 連接コスト表
+/// * `left_id`        - 左文脈ID
+/// * `right_id`       - 右文脈ID
+fn check_context_ids(matrix_builder: &MatrixBuilder, left_id: u16, right_id: u16) -> Result<()> {
+    if right_id as usize >= matrix_builder.get_left_max() || left_id as usize >= matrix_builder.get_right_max() {
+        return Err(Error::InvalidInput(format!(
+            "文脈IDが連接コスト表の範囲外です。(left_id={}, right_id={}, left_max={}, right_max={})",
+            left_id, right_id, matrix_builder.get_left_max(), matrix_builder.get_right_max(),
+        )));
+    }
+    Ok(())
 }
@@ -0,0 +1,91 @@
+extern crate csv;
+
+use midomoji_clone::dictionary::*;
+use midomoji_clone::dictionary::user_dict_builder::UserDictBuilder;
+use midomoji_clone::dictionary::lex_reader::read_csv;
+use midomoji_clone::error::Result;
+use midomoji_clone::token::Token;
+use midomoji_clone::util::*;
+
+use std::env;
+use std::env::Args;
+use std::fs::File;
+use std::collections::HashMap;
+use memmap::*;
+
+fn main() {
+    let options = parse_args(env::args());
+    let dict   = options.get("dict").unwrap();
+    let lex    = options.get("lex").unwrap();
+    let output = options.get("output").unwrap();
+    if let Err(e) = build(dict, lex, output) {
+        exit_with_error(&e);
+    }
+}
+
+fn parse_args(mut args: Args) -> HashMap<String, String> {
+    let mut options = HashMap::new();
+    let _script = args.next().unwrap();
+    for arg in args {
+        if arg == "-h" || arg == "--help" {
+            eprintln!("{}", include_str!("../resources/build-user-dict.txt"));
+            std::process::exit(1);
+        } else if !options.contains_key("dict") {
+            options.insert("dict".to_string(), arg);
+        } else if !options.contains_key("lex") {
+            options.insert("lex".to_string(), arg);
+        } else if !options.contains_key("output") {
+            options.insert("output".to_string(), arg);
+        } else {
+            eprintln!("不明なオプション: {}", arg);
+            std::process::exit(1);
+        }
+    }
+    let required_opts = ["dict", "lex", "output"];
+    for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
+        if !options.contains_key(*k) {
+            eprintln!("{}", include_str!("../resources/build-user-dict.txt"));
+            std::process::exit(1);
+        }
+    }
+    options
+}
+
+/// ユーザー辞書を構築する
+///
+/// # Arguments
+///
+/// * `dict` - システム辞書のパス
+/// * `lex`  - ユーザー辞書のcsvのパス
+fn build(dict: &str, lex: &str, output: &str) -> Result<()> {
+    let mut timer = Timer::new();
+    // システム辞書の読み込み
+    timer.start();
+    let dict_file: File = File::open(dict)?;
+    let mmap: Mmap = unsafe { MmapOptions::new().map(&dict_file)? };
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap)?;
+    let mut builder = UserDictBuilder::new(&dict_set);
+    println!("load system dictionary complete");
+    timer.stop();
+    timer.print();
+
+    // 形態素辞書構築
+    timer.reset();
+    timer.start();
+    read_csv(lex, |record| {
+        let columns: Vec<&str> = record.iter().collect();
+        builder.add(&columns)
+    })?;
+    println!("build trie complete");
+    timer.stop();
+    timer.print();
+
+    // 辞書の書き込み
+    timer.reset();
+    timer.start();
+    builder.serialize(output)?;
+    println!("serialize user dictionary complete");
+    timer.stop();
+    timer.print();
+    Ok(())
+}
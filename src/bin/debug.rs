@@ -1,6 +1,8 @@
 use midomoji_clone::dictionary::*;
 use midomoji_clone::token::Token;
 use midomoji_clone::lattice::Lattice;
+use midomoji_clone::util::exit_with_error;
+use midomoji_clone::error::Result;
 
 use std::env;
 use std::env::Args;
@@ -15,14 +17,11 @@ use memmap::*;
 fn main() {
     let options = parse_args(env::args());
     // 辞書構築
-    let dict_file: File = File::open(options.get("dict").unwrap()).unwrap();
-    let mmap: Mmap = unsafe {
-        MmapOptions::new().map(&dict_file).ok().unwrap()
-    };
-    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap);
+    let mmap: Mmap = map_file(options.get("dict").unwrap()).unwrap_or_else(|e| exit_with_error(&e));
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap).unwrap_or_else(|e| exit_with_error(&e));
 
     // reader
-    let mut reader: BufReader<Box<Read>> = BufReader::new(Box::new(io::stdin()));
+    let mut reader: BufReader<Box<dyn Read>> = BufReader::new(Box::new(io::stdin()));
 
     // writer
     //let mut writer = BufWriter::new(io::stdout());
@@ -36,6 +35,8 @@ fn main() {
             search(dict_set, &mut reader);
         } else if sub_command  == "prefix-search" {
             prefix_search(dict_set, &mut reader);
+        } else if sub_command  == "predictive-search" {
+            predictive_search(dict_set, &mut reader);
         } else {
             eprintln!("不明なサブコマンド: {}", sub_command);
             std::process::exit(1);
@@ -44,6 +45,11 @@ fn main() {
     }
 }
 
+fn map_file(path: &str) -> Result<Mmap> {
+    let file = File::open(path)?;
+    Ok(unsafe { MmapOptions::new().map(&file)? })
+}
+
 fn build<R: Read>(dict_set: DictionarySet<Token>, reader: &mut BufReader<R>) {
     let mut buf = String::new();
     while reader.read_line(&mut buf).unwrap() > 0 {
@@ -99,6 +105,20 @@ fn prefix_search<R: Read>(dict_set: DictionarySet<Token>, reader: &mut BufReader
     }
 }
 
+fn predictive_search<R: Read>(dict_set: DictionarySet<Token>, reader: &mut BufReader<R>) {
+    let mut buf = String::new();
+    while reader.read_line(&mut buf).unwrap() > 0 {
+        let line = buf.trim();
+        for (surface, tokens) in dict_set.predictive_search(line, 100) {
+            println!("{}", surface);
+            for (i, token) in tokens.iter().enumerate() {
+                println!("|    {}: {:?}", i, token);
+            }
+        }
+        buf.clear();
+    }
+}
+
 fn parse_args(mut args: Args) -> HashMap<String, String> {
     let mut options = HashMap::new();
     let _script = args.next().unwrap();
@@ -111,9 +131,9 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
             if arg == "-h" || arg == "--help" {
                 eprintln!("{}", include_str!("../resources/debug.txt"));
                 std::process::exit(1);
-            } else if options.get("dict") == None {
+            } else if !options.contains_key("dict") {
                 options.insert("dict".to_string(), arg);
-            } else if options.get("sub_command") == None {
+            } else if !options.contains_key("sub_command") {
                 options.insert("sub_command".to_string(), arg);
             } else {
                 eprintln!("不明なオプション: {}", arg);
@@ -123,7 +143,7 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     }
     let required_opts = ["dict", "sub_command"];
     for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
-        if options.get(*k) == None {
+        if !options.contains_key(*k) {
                 eprintln!("{}", include_str!("../resources/debug.txt"));
                 std::process::exit(1);
         }
@@ -0,0 +1,91 @@
+use midomoji_clone::dictionary::*;
+use midomoji_clone::dictionary::dumper::{write_lex, write_matrix};
+use midomoji_clone::token::Token;
+use midomoji_clone::error::Result;
+use midomoji_clone::util::*;
+
+use std::env;
+use std::env::Args;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+use std::collections::HashMap;
+use memmap::*;
+
+fn main() {
+    let options = parse_args(env::args());
+    let dict   = options.get("dict").unwrap();
+    let lex    = options.get("lex").unwrap();
+    let matrix = options.get("matrix").unwrap();
+    if let Err(e) = dump(dict, lex, matrix) {
+        exit_with_error(&e);
+    }
+}
+
+fn parse_args(mut args: Args) -> HashMap<String, String> {
+    let mut options = HashMap::new();
+    let _script = args.next().unwrap();
+    for arg in args {
+        if arg == "-h" || arg == "--help" {
+            eprintln!("{}", include_str!("../resources/dump-dict.txt"));
+            std::process::exit(1);
+        } else if !options.contains_key("dict") {
+            options.insert("dict".to_string(), arg);
+        } else if !options.contains_key("lex") {
+            options.insert("lex".to_string(), arg);
+        } else if !options.contains_key("matrix") {
+            options.insert("matrix".to_string(), arg);
+        } else {
+            eprintln!("不明なオプション: {}", arg);
+            std::process::exit(1);
+        }
+    }
+    let required_opts = ["dict", "lex", "matrix"];
+    for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
+        if !options.contains_key(*k) {
+            eprintln!("{}", include_str!("../resources/dump-dict.txt"));
+            std::process::exit(1);
+        }
+    }
+    options
+}
+
+/// 辞書ファイルから形態素辞書ファイルと連接コスト表ファイルを書き出す
+///
+/// # Arguments
+///
+/// * `dict`   - 辞書ファイルのパス
+/// * `lex`    - 形態素辞書ファイルの出力先
+/// * `matrix` - 連接コスト表ファイルの出力先
+fn dump(dict: &str, lex: &str, matrix: &str) -> Result<()> {
+    let mut timer = Timer::new();
+    // 辞書読み込み
+    timer.start();
+    let file: File = File::open(dict)?;
+    let mmap: Mmap = unsafe { MmapOptions::new().map(&file)? };
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap)?;
+    println!("load dictionary complete");
+    timer.stop();
+    timer.print();
+
+    // 形態素辞書の書き出し
+    timer.reset();
+    timer.start();
+    let mut writer = BufWriter::new(File::create(lex)?);
+    write_lex(&dict_set, &mut writer)?;
+    writer.flush()?;
+    println!("dump lex complete");
+    timer.stop();
+    timer.print();
+
+    // 連接コスト表の書き出し
+    timer.reset();
+    timer.start();
+    let mut writer = BufWriter::new(File::create(matrix)?);
+    write_matrix(&dict_set, &mut writer)?;
+    writer.flush()?;
+    println!("dump matrix complete");
+    timer.stop();
+    timer.print();
+    Ok(())
+}
@@ -1,12 +1,15 @@
 extern crate csv;
 
 use midomoji_clone::dictionary::*;
+use midomoji_clone::dictionary::feature_builder::*;
+use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::lex_reader::{read_csv, parse_record};
+use midomoji_clone::error::{Error, Result};
 use midomoji_clone::token::Token;
 use midomoji_clone::util::*;
 
 use std::env;
 use std::env::Args;
-use std::io::prelude::*;
 use std::fs::File;
 use std::io::BufReader;
 use std::collections::HashMap;
@@ -17,7 +20,9 @@ fn main() {
     let lex    = options.get("lex").unwrap();
     let matrix = options.get("matrix").unwrap();
     let dict   = options.get("dict").unwrap();
-    test(lex, matrix, dict);
+    if let Err(e) = test(lex, matrix, dict) {
+        exit_with_error(&e);
+    }
 }
 
 fn parse_args(mut args: Args) -> HashMap<String, String> {
@@ -32,11 +37,11 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
             if arg == "-h" || arg == "--help" {
                 eprintln!("{}", include_str!("../resources/test-dict.txt"));
                 std::process::exit(1);
-            } else if options.get("lex") == None {
+            } else if !options.contains_key("lex") {
                 options.insert("lex".to_string(), arg);
-            } else if options.get("matrix") == None {
+            } else if !options.contains_key("matrix") {
                 options.insert("matrix".to_string(), arg);
-            } else if options.get("dict") == None {
+            } else if !options.contains_key("dict") {
                 options.insert("dict".to_string(), arg);
             } else {
                 eprintln!("不明なオプション: {}", arg);
@@ -46,7 +51,7 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     }
     let required_opts = ["lex", "matrix", "dict"];
     for k in required_opts.iter() { // k は std::borrow::Borrow<&str>
-        if options.get(*k) == None {
+        if !options.contains_key(*k) {
             eprintln!("{}", include_str!("../resources/test-dict.txt"));
             std::process::exit(1);
         }
@@ -54,15 +59,13 @@ fn parse_args(mut args: Args) -> HashMap<String, String> {
     options
 }
 
-fn test(lex: &str, matrix: &str, dict: &str) {
+fn test(lex: &str, matrix: &str, dict: &str) -> Result<()> {
     let mut timer = Timer::new();
     // 辞書読み込み
     timer.start();
-    let file: File = File::open(&dict).ok().unwrap();
-    let mmap: Mmap = unsafe {
-        MmapOptions::new().map(&file).ok().unwrap()
-    };
-    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap);
+    let file: File = File::open(dict)?;
+    let mmap: Mmap = unsafe { MmapOptions::new().map(&file)? };
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap)?;
     println!("load dictionary complete");
     timer.stop();
     timer.print();
@@ -71,23 +74,23 @@ fn test(lex: &str, matrix: &str, dict: &str) {
     timer.reset();
     timer.start();
     {
-        let matrix_reader: BufReader<File> = BufReader::new(File::open(&matrix).ok().unwrap());
-        let mut lines = matrix_reader.lines();
-        lines.next();
-        for result in lines {
-            let line: String = result.ok().unwrap();
-            let mut record = line.trim().split_whitespace();
-            let left_id: usize  = record.next().unwrap().parse::<usize>().ok().unwrap();
-            let right_id: usize = record.next().unwrap().parse::<usize>().ok().unwrap();
-            let cost: i16       = record.next().unwrap().parse::<i16>().ok().unwrap();
-            if dict_set.get_matrix(left_id, right_id) != cost {
-                panic!(
-                    "matrix: left_id={}, right_id={}, cost(file)={}, cost(index)={}",
-                    left_id,
-                    right_id,
-                    cost,
-                    dict_set.get_matrix(left_id, right_id),
-                );
+        let matrix_builder = MatrixBuilder::from_matrix_def(BufReader::new(File::open(matrix)?))?;
+        let size = (matrix_builder.get_left_max(), matrix_builder.get_right_max());
+        if dict_set.get_matrix_size() != size {
+            return Err(mismatch(format!("matrix: size(file)={:?}, size(index)={:?}", size, dict_set.get_matrix_size())));
+        }
+        for left_id in 0..size.0 {
+            for right_id in 0..size.1 {
+                let cost = matrix_builder.get(left_id, right_id);
+                if dict_set.get_matrix(left_id, right_id) != cost {
+                    return Err(mismatch(format!(
+                        "matrix: left_id={}, right_id={}, cost(file)={}, cost(index)={}",
+                        left_id,
+                        right_id,
+                        cost,
+                        dict_set.get_matrix(left_id, right_id),
+                    )));
+                }
             }
         }
     }
@@ -98,34 +101,37 @@ fn test(lex: &str, matrix: &str, dict: &str) {
     // trie test
     timer.reset();
     timer.start();
-    {
-        let mut lex_reader = csv::Reader::from_reader(File::open(lex).ok().unwrap());
-        for result in lex_reader.records() {
-            let record = result.ok().unwrap();
-            let lex = &record[0];
-            let token = Token {
-                left_id : record[1].parse::<u16>().ok().unwrap(),
-                right_id: record[2].parse::<u16>().ok().unwrap(),
-                cost    : record[3].parse::<i16>().ok().unwrap(),
-            };
-            let values = dict_set.get_trie(&lex);
-            if values.is_some() {
-                let mut exists = false;
-                for v in values.unwrap() {
-                    if token == *v {
-                        exists = true;
-                        break;
-                    }
-                }
-                if !exists {
-                    panic!("trie: lex={}, token={:?}", lex, token);
-                }
-            } else {
-                panic!("trie: lex={}, token={:?}", lex, token);
-            }
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    read_csv(lex, |record| {
+        let (lex, left_id, right_id, cost) = parse_record(record)?;
+        *counts.entry(lex.to_string()).or_insert(0) += 1;
+        let feature = join_feature(record.iter().skip(4));
+        let exists = match dict_set.get_trie(lex) {
+            Some(values) => values.iter().any(|v| {
+                left_id == v.left_id && right_id == v.right_id && cost == v.cost &&
+                    feature == dict_set.get_feature(v.feature_id as usize)
+            }),
+            None => false,
+        };
+        if !exists {
+            return Err(mismatch(format!("trie: lex={}, left_id={}, right_id={}, cost={}, feature={}", lex, left_id, right_id, cost, feature)));
+        }
+        Ok(())
+    })?;
+    // 1つの表層形に登録されている単語の数が一致する
+    for (lex, count) in counts.iter() {
+        let len = dict_set.get_trie(lex).map_or(0, |values| values.len());
+        if len != *count {
+            return Err(mismatch(format!("trie: lex={}, count(file)={}, count(index)={}", lex, count, len)));
         }
     }
     println!("test trie complete");
     timer.stop();
     timer.print();
+    Ok(())
+}
+
+/// 辞書の内容が元のファイルと一致しない場合のエラー
+fn mismatch(msg: String) -> Error {
+    Error::DictionaryFormat(format!("元のファイルと一致しません。{}", msg))
 }
@@ -1,83 +1,262 @@
 pub mod trie;
+pub mod double_array;
+pub mod double_array_builder;
+pub mod external_sort;
 pub mod matrix_builder;
+pub mod feature_builder;
+pub mod char_def;
+pub mod user_dict_builder;
+pub mod lex_reader;
+pub mod dumper;
 mod bit_cache;
+mod checksum;
 
-use self::matrix_builder::MatrixBuilder;
+use self::matrix_builder::{MatrixBuilder, MatrixEncoding};
+use self::feature_builder::{Feature, FeatureBuilder, FeatureLayout};
+use self::char_def::{CharDefBuilder, CharCategory, CharInfo};
+use self::checksum::Checksum;
+use self::trie::CharCodes;
+use self::double_array::{DoubleArrayRef, EntryIter, KeyUnit, PrefixSearchIter};
+use crate::error::{Error, Result};
 
 use std::fmt::Debug;
 use std::slice;
 use std::mem;
-use std::io;
 use std::io::prelude::*;
+use std::io::BufWriter;
 use std::fs::File;
 use std::ptr;
 
+/// 辞書ファイルの先頭に置くマジックナンバー
+pub const MAGIC: [u8; 8] = *b"MIDOMOJI";
+/// 辞書ファイルのフォーマットのバージョン。フォーマットを変更したら上げる
+pub const FORMAT_VERSION: u32 = 6;
+/// 辞書を構築した環境のエンディアンを判定するためのマーカー
+const ENDIAN_MARKER: u32 = 0x0102_0304;
+/// 各セクションの先頭はこのバイト数の倍数に揃える
+const SECTION_ALIGN: usize = 8;
+
+/// 辞書ファイルのヘッダ
+/// 32bit/64bit環境で同じレイアウトになるように、サイズはすべてu64で持つ
+/// *_idx はファイル先頭からのバイト数、*_len は要素数
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
+#[repr(C)]
 pub struct DictionaryHeader {
-    base_idx        : usize,
-    check_idx       : usize,
-    data_idx        : usize,
-    matrix_idx      : usize,
-    base_len        : usize,
-    check_len       : usize,
-    data_len        : usize,
-    matrix_len      : usize,
-    matrix_left_max : usize,
-    matrix_right_max: usize,
+    magic           : [u8; 8],
+    version         : u32,
+    endian          : u32,
+    checksum        : u64, // この値を0にしたヘッダと、ヘッダ以降のバイト列のチェックサム
+    data_size       : u64, // data_arrの1要素のバイト数
+    base_idx        : u64,
+    check_idx       : u64,
+    data_idx        : u64,
+    data_offsets_idx: u64,
+    feature_offsets_idx: u64,
+    matrix_idx      : u64,
+    matrix_index_idx: u64,
+    feature_bytes_idx  : u64,
+    unknown_idx        : u64,
+    char_map_idx       : u64,
+    char_categories_idx: u64,
+    char_codes_idx  : u64,
+    code_chars_idx  : u64,
+    base_len        : u64,
+    check_len       : u64,
+    data_len        : u64,
+    data_offsets_len: u64,
+    matrix_len      : u64,
+    matrix_index_len: u64,
+    feature_offsets_len: u64,
+    feature_bytes_len  : u64,
+    unknown_len        : u64,
+    char_map_len       : u64,
+    char_categories_len: u64,
+    char_codes_len  : u64,
+    code_chars_len  : u64,
+    matrix_left_max : u64,
+    matrix_right_max: u64,
+    matrix_encoding : u64, // MatrixEncoding
+    matrix_width    : u64, // 連接コスト表(matrix)の列数
+    matrix_default  : u64, // Sparseの場合のデフォルトのコスト(i16)
+    char_default_info  : u64, // char_mapの範囲外の文字のCharInfo
+    key_unit        : u64, // ダブル配列の遷移の単位(0: バイト, 1: 文字)
+}
+
+/// 辞書ファイルのバイト列から、コピーせずにスライスとして参照できる型
+///
+/// # Safety
+///
+/// 実装する型は次の条件を満たさなければならない
+///
+/// * 整数型か、整数型のフィールドだけからなるパディングのない#[repr(C)]の構造体である
+/// * どのようなビット列も有効な値である(bool, char, enum, 参照, ポインタを含まない)
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for usize {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for isize {}
+// 固定長の配列と整数だけからなり、パディングはない
+unsafe impl Pod for DictionaryHeader {}
+
+/// 辞書に格納する値の文脈ID
+/// DictionarySet::newで、単語と未知語の文脈IDが連接コスト表の範囲内かを検証するのに使う
+pub trait ContextIds {
+    /// (左文脈ID, 右文脈ID)。文脈IDを持たない値はNone
+    fn context_ids(&self) -> Option<(usize, usize)> {
+        None
+    }
 }
 
+impl ContextIds for u8 {}
+impl ContextIds for u16 {}
+impl ContextIds for u32 {}
+impl ContextIds for u64 {}
+impl ContextIds for usize {}
+impl ContextIds for i8 {}
+impl ContextIds for i16 {}
+impl ContextIds for i32 {}
+impl ContextIds for i64 {}
+impl ContextIds for isize {}
 
-pub struct DictionarySet<'a, T: Copy + Debug> {
+/// 辞書ファイルに書き込む内容
+/// DictionarySet::write, DictionarySet::serializeに渡す
+pub struct DictionaryParts<'p, T: Copy> {
+    pub base_arr    : &'p [u32],
+    pub check_arr   : &'p [u32],
+    pub data_arr    : &'p [T],
+    pub data_offsets: &'p [u32],
+    pub char_codes  : Option<&'p CharCodes>, // Trie::to_char_double_arrayで構築した場合は文字とコードの対応表、バイト単位の場合はNone
+    pub matrix      : &'p MatrixBuilder,
+    pub features    : &'p FeatureBuilder,
+    pub char_def    : &'p CharDefBuilder<T>,
+}
+
+pub struct DictionarySet<'a, T: Pod> {
     header   : DictionaryHeader,
-    pub base_arr : &'a [u32],
-    pub check_arr: &'a [u32],
-    pub data_arr : &'a [T],
+    double_array: DoubleArrayRef<'a, T>,
+    data_arr : &'a [T],
     pub matrix   : &'a [i16],
+    pub matrix_index: &'a [u32], // 連接コスト表の形式ごとの対応表(EncodedMatrix.index)
+    matrix_encoding : MatrixEncoding,
+    pub feature_offsets: &'a [u32],
+    pub feature_bytes  : &'a [u8],
+    pub unknown_arr    : &'a [T],
+    pub char_map       : &'a [u32],
+    pub char_categories: &'a [CharCategory],
 }
 
-impl<'a, T: Copy + Debug> DictionarySet<'a, T> {
-    /**
-     * byte列を辞書として読み込む
-     */
-    pub fn new(bytes: &[u8]) -> DictionarySet<'a, T> {
+impl<'a, T: Pod> DictionarySet<'a, T> {
+    /// byte列を辞書として読み込む
+    /// マジックナンバー、バージョン、エンディアン、各セクションの範囲とアラインメント、チェックサムを検証し、
+    /// 不正な場合は Error::DictionaryFormat のエラーを返す
+    /// 連接コスト表を持つ辞書は、単語と未知語の文脈IDが範囲内かも検証し、範囲外の場合は Error::InvalidMatrixId のエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 辞書ファイルのバイト列(mmapしたものなど)
+    pub fn new(bytes: &'a [u8]) -> Result<DictionarySet<'a, T>> where T: ContextIds {
         // header
+        let header_size = mem::size_of::<DictionaryHeader>();
+        if bytes.len() < header_size {
+            return Err(invalid_data(format!(
+                "辞書ファイルのサイズがヘッダより小さいです。(size={}, header={})", bytes.len(), header_size
+            )));
+        }
         let header: DictionaryHeader = unsafe {
-            ptr::read(bytes.as_ptr() as *const DictionaryHeader)
+            ptr::read_unaligned(bytes.as_ptr() as *const DictionaryHeader)
         };
+        if header.magic != MAGIC {
+            return Err(invalid_data("midomojiの辞書ファイルではありません。(マジックナンバーが一致しません)".to_string()));
+        }
+        if header.endian != ENDIAN_MARKER {
+            if header.endian.swap_bytes() == ENDIAN_MARKER {
+                return Err(invalid_data("エンディアンが異なる環境で構築された辞書ファイルです。".to_string()));
+            }
+            return Err(invalid_data(format!("エンディアンマーカーが不正です。(marker={:#x})", header.endian)));
+        }
+        if header.version != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "辞書ファイルのバージョンが一致しません。(file={}, expected={})", header.version, FORMAT_VERSION
+            )));
+        }
+        if header.data_size != mem::size_of::<T>() as u64 {
+            return Err(invalid_data(format!(
+                "data_arrの要素のサイズが一致しません。(file={}, expected={})", header.data_size, mem::size_of::<T>()
+            )));
+        }
 
-        // base_arr
-        let base_arr: &'a [u32] = unsafe {
-            slice::from_raw_parts(
-                bytes[header.base_idx..].as_ptr() as *const u32,
-                header.base_len
-            )
-        };
+        // 各セクション
+        let base_arr : &'a [u32] = section(bytes, "base_arr" , header.base_idx , header.base_len)?;
+        let check_arr: &'a [u32] = section(bytes, "check_arr", header.check_idx, header.check_len)?;
+        let data_arr : &'a [T]   = section(bytes, "data_arr" , header.data_idx , header.data_len)?;
+        let data_offsets: &'a [u32] = section(bytes, "data_offsets", header.data_offsets_idx, header.data_offsets_len)?;
+        let feature_offsets: &'a [u32] = section(bytes, "feature_offsets", header.feature_offsets_idx, header.feature_offsets_len)?;
+        let matrix   : &'a [i16] = section(bytes, "matrix"   , header.matrix_idx, header.matrix_len)?;
+        let matrix_index: &'a [u32] = section(bytes, "matrix_index", header.matrix_index_idx, header.matrix_index_len)?;
+        let feature_bytes  : &'a [u8]  = section(bytes, "feature_bytes"  , header.feature_bytes_idx  , header.feature_bytes_len)?;
+        let unknown_arr    : &'a [T]   = section(bytes, "unknown_arr"    , header.unknown_idx        , header.unknown_len)?;
+        let char_map       : &'a [u32] = section(bytes, "char_map"       , header.char_map_idx       , header.char_map_len)?;
+        let char_categories: &'a [CharCategory] = section(bytes, "char_categories", header.char_categories_idx, header.char_categories_len)?;
+        let char_codes     : &'a [u32] = section(bytes, "char_codes"     , header.char_codes_idx     , header.char_codes_len)?;
+        let code_chars     : &'a [u32] = section(bytes, "code_chars"     , header.code_chars_idx     , header.code_chars_len)?;
 
-        // check_arr
-        let check_arr: &'a [u32] = unsafe {
-            slice::from_raw_parts(
-                bytes[header.check_idx..].as_ptr() as *const u32,
-                header.check_len
-            )
-        };
+        // セクション間の整合性
+        let key_unit = validate_key_unit(&header, char_codes, code_chars)?;
+        let double_array = DoubleArrayRef::from_sections(base_arr, check_arr, data_arr, data_offsets, key_unit)?;
+        let matrix_encoding = validate_matrix(&header, matrix, matrix_index)?;
+        if feature_offsets.last().map(|&last| last as usize) != Some(feature_bytes.len()) {
+            return Err(invalid_data(format!(
+                "素性文字列表のサイズが不正です。(offsets={}, bytes={})", feature_offsets.len(), feature_bytes.len()
+            )));
+        }
 
-        // data_arr
-        let data_arr: &'a [T] = unsafe {
-            slice::from_raw_parts(
-                bytes[header.data_idx..].as_ptr() as *const T,
-                header.data_len
-            )
-        };
+        let category_len = char_categories.len();
+        let invalid_category = char_categories.iter().any(|c| c.unk_idx as usize + c.unk_len as usize > unknown_arr.len()) ||
+            CharInfo(header.char_default_info as u32).default_category() >= category_len ||
+            char_map.iter().any(|&info| CharInfo(info).default_category() >= category_len);
+        if invalid_category {
+            return Err(invalid_data(format!(
+                "文字種定義が不正です。(categories={}, unknowns={})", category_len, unknown_arr.len()
+            )));
+        }
 
-        // matrix
-        let matrix: &'a [i16] = unsafe {
-            slice::from_raw_parts(
-                bytes[header.matrix_idx..].as_ptr() as *const i16,
-                header.matrix_len
-            )
-        };
+        // 解析時にget_matrixで範囲外を参照しないように、文脈IDを検証する
+        // ユーザー辞書は連接コスト表を持たない(0, 0)ので、組み合わせるシステム辞書の連接コスト表に対してcheck_context_idsで検証する
+        if header.matrix_left_max > 0 || header.matrix_right_max > 0 {
+            let (left_max, right_max) = (header.matrix_left_max as usize, header.matrix_right_max as usize);
+            validate_context_ids(data_arr, left_max, right_max)?;
+            validate_context_ids(unknown_arr, left_max, right_max)?;
+        }
+
+        // checksum
+        let mut checksum = Checksum::new();
+        checksum.update(header_bytes(&DictionaryHeader { checksum: 0, ..header }));
+        checksum.update(&bytes[header_size..]);
+        let checksum = checksum.finish();
+        if checksum != header.checksum {
+            return Err(invalid_data(format!(
+                "チェックサムが一致しません。辞書ファイルが壊れている可能性があります。(file={:#x}, actual={:#x})",
+                header.checksum, checksum
+            )));
+        }
+
+        Ok(DictionarySet {
+            header, double_array, data_arr, matrix, matrix_index, matrix_encoding,
+            feature_offsets, feature_bytes, unknown_arr, char_map, char_categories,
+        })
+    }
 
-        DictionarySet { header, base_arr, check_arr, data_arr, matrix }
+    /// 単語のダブル配列を返す
+    pub fn double_array(&self) -> DoubleArrayRef<'a, T> {
+        self.double_array
     }
 
     /// ダブル配列から指定されたkeyを探索する関数
@@ -89,25 +268,7 @@ impl<'a, T: Copy + Debug> DictionarySet<'a, T> {
     ///
     /// * `key`       - 探索対象の文字列
     pub fn get_trie(&self, key: &str) -> Option<&'a [T]> {
-        let mut idx  = 1;
-        let mut base = self.base_arr[idx] as usize;
-
-        for &byte in key.as_bytes() {
-            let next_idx = base + (byte as usize);
-            if  self.check_arr[next_idx] as usize != idx {
-                return None;
-            }
-            idx  = next_idx;
-            base = self.base_arr[idx] as usize;
-        }
-        let value_idx = base + (u8::max_value() as usize);
-        if self.check_arr[value_idx] as usize == idx {
-            let data_idx = (self.base_arr[value_idx] >> 8) as usize;
-            let data_len = (self.base_arr[value_idx] & 0b11111111) as usize;
-            Some(&self.data_arr[data_idx..(data_idx + data_len)])
-        } else {
-            None
-        }
+        self.double_array.get(key)
     }
 
     /// ダブル配列で共通接頭辞検索を行う
@@ -117,224 +278,660 @@ impl<'a, T: Copy + Debug> DictionarySet<'a, T> {
     ///
     /// * `key`       - 探索対象の文字列
     pub fn prefix_search(&self, key: &'a str) -> Vec<(&'a str, &'a[T])> {
-        let mut ret: Vec<(&str, &[T])> = Vec::new();
-        let mut idx = 1;
-        let mut base = self.base_arr[idx] as usize;
-
-        for (i, &byte) in key.as_bytes().iter().enumerate() {
-            // 次のノードに遷移
-            let next_idx = base + (byte as usize);
-            if self.check_arr[next_idx] as usize != idx {
-                break;
-            }
-            idx = next_idx;
-            base = self.base_arr[idx] as usize;
-            // value があれば戻り値の配列に追加
-            let value_idx = base + (u8::max_value() as usize);
-            if self.check_arr[value_idx] as usize == idx {
-                let data_idx = (self.base_arr[value_idx] >> 8) as usize;
-                let data_len = (self.base_arr[value_idx] & 0b11111111) as usize;
-                ret.push((&key[0..(i + 1)], &self.data_arr[data_idx..(data_idx + data_len)]));
-            }
-        }
-        ret
+        self.double_array.common_prefix_search(key)
     }
 
-    /// ダブル配列から指定されたkeyを探索する関数
-    /// 途中で遷移できなくなった場合、data_arrに値が存在しない場合はNoneを返す
-    /// 遷移ができて、data_arrに値が存在する場合はdata_arrのスライスを返す
+    /// ダブル配列で共通接頭辞検索を行うイテレータを返す
+    /// keyの先頭から一致する単語を短い順に、(一致したバイト数, 値のスライス)として返す
+    /// 検索中にメモリの確保は行わない
     ///
     /// # Arguments
     ///
-    /// * `key`       - 探索対象の文字列
-    pub fn get_matrix(&self, left_id: usize, right_id: usize) -> i16 {
+    /// * `key` - 探索対象のバイト列
+    pub fn common_prefix_iter<'b>(&self, key: &'b [u8]) -> PrefixSearchIter<'a, 'b, T> {
+        self.double_array.common_prefix_iter(key)
+    }
+
+    /// ダブル配列で予測検索(前方一致検索)を行う
+    /// prefixから始まる単語を辞書順(バイト列の順)に最大limit個、(単語, 値のスライス)として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    /// * `limit`  - 返す単語の最大数
+    pub fn predictive_search(&self, prefix: &str, limit: usize) -> Vec<(String, &'a [T])> {
+        self.double_array.predictive_search(prefix, limit)
+    }
+
+    /// 値のノードからルートまで遡って、単語(key)を復元する
+    /// 値のノードでない場合はNoneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `value_node` - 値のノードのindex(entriesで取得したもの)
+    pub fn key_of(&self, value_node: usize) -> Option<String> {
+        self.double_array.key_of(value_node)
+    }
+
+    /// 辞書に登録されているすべての単語を、(値のノードのindex, 値のスライス)として返すイテレータ
+    /// 順序はダブル配列のindexの順。単語はkey_ofで復元できる
+    pub fn entries(&self) -> EntryIter<'a, T> {
+        self.double_array.entries()
+    }
+
+    /// ダブル配列が文字単位(Trie::to_char_double_arrayで構築したもの)か
+    pub fn is_char_double_array(&self) -> bool {
+        self.double_array.is_char_double_array()
+    }
+
+    /// 連接コストを取得する
+    /// 引数の順序はmatrix.defの1列目・2列目と同じ
+    /// 連接コスト表の形式(MatrixEncoding)によらず同じ値を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prev_right_id` - 左側の単語の右文脈ID(matrix.defの1列目)
+    /// * `next_left_id`  - 右側の単語の左文脈ID(matrix.defの2列目)
+    pub fn get_matrix(&self, prev_right_id: usize, next_left_id: usize) -> i16 {
         let unknown_id = u16::max_value() as usize;
-        if left_id == unknown_id || right_id == unknown_id {
-            // アドホックな未知語の連接コストの対応。
-            // TODO: 未知語の設定ファイルを読み込めるようにする
+        if prev_right_id == unknown_id || next_left_id == unknown_id {
+            // char.def, unk.defを指定せずに構築した辞書の未知語(Token::unknown())の連接コスト
             return i16::max_value();
         }
-        self.matrix[(left_id * self.header.matrix_right_max) + right_id]
+        let width = self.header.matrix_width as usize;
+        match self.matrix_encoding {
+            MatrixEncoding::Dense  => self.matrix[(prev_right_id * width) + next_left_id],
+            MatrixEncoding::Dedup  => {
+                let row = self.matrix_index[prev_right_id] as usize;
+                let col = self.matrix_index[self.header.matrix_left_max as usize + next_left_id] as usize;
+                self.matrix[(row * width) + col]
+            },
+            MatrixEncoding::Sparse => {
+                let start = self.matrix_index[prev_right_id] as usize;
+                let end   = self.matrix_index[prev_right_id + 1] as usize;
+                let right_ids = &self.matrix_index[(self.header.matrix_left_max as usize + 1)..];
+                match right_ids[start..end].binary_search(&(next_left_id as u32)) {
+                    Ok(i)  => self.matrix[start + i],
+                    Err(_) => self.header.matrix_default as i16,
+                }
+            },
+        }
+    }
+
+    /// 連接コスト表のサイズ(left_max, right_max)
+    /// ユーザー辞書は連接コスト表を持たないので(0, 0)
+    pub fn get_matrix_size(&self) -> (usize, usize) {
+        (self.header.matrix_left_max as usize, self.header.matrix_right_max as usize)
+    }
+
+    /// 単語と未知語の文脈IDが、別の辞書の連接コスト表の範囲内かを検証する
+    /// ユーザー辞書をシステム辞書と組み合わせて使う前に、システム辞書のget_matrix_sizeを渡して検証する
+    /// 範囲外の場合は Error::InvalidMatrixId のエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `left_max`  - 連接コスト表の左文脈(matrix.defの1列目)の数
+    /// * `right_max` - 連接コスト表の右文脈(matrix.defの2列目)の数
+    pub fn check_context_ids(&self, left_max: usize, right_max: usize) -> Result<()> where T: ContextIds {
+        validate_context_ids(self.data_arr, left_max, right_max)?;
+        validate_context_ids(self.unknown_arr, left_max, right_max)
+    }
+
+    /// 素性文字列を取得する
+    /// 素性を持たないトークン(Token::NO_FEATURE)の場合は空文字列を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `feature_id` - 素性ID(Token.feature_id)
+    pub fn get_feature(&self, feature_id: usize) -> &'a str {
+        if feature_id + 1 >= self.feature_offsets.len() {
+            return "";
+        }
+        let start = self.feature_offsets[feature_id] as usize;
+        let end   = self.feature_offsets[feature_id + 1] as usize;
+        std::str::from_utf8(&self.feature_bytes[start..end]).unwrap_or("")
+    }
+
+    /// 素性文字列をカラムに分割して取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `feature_id` - 素性ID(Token.feature_id)
+    /// * `layout`     - 素性のカラムの位置(辞書の形式)
+    pub fn get_feature_columns(&self, feature_id: usize, layout: FeatureLayout) -> Feature {
+        Feature::parse(self.get_feature(feature_id), layout)
+    }
+
+    /// 文字の情報(文字種)を取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - 文字
+    pub fn get_char_info(&self, c: char) -> CharInfo {
+        match self.char_map.get(c as usize) {
+            Some(&info) => CharInfo(info),
+            None        => CharInfo(self.header.char_default_info as u32),
+        }
+    }
+
+    /// 空白(0x20)の文字の情報を取得する
+    /// char.defで0x20にDEFAULT以外の文字種(IPADICなどではSPACE)が割り当てられていない場合はNone
+    /// 解析時はこの文字種の文字を読み飛ばす(MeCabと同じ)
+    pub fn get_space_char_info(&self) -> Option<CharInfo> {
+        let space = self.get_char_info(' ');
+        if space.default_category() == CharInfo(self.header.char_default_info as u32).default_category() {
+            None
+        } else {
+            Some(space)
+        }
+    }
+
+    /// 文字種の定義を取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `category_id` - 文字種ID
+    pub fn get_char_category(&self, category_id: usize) -> &'a CharCategory {
+        &self.char_categories[category_id]
     }
 
-    /// ダブル配列、連接コスト表をバイト列としてファイルに書き込む
+    /// 文字種に対応する未知語のトークンを取得する
     ///
     /// # Arguments
     ///
+    /// * `category` - 文字種の定義
+    pub fn get_unknowns(&self, category: &CharCategory) -> &'a [T] {
+        let unk_idx = category.unk_idx as usize;
+        &self.unknown_arr[unk_idx..(unk_idx + category.unk_len as usize)]
+    }
+
+    /// ダブル配列、連接コスト表、素性文字列表、文字種定義をバイト列としてファイルに書き込む
+    ///
+    /// # Arguments
+    ///
+    /// * `parts`       - 辞書ファイルに書き込む内容
     /// * `output_path` - 出力するファイル
-    pub fn serialize(base_arr: &[u32], check_arr: &[u32], data_arr: &[T], matrix: MatrixBuilder, output_path: &str) -> io::Result<()> {
-        // base_arr
-        let base_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                base_arr.as_ptr() as *const u8,
-                mem::size_of::<u32>() * base_arr.len()
-            )
-        };
-        // check_arr
-        let check_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                check_arr.as_ptr() as *const u8,
-                mem::size_of::<u32>() * check_arr.len()
-            )
-        };
-        // data_arr
-        let data_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                data_arr.as_ptr() as *const u8,
-                mem::size_of::<T>() * data_arr.len()
-            )
-        };
-        // matrix
-        let matrix_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                matrix.get_matrix().as_ptr() as *const u8,
-                mem::size_of::<u16>() * matrix.get_matrix().len()
-            )
+    pub fn serialize(parts: &DictionaryParts<T>, output_path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(output_path)?);
+        DictionarySet::<T>::write(parts, &mut f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    /// ダブル配列、連接コスト表、素性文字列表、文字種定義をバイト列としてwriterに書き込む
+    /// 各セクションはSECTION_ALIGNの倍数の位置から始まるように0埋めする
+    ///
+    /// # Arguments
+    ///
+    /// * `parts`  - 辞書ファイルに書き込む内容
+    /// * `writer` - 書き込み先
+    pub fn write<W: Write>(parts: &DictionaryParts<T>, writer: &mut W) -> Result<()> {
+        let DictionaryParts { base_arr, check_arr, data_arr, data_offsets, char_codes, matrix, features, char_def } = *parts;
+        let encoded = matrix.encode();
+        let feature_bytes: &[u8] = features.get_bytes();
+        let unknown_arr = char_def.get_unknowns();
+        let char_categories = char_def.get_categories();
+        let empty: &[u32] = &[];
+        let (char_codes_arr, code_chars_arr) = match char_codes {
+            Some(char_codes) => (&char_codes.char_codes[..], &char_codes.code_chars[..]),
+            None             => (empty, empty),
         };
-        // dictionary_header
+
+        // 各セクションの開始位置
         let header_size: usize = mem::size_of::<DictionaryHeader>();
-        let header = DictionaryHeader {
-            base_idx        : header_size,
-            check_idx       : header_size + base_bytes.len(),
-            data_idx        : header_size + base_bytes.len() + check_bytes.len(),
-            matrix_idx      : header_size + base_bytes.len() + check_bytes.len() + data_bytes.len(),
-            base_len        : base_arr.len(),
-            check_len       : check_arr.len(),
-            data_len        : data_arr.len(),
-            matrix_len      : matrix.get_matrix().len(),
-            matrix_left_max : matrix.get_left_max(),
-            matrix_right_max: matrix.get_right_max(),
-        };
-        let header_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                &header as *const DictionaryHeader as *const u8,
-                header_size,
-            )
+        let sections: [&[u8]; 13] = [
+            as_bytes(base_arr), as_bytes(check_arr), as_bytes(data_arr), as_bytes(data_offsets), as_bytes(features.get_offsets()),
+            as_bytes(&encoded.costs), as_bytes(&encoded.index), feature_bytes, as_bytes(&unknown_arr),
+            as_bytes(char_def.get_char_map()), as_bytes(&char_categories), as_bytes(char_codes_arr), as_bytes(code_chars_arr),
+        ];
+        let padding = [0u8; SECTION_ALIGN];
+        let mut section_idx = [0u64; 13];
+        let mut pos = header_size;
+        for (i, bytes) in sections.iter().enumerate() {
+            section_idx[i] = pos as u64;
+            pos += bytes.len() + padding_len(bytes.len());
+        }
+
+        // dictionary_header
+        let mut header = DictionaryHeader {
+            magic           : MAGIC,
+            version         : FORMAT_VERSION,
+            endian          : ENDIAN_MARKER,
+            checksum        : 0,
+            data_size       : mem::size_of::<T>() as u64,
+            base_idx        : section_idx[0],
+            check_idx       : section_idx[1],
+            data_idx        : section_idx[2],
+            data_offsets_idx: section_idx[3],
+            feature_offsets_idx: section_idx[4],
+            matrix_idx      : section_idx[5],
+            matrix_index_idx: section_idx[6],
+            feature_bytes_idx  : section_idx[7],
+            unknown_idx        : section_idx[8],
+            char_map_idx       : section_idx[9],
+            char_categories_idx: section_idx[10],
+            char_codes_idx  : section_idx[11],
+            code_chars_idx  : section_idx[12],
+            base_len        : base_arr.len() as u64,
+            check_len       : check_arr.len() as u64,
+            data_len        : data_arr.len() as u64,
+            data_offsets_len: data_offsets.len() as u64,
+            matrix_len      : encoded.costs.len() as u64,
+            matrix_index_len: encoded.index.len() as u64,
+            feature_offsets_len: features.get_offsets().len() as u64,
+            feature_bytes_len  : feature_bytes.len() as u64,
+            unknown_len        : unknown_arr.len() as u64,
+            char_map_len       : char_def.get_char_map().len() as u64,
+            char_categories_len: char_categories.len() as u64,
+            char_codes_len  : char_codes_arr.len() as u64,
+            code_chars_len  : code_chars_arr.len() as u64,
+            matrix_left_max : matrix.get_left_max() as u64,
+            matrix_right_max: matrix.get_right_max() as u64,
+            matrix_encoding : encoded.encoding as u64,
+            matrix_width    : encoded.width as u64,
+            matrix_default  : i64::from(encoded.default) as u64,
+            char_default_info  : char_def.get_default_char_info().0 as u64,
+            key_unit        : if char_codes.is_some() { 1 } else { 0 },
         };
 
-        let mut f = File::create(output_path)?;
-        f.write_all(header_bytes)?;
-        f.write_all(base_bytes)?;
-        f.write_all(check_bytes)?;
-        f.write_all(data_bytes)?;
-        f.write_all(matrix_bytes)?;
-        f.flush()?;
+        // チェックサムはchecksumを0にしたヘッダから計算する
+        let mut checksum = Checksum::new();
+        checksum.update(header_bytes(&header));
+        for bytes in sections.iter() {
+            checksum.update(bytes);
+            checksum.update(&padding[..padding_len(bytes.len())]);
+        }
+        header.checksum = checksum.finish();
+
+        writer.write_all(header_bytes(&header))?;
+        for bytes in sections.iter() {
+            writer.write_all(bytes)?;
+            writer.write_all(&padding[..padding_len(bytes.len())])?;
+        }
         Ok(())
     }
+}
 
+impl<'a, T: Pod + Debug> DictionarySet<'a, T> {
     /// ダブル配列をデバッグ目的で表示するための関数
     pub fn debug_double_array(&self, len: usize) {
-        let base_arr = self.base_arr;
-        let check_arr = self.check_arr;
-        let data_arr = self.data_arr;
-        println!("size: base={}, check={}, data={}", base_arr.len(), check_arr.len(), data_arr.len());
-        println!("{:-10} | {:-10} | {:-10} |", "index", "base", "check");
-        println!("{:-10} | {:-10} | {:-10} |", 0, base_arr[0], check_arr[0]);
-        println!("{:-10} | {:-10} | {:-10} |", 1, base_arr[1], check_arr[1]);
-        for i in 2..len {
-            let check = check_arr[i];
-            if  check != 0 {
-                if i == base_arr[check as usize] as usize {
-                    let data_idx = (base_arr[i] >> 8) as usize;
-                    let data_len = (base_arr[i] & 0b11111111) as usize;
-                    println!(
-                        "{:-10} | {:-10} | {:-10} | {:?}",
-                        i,
-                        base_arr[i],
-                        check_arr[i],
-                        &data_arr[data_idx..(data_idx + data_len)],
-                        );
-                } else {
-                    println!(
-                        "{:-10} | {:-10} | {:-10} |",
-                        i,
-                        base_arr[i],
-                        check_arr[i],
-                        );
-                }
-            }
+        self.double_array.debug_double_array(len)
+    }
+}
+
+/// 辞書ファイルの形式が不正な場合のエラーを生成する
+fn invalid_data(msg: String) -> Error {
+    Error::DictionaryFormat(msg)
+}
+
+/// 値の文脈IDが連接コスト表の範囲内かを検証する
+/// matrix.defの1列目は左側の単語の右文脈ID、2列目は右側の単語の左文脈ID
+/// char.def, unk.defを指定せずに構築した辞書の未知語(Token::unknown())の文脈IDは、get_matrixで扱うので範囲外でもよい
+///
+/// # Arguments
+///
+/// * `values`    - 単語または未知語の配列
+/// * `left_max`  - 連接コスト表の左文脈の数
+/// * `right_max` - 連接コスト表の右文脈の数
+fn validate_context_ids<T: ContextIds>(values: &[T], left_max: usize, right_max: usize) -> Result<()> {
+    let unknown_id = u16::max_value() as usize;
+    for (left_id, right_id) in values.iter().filter_map(|value| value.context_ids()) {
+        if left_id == unknown_id && right_id == unknown_id {
+            continue;
+        }
+        if right_id >= left_max || left_id >= right_max {
+            return Err(Error::InvalidMatrixId { left_id, right_id, left_max, right_max });
         }
     }
+    Ok(())
 }
 
-/*
-use std::iter::Iterator;
-struct PrefixSearchIter<'a, T> {
-    idx      : usize,
-    base_arr : &'a [u32],
-    check_arr: &'a [u32],
-    data_arr : &'a [T],
+/// 連接コスト表のサイズと対応表が形式どおりかを検証して、形式を返す
+/// get_matrixで範囲外を参照しないように、対応表の値はすべて検証する
+///
+/// # Arguments
+///
+/// * `header`       - 辞書ファイルのヘッダ
+/// * `matrix`       - 連接コスト表
+/// * `matrix_index` - 連接コスト表の形式ごとの対応表
+fn validate_matrix(header: &DictionaryHeader, matrix: &[i16], matrix_index: &[u32]) -> Result<MatrixEncoding> {
+    let left_max  = header.matrix_left_max as usize;
+    let right_max = header.matrix_right_max as usize;
+    let width     = header.matrix_width as usize;
+    let encoding = match MatrixEncoding::from_id(header.matrix_encoding) {
+        Some(encoding) => encoding,
+        None => return Err(invalid_data(format!("連接コスト表の形式が不正です。(encoding={})", header.matrix_encoding))),
+    };
+    let valid = match encoding {
+        MatrixEncoding::Dense => {
+            width == right_max && matrix_index.is_empty() && left_max.checked_mul(right_max) == Some(matrix.len())
+        },
+        MatrixEncoding::Dedup => {
+            let rows = matrix.len().checked_div(width).unwrap_or(0);
+            (width == 0 || matrix.len() % width == 0) &&
+                left_max.checked_add(right_max) == Some(matrix_index.len()) &&
+                matrix_index[..left_max].iter().all(|&row| (row as usize) < rows) &&
+                matrix_index[left_max..].iter().all(|&col| (col as usize) < width)
+        },
+        MatrixEncoding::Sparse => {
+            let valid_len = left_max.checked_add(1).and_then(|n| n.checked_add(matrix.len())) == Some(matrix_index.len());
+            valid_len && {
+                let (offsets, right_ids) = matrix_index.split_at(left_max + 1);
+                // 行ごとの右文脈IDは昇順(二分探索するため)
+                offsets[0] == 0 && offsets[left_max] as usize == matrix.len() &&
+                    offsets.windows(2).all(|w| w[0] <= w[1]) &&
+                    offsets.windows(2).all(|w| {
+                        let row = &right_ids[(w[0] as usize)..(w[1] as usize)];
+                        row.windows(2).all(|ids| ids[0] < ids[1]) && row.iter().all(|&id| (id as usize) < right_max)
+                    })
+            }
+        },
+    };
+    if !valid {
+        return Err(invalid_data(format!(
+            "連接コスト表のサイズが不正です。(encoding={:?}, left_max={}, right_max={}, len={}, index_len={})",
+            encoding, left_max, right_max, matrix.len(), matrix_index.len()
+        )));
+    }
+    Ok(encoding)
 }
 
-impl<'a, T> Iterator for PrefixSearchIter<'a, T>  {
-    type Item =  &'a [T];
+/// ダブル配列の遷移の単位と、文字とコードの対応表を検証する
+/// 文字単位の場合、対応表はコードポイントとコード(1以上)の1対1の対応でなければならない
+///
+/// # Arguments
+///
+/// * `header`     - 辞書ファイルのヘッダ
+/// * `char_codes` - コードポイント -> コード
+/// * `code_chars` - コード -> コードポイント
+fn validate_key_unit<'a>(header: &DictionaryHeader, char_codes: &'a [u32], code_chars: &'a [u32]) -> Result<KeyUnit<'a>> {
+    let (key_unit, valid) = match header.key_unit {
+        0 => (KeyUnit::Byte, char_codes.is_empty() && code_chars.is_empty()),
+        1 => {
+            let codes = char_codes.iter().filter(|&&code| code != 0).count();
+            let valid = !code_chars.is_empty() && codes == code_chars.len() - 1 &&
+                code_chars.iter().enumerate().skip(1).all(|(code, &c)| {
+                    std::char::from_u32(c).is_some() && char_codes.get(c as usize) == Some(&(code as u32))
+                });
+            (KeyUnit::Char(char_codes, code_chars), valid)
+        },
+        _ => return Err(invalid_data(format!("ダブル配列の遷移の単位が不正です。(key_unit={})", header.key_unit))),
+    };
+    if !valid {
+        return Err(invalid_data(format!(
+            "文字とコードの対応表が不正です。(char_codes={}, code_chars={})", char_codes.len(), code_chars.len()
+        )));
+    }
+    Ok(key_unit)
+}
+
+/// ヘッダをバイト列として参照する
+fn header_bytes(header: &DictionaryHeader) -> &[u8] {
+    as_bytes(slice::from_ref(header))
+}
+
+/// 配列をバイト列として参照する
+fn as_bytes<U: Pod>(arr: &[U]) -> &[u8] {
+    unsafe { slice::from_raw_parts(arr.as_ptr() as *const u8, mem::size_of_val(arr)) }
+}
 
-    fn next(&mut self) -> Option<&'a [T]> {
-        Some(&self.data_arr[0..1])
+/// lenバイトのセクションの後ろに必要な0埋めのバイト数
+fn padding_len(len: usize) -> usize {
+    (SECTION_ALIGN - len % SECTION_ALIGN) % SECTION_ALIGN
+}
+
+/// 辞書ファイルのバイト列からセクションを切り出す
+/// セクションがバイト列の範囲外の場合や、アラインメントが揃っていない場合はエラーを返す
+///
+/// # Arguments
+///
+/// * `bytes` - 辞書ファイルのバイト列
+/// * `name`  - セクション名(エラーメッセージ用)
+/// * `idx`   - セクションの開始位置(バイト)
+/// * `len`   - セクションの要素数
+fn section<'a, U: Pod>(bytes: &'a [u8], name: &str, idx: u64, len: u64) -> Result<&'a [U]> {
+    let end = len.checked_mul(mem::size_of::<U>() as u64).and_then(|size| size.checked_add(idx));
+    match end {
+        Some(end) if end <= bytes.len() as u64 => {},
+        _ => return Err(invalid_data(format!(
+            "{}が辞書ファイルの範囲外です。辞書ファイルが途中で切れている可能性があります。(idx={}, len={}, file_size={})",
+            name, idx, len, bytes.len()
+        ))),
+    }
+    let ptr = bytes[(idx as usize)..].as_ptr();
+    if (ptr as usize) % mem::align_of::<U>() != 0 {
+        return Err(invalid_data(format!("{}のアラインメントが揃っていません。(idx={})", name, idx)));
     }
+    Ok(unsafe { slice::from_raw_parts(ptr as *const U, len as usize) })
 }
-*/
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::AlignedBytes;
+
+    /// テスト用の辞書のバイト列を生成する
+    fn build_dictionary_bytes() -> Vec<u8> {
+        build_dictionary_bytes_with_offsets(&[0, 2, 5])
+    }
+
+    fn build_dictionary_bytes_with_offsets(data_offsets: &[u32]) -> Vec<u8> {
+        let base_arr: Vec<u32> = (0..256).collect();
+        let check_arr: Vec<u32> = (0..256).map(|i| i * 10).collect();
+        let data_arr: Vec<u32> = vec![100,200,300,400,500];
+        let mut matrix = MatrixBuilder::new(1, 2);
+        matrix.set(0, 0, 1000).unwrap();
+        matrix.set(0, 1, 2000).unwrap();
+        let mut features = FeatureBuilder::new();
+        features.push("名");
+        features.push("詞");
+        let mut char_def: CharDefBuilder<u32> = CharDefBuilder::from_char_def("DEFAULT 0 1 0\nKANJI 0 0 2\n0x4E00..0x9FA5 KANJI").unwrap();
+        char_def.add_unknown("DEFAULT", 600).unwrap();
+        char_def.add_unknown("KANJI", 700).unwrap();
+        char_def.add_unknown("KANJI", 800).unwrap();
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets, char_codes: None,
+            matrix: &matrix, features: &features, char_def: &char_def,
+        };
+        let mut bytes: Vec<u8> = vec![];
+        DictionarySet::write(&parts, &mut bytes).unwrap();
+        bytes
+    }
 
     #[test]
     fn test_dictionary_set_new() {
-        // base_arr
-        let base_arr: Vec<u32> = vec![1,2,3,4,5];
-        let base_bytes: &[u8] = unsafe {
-            slice::from_raw_parts( base_arr.as_ptr() as *const u8, mem::size_of::<u32>() * base_arr.len())
-        };
+        let bytes = build_dictionary_bytes();
+        let aligned = AlignedBytes::new(&bytes);
+        let dict_set: DictionarySet<u32> = DictionarySet::new(&aligned).unwrap();
+        assert_eq!((0..256).collect::<Vec<u32>>()             , dict_set.double_array().base_arr());
+        assert_eq!((0..256).map(|i| i * 10).collect::<Vec<u32>>(), dict_set.double_array().check_arr());
+        assert_eq!(2, dict_set.double_array().len());
+        assert_eq!([1000,2000]          , dict_set.matrix);
+        assert_eq!(1, dict_set.header.matrix_left_max);
+        assert_eq!(2, dict_set.header.matrix_right_max);
+        assert_eq!("名"  , dict_set.get_feature(0));
+        assert_eq!("詞"  , dict_set.get_feature(1));
+        assert_eq!(""    , dict_set.get_feature(2));
+        // 文字種
+        let kanji = dict_set.get_char_info('漢');
+        let alpha = dict_set.get_char_info('a');
+        let emoji = dict_set.get_char_info('🍣');
+        assert_eq!(1, kanji.default_category());
+        assert_eq!(0, alpha.default_category());
+        assert_eq!(0, emoji.default_category());
+        assert_eq!([700, 800], dict_set.get_unknowns(dict_set.get_char_category(kanji.default_category())));
+        assert_eq!([600]     , dict_set.get_unknowns(dict_set.get_char_category(emoji.default_category())));
+        assert_eq!(2, dict_set.get_char_category(kanji.default_category()).length);
+    }
 
-        // check_arr
-        let check_arr: Vec<u32> = vec![10,20,30,40,50];
-        let check_bytes: &[u8] = unsafe {
-            slice::from_raw_parts( check_arr.as_ptr() as *const u8, mem::size_of::<u32>() * check_arr.len())
+    #[test]
+    fn test_dictionary_set_new_invalid() {
+        let bytes = build_dictionary_bytes();
+        let load = |bytes: &[u8]| -> String {
+            let aligned = AlignedBytes::new(bytes);
+            match DictionarySet::<u32>::new(&aligned) {
+                Ok(_)  => String::from("ok"),
+                Err(e) => {
+                    match e {
+                        Error::DictionaryFormat(_) => {},
+                        e => panic!("{:?}", e),
+                    }
+                    e.to_string()
+                },
+            }
         };
 
-        // data_arr
-        let data_arr: Vec<u32> = vec![100,200,300,400,500];
-        let data_bytes: &[u8] = unsafe {
-            slice::from_raw_parts( data_arr.as_ptr() as *const u8, mem::size_of::<u32>() * data_arr.len())
+        // ヘッダより小さい
+        assert!(load(&bytes[..10]).contains("ヘッダより小さい"));
+        // マジックナンバーが違う
+        let mut broken = bytes.clone();
+        broken[0] = b'X';
+        assert!(load(&broken).contains("マジックナンバー"));
+        // バージョンが違う
+        let mut broken = bytes.clone();
+        broken[8] = broken[8].wrapping_add(1);
+        assert!(load(&broken).contains("バージョン"));
+        // エンディアンが違う
+        let mut broken = bytes.clone();
+        broken[12..16].copy_from_slice(&ENDIAN_MARKER.swap_bytes().to_ne_bytes());
+        assert!(load(&broken).contains("エンディアン"));
+        // 途中で切れている
+        assert!(load(&bytes[..(bytes.len() - 8)]).contains("範囲外"));
+        // 中身が壊れている
+        let mut broken = bytes.clone();
+        let last = broken.len() - 9;
+        broken[last] ^= 1;
+        assert!(load(&broken).contains("チェックサム"));
+        // data_offsetsがdata_arrと一致しない
+        assert!(load(&build_dictionary_bytes_with_offsets(&[0, 2, 4])).contains("data_offsets"));
+        assert!(load(&build_dictionary_bytes_with_offsets(&[0, 3, 2, 5])).contains("data_offsets"));
+        // 要素の型が違う
+        let aligned = AlignedBytes::new(&bytes);
+        let err = DictionarySet::<u64>::new(&aligned).err().unwrap();
+        assert!(err.to_string().contains("要素のサイズ"));
+        // 連接コスト表の形式が不正
+        let mut broken = bytes.clone();
+        unsafe {
+            let mut header = ptr::read_unaligned(broken.as_ptr() as *const DictionaryHeader);
+            header.matrix_encoding = 9;
+            ptr::write_unaligned(broken.as_mut_ptr() as *mut DictionaryHeader, header);
+        }
+        assert!(load(&broken).contains("連接コスト表の形式"));
+        // ヘッダのセクションの長さが壊れている(範囲内でもチェックサムで検出する)
+        let mut broken = bytes.clone();
+        unsafe {
+            let mut header = ptr::read_unaligned(broken.as_ptr() as *const DictionaryHeader);
+            header.base_idx += 8;
+            ptr::write_unaligned(broken.as_mut_ptr() as *mut DictionaryHeader, header);
+        }
+        assert!(load(&broken).contains("チェックサム"));
+        // 値のノードのkeyの番号がdata_offsetsの範囲外
+        let mut base_arr: Vec<u32> = vec![0; 256];
+        let mut check_arr: Vec<u32> = vec![0; 256];
+        base_arr[1] = 0;
+        check_arr[255] = 1;
+        base_arr[255] = 5;
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &[1u32], data_offsets: &[0, 1], char_codes: None,
+            matrix: &MatrixBuilder::new(0, 0), features: &FeatureBuilder::new(), char_def: &CharDefBuilder::new(),
         };
+        let mut broken: Vec<u8> = vec![];
+        DictionarySet::write(&parts, &mut broken).unwrap();
+        assert!(load(&broken).contains("keyの番号が範囲外"));
+        // 正常
+        assert_eq!("ok", load(&bytes));
+    }
 
-        // matrix
-        let matrix: Vec<u16> = vec![1000,2000,3000,4000];
-        let matrix_bytes: &[u8] = unsafe {
-            slice::from_raw_parts( matrix.as_ptr() as *const u8, mem::size_of::<u16>() * matrix.len())
+    #[test]
+    fn test_dictionary_set_context_ids() {
+        use crate::token::Token;
+        let load = |data: Token, unknown: Token, matrix: &MatrixBuilder| -> Result<()> {
+            let mut char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+            char_def.add_unknown("DEFAULT", unknown).unwrap();
+            let parts = DictionaryParts {
+                base_arr: &[0; 256], check_arr: &[0; 256], data_arr: &[data], data_offsets: &[0, 1], char_codes: None,
+                matrix, features: &FeatureBuilder::new(), char_def: &char_def,
+            };
+            let mut bytes: Vec<u8> = vec![];
+            DictionarySet::write(&parts, &mut bytes).unwrap();
+            let aligned = AlignedBytes::new(&bytes);
+            DictionarySet::<Token>::new(&aligned).map(|_| ())
         };
+        // 右文脈IDは左文脈(1列目)の数、左文脈IDは右文脈(2列目)の数より小さい
+        let matrix = MatrixBuilder::new(2, 3);
+        assert!(load(Token::new(2, 1, 0, 0), Token::new(0, 0, 0, 0), &matrix).is_ok());
+        match load(Token::new(3, 1, 0, 0), Token::new(0, 0, 0, 0), &matrix) {
+            Err(Error::InvalidMatrixId { left_id: 3, right_id: 1, left_max: 2, right_max: 3 }) => {},
+            r => panic!("{:?}", r),
+        }
+        // 未知語も検証する
+        assert!(load(Token::new(0, 0, 0, 0), Token::new(0, 2, 0, 0), &matrix).is_err());
+        // char.def, unk.defを指定しない場合の未知語は範囲外でもよい
+        assert!(load(Token::new(0, 0, 0, 0), Token::unknown(), &matrix).is_ok());
+        // 連接コスト表を持たないユーザー辞書は、読み込み時には検証しない
+        assert!(load(Token::new(3, 3, 0, 0), Token::new(0, 0, 0, 0), &MatrixBuilder::new(0, 0)).is_ok());
+    }
 
-        // dictionary_header
-        let header_size: usize = mem::size_of::<DictionaryHeader>();
-        let header = DictionaryHeader {
-            base_idx        : header_size,
-            check_idx       : header_size + base_bytes.len(),
-            data_idx        : header_size + base_bytes.len() + check_bytes.len(),
-            matrix_idx      : header_size + base_bytes.len() + check_bytes.len() + data_bytes.len(),
-            base_len        : base_arr.len(),
-            check_len       : check_arr.len(),
-            data_len        : data_arr.len(),
-            matrix_len      : matrix.len(),
-            matrix_left_max : 1,
-            matrix_right_max: 2,
-        };
-        let header_bytes: &[u8] = unsafe {
-            slice::from_raw_parts( &header as *const DictionaryHeader as *const u8, header_size)
-        };
+    #[test]
+    fn test_matrix_encoding() {
+        // 同じ内容の行・列が多く、ほとんどのコストが同じ連接コスト表
+        let (left_max, right_max) = (30, 20);
+        let mut matrix = MatrixBuilder::new(left_max, right_max);
+        for l in 0..left_max {
+            for r in 0..right_max {
+                let cost = if l % 3 == 0 && r % 4 == 0 { -((l * 10 + r) as i16) - 1 } else { 50 };
+                matrix.set(l, r, cost).unwrap();
+            }
+        }
+        let char_def: CharDefBuilder<u32> = CharDefBuilder::new();
+        let base_arr: Vec<u32> = (0..256).collect();
+        let check_arr: Vec<u32> = vec![0; 256];
+        let mut sizes: Vec<usize> = Vec::new();
+        for &encoding in [MatrixEncoding::Dense, MatrixEncoding::Dedup, MatrixEncoding::Sparse].iter() {
+            matrix.set_encoding(encoding);
+            let parts = DictionaryParts {
+                base_arr: &base_arr, check_arr: &check_arr, data_arr: &[], data_offsets: &[0], char_codes: None,
+                matrix: &matrix, features: &FeatureBuilder::new(), char_def: &char_def,
+            };
+            let mut bytes: Vec<u8> = vec![];
+            DictionarySet::write(&parts, &mut bytes).unwrap();
+            let aligned = AlignedBytes::new(&bytes);
+            let dict_set: DictionarySet<u32> = DictionarySet::new(&aligned).unwrap();
+            // どの形式でも同じコストを引ける
+            assert_eq!((left_max, right_max), dict_set.get_matrix_size());
+            for l in 0..left_max {
+                for r in 0..right_max {
+                    assert_eq!(matrix.get(l, r), dict_set.get_matrix(l, r), "{:?} ({}, {})", encoding, l, r);
+                }
+            }
+            sizes.push(bytes.len());
+        }
+        // Dedup, SparseはDenseより小さい
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] < sizes[0]);
 
-        let mut bytes: Vec<u8> = vec![];
-        bytes.extend_from_slice(header_bytes);
-        bytes.extend_from_slice(base_bytes);
-        bytes.extend_from_slice(check_bytes);
-        bytes.extend_from_slice(data_bytes);
-        bytes.extend_from_slice(matrix_bytes);
-        let dict_set: DictionarySet<u32> = DictionarySet::new(&bytes);
-        assert_eq!([1,2,3,4,5]          , dict_set.base_arr);
-        assert_eq!([10,20,30,40,50]     , dict_set.check_arr);
-        assert_eq!([100,200,300,400,500], dict_set.data_arr);
-        assert_eq!([1000,2000,3000,4000], dict_set.matrix);
-        assert_eq!(1, dict_set.header.matrix_left_max);
-        assert_eq!(2, dict_set.header.matrix_right_max);
+        let encoded = {
+            matrix.set_encoding(MatrixEncoding::Dedup);
+            matrix.encode()
+        };
+        // l % 3 != 0 の行、r % 4 != 0 の列はそれぞれひとつにまとまるので、11行 * 6列
+        assert_eq!((11 * 6, 6), (encoded.costs.len(), encoded.width));
+        matrix.set_encoding(MatrixEncoding::Sparse);
+        let encoded = matrix.encode();
+        assert_eq!(50, encoded.default);
+        assert_eq!(10 * 5, encoded.costs.len());
+        // 空の連接コスト表
+        for &encoding in [MatrixEncoding::Dense, MatrixEncoding::Dedup, MatrixEncoding::Sparse].iter() {
+            let mut matrix = MatrixBuilder::new(0, 0);
+            matrix.set_encoding(encoding);
+            let parts = DictionaryParts {
+                base_arr: &base_arr, check_arr: &check_arr, data_arr: &[], data_offsets: &[0], char_codes: None,
+                matrix: &matrix, features: &FeatureBuilder::new(), char_def: &char_def,
+            };
+            let mut bytes: Vec<u8> = vec![];
+            DictionarySet::write(&parts, &mut bytes).unwrap();
+            let aligned = AlignedBytes::new(&bytes);
+            assert!(DictionarySet::<u32>::new(&aligned).is_ok());
+        }
     }
 }
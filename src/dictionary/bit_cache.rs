@@ -11,9 +11,19 @@ impl BitCache {
     const BIT_CNT: usize = 6;
 
     pub fn new() -> BitCache {
+        BitCache::with_len(65535 * Self::BIT_LEN)
+    }
+
+    /// len個のindexの分の領域を確保する。それより後ろのindexはsetしたときに拡張する
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - 最初に確保するindexの数
+    pub fn with_len(len: usize) -> BitCache {
+        let start = 4; // utf8想定なので256 / 64
         BitCache {
-            cache: vec![0; 65535],
-            start: 4, // utf8想定なので256 / 64
+            cache: vec![0; ((len + Self::BIT_LEN - 1) >> Self::BIT_CNT).max(start + 1)],
+            start,
         }
     }
 
@@ -118,11 +128,11 @@ mod tests {
         bit_cache.set(100);
         bit_cache.set(100000000);
         // セットしたindexが登録されている
-        assert_eq!(false, bit_cache.get(0) == 0);
-        assert_eq!(false, bit_cache.get(100) == 0);
-        assert_eq!(false, bit_cache.get(100000000) == 0);
+        assert_ne!(0, bit_cache.get(0));
+        assert_ne!(0, bit_cache.get(100));
+        assert_ne!(0, bit_cache.get(100000000));
         // セットしていないindexは登録されていない
-        assert_eq!(true, bit_cache.get(1000000) == 0);
+        assert_eq!(0, bit_cache.get(1000000));
     }
 
     #[test]
@@ -0,0 +1,293 @@
+use crate::dictionary::Pod;
+use crate::error::{Error, Result};
+
+/// 文字種の定義(char.defのカテゴリ定義行)
+/// 未知語のトークンはunknown_arr[unk_idx..(unk_idx + unk_len)]に格納されている
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct CharCategory {
+    pub invoke : u8,  // 1: 辞書に単語があっても未知語処理を行う
+    pub group  : u8,  // 1: 同じ文字種の文字をまとめて未知語にする
+    pub length : u16, // 1文字からlength文字までの未知語を生成する
+    pub unk_idx: u32,
+    pub unk_len: u32,
+}
+
+// u8, u16, u32だけからなり、パディングはない
+unsafe impl Pod for CharCategory {}
+
+/// 文字の情報
+/// 下位24bit: 文字が属する文字種のbitマスク, 上位8bit: デフォルトの文字種ID(char.defで最初に指定された文字種)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharInfo(pub u32);
+
+impl CharInfo {
+    /// 定義できる文字種の最大数
+    pub const MAX_CATEGORIES: usize = 24;
+    const MASK: u32 = 0x00ff_ffff;
+
+    pub fn new(default_category: usize, category_mask: u32) -> CharInfo {
+        CharInfo(((default_category as u32) << 24) | (category_mask & Self::MASK))
+    }
+
+    pub fn default_category(self) -> usize {
+        (self.0 >> 24) as usize
+    }
+
+    pub fn category_mask(self) -> u32 {
+        self.0 & Self::MASK
+    }
+
+    /// 同じ文字種の文字として扱えるか
+    /// 共通の文字種に属していればtrue(MeCabのCharInfo::isKindOfと同じ)
+    pub fn is_kind_of(self, other: CharInfo) -> bool {
+        self.category_mask() & other.category_mask() != 0
+    }
+}
+
+/// 文字種定義(char.def)と未知語定義(unk.def)を構築する構造体
+pub struct CharDefBuilder<T: Copy> {
+    names     : Vec<String>,
+    categories: Vec<CharCategory>,
+    char_map  : Vec<u32>,
+    unknowns  : Vec<Vec<T>>,
+}
+
+impl<T: Copy> CharDefBuilder<T> {
+    /// char.defを指定しない場合の文字種定義
+    /// DEFAULTのみで、すべての文字を1文字ずつ未知語にする
+    pub fn new() -> CharDefBuilder<T> {
+        CharDefBuilder {
+            names     : vec![String::from("DEFAULT")],
+            categories: vec![CharCategory { invoke: 1, group: 0, length: 1, unk_idx: 0, unk_len: 0 }],
+            char_map  : Vec::new(),
+            unknowns  : vec![Vec::new()],
+        }
+    }
+
+    /// char.defを読み込む
+    /// 文字種定義行: "<NAME> <INVOKE> <GROUP> <LENGTH>"
+    /// 文字コード定義行: "<0xXXXX[..0xYYYY]> <NAME> [<NAME>...]"
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - char.defの中身
+    pub fn from_char_def(text: &str) -> Result<CharDefBuilder<T>> {
+        let mut builder = CharDefBuilder { names: Vec::new(), categories: Vec::new(), char_map: Vec::new(), unknowns: Vec::new() };
+        let mut mappings: Vec<(usize, u32, u32, Vec<&str>)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None      => line,
+            };
+            let record: Vec<&str> = line.split_whitespace().collect();
+            if record.is_empty() {
+                continue;
+            }
+            if record[0].starts_with("0x") {
+                // 文字コード定義行はすべての文字種が定義されてから処理する
+                if record.len() < 2 {
+                    return Err(invalid_char_def(line_no, "文字種が指定されていません。"));
+                }
+                let (start, end) = parse_range(record[0]).ok_or_else(|| invalid_char_def(line_no, "文字コードが不正です。"))?;
+                mappings.push((line_no, start, end, record[1..].to_vec()));
+            } else {
+                if record.len() < 4 {
+                    return Err(invalid_char_def(line_no, "文字種の定義には INVOKE GROUP LENGTH が必要です。"));
+                }
+                let invoke = record[1].parse::<u8>().ok().filter(|&v| v <= 1);
+                let group  = record[2].parse::<u8>().ok().filter(|&v| v <= 1);
+                let length = record[3].parse::<u16>().ok();
+                match (invoke, group, length) {
+                    (Some(invoke), Some(group), Some(length)) => {
+                        if builder.names.iter().any(|name| name == record[0]) {
+                            return Err(invalid_char_def(line_no, &format!("文字種 {} が重複しています。", record[0])));
+                        }
+                        if builder.names.len() >= CharInfo::MAX_CATEGORIES {
+                            return Err(invalid_char_def(line_no, &format!("文字種は{}個までしか定義できません。", CharInfo::MAX_CATEGORIES)));
+                        }
+                        builder.names.push(record[0].to_string());
+                        builder.categories.push(CharCategory { invoke, group, length, unk_idx: 0, unk_len: 0 });
+                        builder.unknowns.push(Vec::new());
+                    },
+                    _ => return Err(invalid_char_def(line_no, "INVOKE GROUP LENGTH の値が不正です。")),
+                }
+            }
+        }
+
+        // 定義されていない文字はDEFAULTとして扱う
+        let default_id = builder.category_id("DEFAULT").ok_or_else(|| invalid_char_def(0, "DEFAULTの文字種が定義されていません。"))?;
+        let default_info = CharInfo::new(default_id, 1 << default_id);
+        let max_code = mappings.iter().map(|m| m.2).max();
+        if let Some(max_code) = max_code {
+            builder.char_map = vec![default_info.0; max_code as usize + 1];
+        }
+        for (line_no, start, end, names) in mappings {
+            let mut mask = 0;
+            for name in names.iter() {
+                let id = builder.category_id(name).ok_or_else(|| invalid_char_def(line_no, &format!("文字種 {} が定義されていません。", name)))?;
+                mask |= 1 << id;
+            }
+            let default_category = builder.category_id(names[0]).unwrap();
+            for code in start..=end {
+                builder.char_map[code as usize] = CharInfo::new(default_category, mask).0;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// 文字種名から文字種IDを引く
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - 文字種名
+    pub fn category_id(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// 未知語のトークンを追加する(unk.defの1行に相当)
+    /// 文字種が定義されていない場合は Error::InvalidInput を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `name`  - 文字種名
+    /// * `token` - 未知語のトークン
+    pub fn add_unknown(&mut self, name: &str, token: T) -> Result<()> {
+        match self.category_id(name) {
+            Some(id) => {
+                self.unknowns[id].push(token);
+                Ok(())
+            },
+            None => Err(Error::InvalidInput(format!("文字種 {} がchar.defに定義されていません。", name))),
+        }
+    }
+
+    /// 文字種定義を取得する。unk_idx, unk_lenはget_unknownsの配列の位置を指す
+    pub fn get_categories(&self) -> Vec<CharCategory> {
+        let mut unk_idx = 0;
+        let mut categories = self.categories.clone();
+        for (category, unknowns) in categories.iter_mut().zip(self.unknowns.iter()) {
+            category.unk_idx = unk_idx as u32;
+            category.unk_len = unknowns.len() as u32;
+            unk_idx += unknowns.len();
+        }
+        categories
+    }
+
+    /// 文字コードからCharInfoを引く表
+    pub fn get_char_map(&self) -> &[u32] {
+        &self.char_map
+    }
+
+    /// char_mapの範囲外の文字のCharInfo(DEFAULT)
+    pub fn get_default_char_info(&self) -> CharInfo {
+        let default_id = self.category_id("DEFAULT").unwrap_or(0);
+        CharInfo::new(default_id, 1 << default_id)
+    }
+
+    /// 未知語のトークンを文字種の順に並べた配列
+    pub fn get_unknowns(&self) -> Vec<T> {
+        self.unknowns.iter().flat_map(|tokens| tokens.iter().cloned()).collect()
+    }
+}
+
+impl<T: Copy> Default for CharDefBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn invalid_char_def(line_no: usize, msg: &str) -> Error {
+    Error::parse("char.def", line_no as u64, msg)
+}
+
+/// "0xXXXX" または "0xXXXX..0xYYYY" を文字コードの範囲に変換する
+fn parse_range(range: &str) -> Option<(u32, u32)> {
+    let parse = |code: &str| -> Option<u32> {
+        if !code.starts_with("0x") {
+            return None;
+        }
+        u32::from_str_radix(&code[2..], 16).ok().filter(|&c| c <= 0x10ffff)
+    };
+    let mut split = range.split("..");
+    let start = parse(split.next()?)?;
+    let end = match split.next() {
+        Some(end) => parse(end)?,
+        None      => start,
+    };
+    if split.next().is_some() || start > end {
+        return None;
+    }
+    Some((start, end))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAR_DEF: &str = "
+# 文字種定義
+DEFAULT      0 1 0  # DEFAULT is a mandatory category!
+SPACE        0 1 0
+KANJI        0 0 2
+NUMERIC      1 1 0
+KANJINUMERIC 1 1 0
+
+0x0020 SPACE
+0x0030..0x0039 NUMERIC
+0x4E00..0x9FA5 KANJI
+0x4E00 KANJINUMERIC KANJI # 一
+";
+
+    #[test]
+    fn test_from_char_def() {
+        let mut builder: CharDefBuilder<u32> = CharDefBuilder::from_char_def(CHAR_DEF).unwrap();
+        assert_eq!(Some(0), builder.category_id("DEFAULT"));
+        assert_eq!(Some(2), builder.category_id("KANJI"));
+        assert_eq!(None   , builder.category_id("HIRAGANA"));
+
+        let char_map = builder.get_char_map();
+        assert_eq!(0x9FA5 + 1, char_map.len());
+        // 定義されていない文字はDEFAULT
+        assert_eq!(CharInfo::new(0, 0b00001), CharInfo(char_map['a' as usize]));
+        assert_eq!(CharInfo::new(1, 0b00010), CharInfo(char_map[' ' as usize]));
+        assert_eq!(CharInfo::new(3, 0b01000), CharInfo(char_map['5' as usize]));
+        assert_eq!(CharInfo::new(2, 0b00100), CharInfo(char_map['漢' as usize]));
+        // 複数の文字種に属する文字は最初の文字種がデフォルト
+        let ichi = CharInfo(char_map['一' as usize]);
+        assert_eq!(CharInfo::new(4, 0b10100), ichi);
+        assert!(ichi.is_kind_of(CharInfo(char_map['漢' as usize])));
+        assert!(CharInfo(char_map['漢' as usize]).is_kind_of(ichi));
+        assert!(!ichi.is_kind_of(CharInfo(char_map['5' as usize])));
+
+        // 未知語のトークンは文字種の順に並ぶ
+        builder.add_unknown("KANJI", 10).unwrap();
+        builder.add_unknown("DEFAULT", 20).unwrap();
+        builder.add_unknown("KANJI", 30).unwrap();
+        assert!(builder.add_unknown("HIRAGANA", 40).is_err());
+        assert_eq!(vec![20, 10, 30], builder.get_unknowns());
+        let categories = builder.get_categories();
+        assert_eq!(CharCategory { invoke: 0, group: 1, length: 0, unk_idx: 0, unk_len: 1 }, categories[0]);
+        assert_eq!(CharCategory { invoke: 0, group: 0, length: 2, unk_idx: 1, unk_len: 2 }, categories[2]);
+        assert_eq!(CharCategory { invoke: 1, group: 1, length: 0, unk_idx: 3, unk_len: 0 }, categories[4]);
+    }
+
+    #[test]
+    fn test_from_char_def_invalid() {
+        // DEFAULTがない
+        assert!(CharDefBuilder::<u32>::from_char_def("KANJI 0 0 2\n").is_err());
+        // 定義されていない文字種
+        assert!(CharDefBuilder::<u32>::from_char_def("DEFAULT 0 1 0\n0x0020 SPACE\n").is_err());
+        // 文字コードが不正
+        assert!(CharDefBuilder::<u32>::from_char_def("DEFAULT 0 1 0\n0xZZZZ DEFAULT\n").is_err());
+        // INVOKEが不正
+        assert!(CharDefBuilder::<u32>::from_char_def("DEFAULT 2 1 0\n").is_err());
+        // エラーには行番号が付く
+        match CharDefBuilder::<u32>::from_char_def("DEFAULT 0 1 0\n\n0xZZZZ DEFAULT\n") {
+            Err(Error::Parse { line, .. }) => assert_eq!(3, line),
+            _ => panic!(),
+        }
+    }
+}
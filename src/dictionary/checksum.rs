@@ -0,0 +1,97 @@
+/// 辞書ファイルの破損を検知するためのチェックサム
+/// FNV-1aを8byte単位で適用する(末尾の端数は0埋めして計算する)
+/// 数百MBの辞書でも読み込み時に検証できるように、1byteずつではなく8byteずつ処理する
+pub struct Checksum {
+    hash    : u64,
+    tail    : [u8; 8],
+    tail_len: usize,
+    len     : u64,
+}
+
+impl Checksum {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64        = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Checksum {
+        Checksum { hash: Self::OFFSET_BASIS, tail: [0; 8], tail_len: 0, len: 0 }
+    }
+
+    /// バイト列をチェックサムに加える
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 追加するバイト列
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        // 前回の端数を埋める
+        if self.tail_len > 0 {
+            let n = std::cmp::min(8 - self.tail_len, bytes.len());
+            self.tail[self.tail_len..(self.tail_len + n)].copy_from_slice(&bytes[..n]);
+            self.tail_len += n;
+            bytes = &bytes[n..];
+            if self.tail_len < 8 {
+                return;
+            }
+            let word = u64::from_le_bytes(self.tail);
+            self.write_word(word);
+            self.tail_len = 0;
+        }
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0; 8];
+            word.copy_from_slice(chunk);
+            self.write_word(u64::from_le_bytes(word));
+        }
+        let rest = chunks.remainder();
+        self.tail[..rest.len()].copy_from_slice(rest);
+        self.tail_len = rest.len();
+    }
+
+    /// チェックサムを計算する
+    pub fn finish(mut self) -> u64 {
+        if self.tail_len > 0 {
+            for b in &mut self.tail[self.tail_len..] {
+                *b = 0;
+            }
+            let word = u64::from_le_bytes(self.tail);
+            self.write_word(word);
+        }
+        // 末尾の0埋めと区別するために長さも混ぜる
+        let len = self.len;
+        self.write_word(len);
+        self.hash
+    }
+
+    fn write_word(&mut self, word: u64) {
+        self.hash ^= word;
+        self.hash = self.hash.wrapping_mul(Self::PRIME);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(chunks: &[&[u8]]) -> u64 {
+        let mut checksum = Checksum::new();
+        for chunk in chunks {
+            checksum.update(chunk);
+        }
+        checksum.finish()
+    }
+
+    #[test]
+    fn test_checksum() {
+        let bytes: Vec<u8> = (0..100u8).collect();
+        // 分割の仕方に依存しない
+        let expected = checksum(&[&bytes]);
+        assert_eq!(expected, checksum(&[&bytes[..3], &bytes[3..10], &bytes[10..]]));
+        assert_eq!(expected, checksum(&[&bytes[..8], &[], &bytes[8..99], &bytes[99..]]));
+        // 1byteでも違えば値が変わる
+        let mut broken = bytes.clone();
+        broken[50] ^= 1;
+        assert_ne!(expected, checksum(&[&broken]));
+        // 末尾に0を足しても値が変わる
+        assert_ne!(checksum(&[&bytes[..99]]), checksum(&[&bytes[..99], &[0]]));
+    }
+}
@@ -0,0 +1,1071 @@
+use crate::dictionary::trie::{DoubleArrayParts, Trie, BYTE_VALUE_KEY, CHAR_VALUE_KEY};
+use crate::dictionary::{invalid_data, ContextIds, DictionaryParts, DictionarySet, Pod};
+use crate::dictionary::matrix_builder::MatrixBuilder;
+use crate::dictionary::feature_builder::FeatureBuilder;
+use crate::dictionary::char_def::CharDefBuilder;
+use crate::error::{Error, Result};
+use crate::util::AlignedBytes;
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::iter::FromIterator;
+use std::mem;
+
+/// 子ノードが取りうるkeyのパターン(値のノードのkeyを含む)
+const MAX_KEY: usize = u8::max_value() as usize + 1;
+/// ルートノードのindex
+const ROOT: usize = 1;
+
+/// 文字列 -> 値の配列の対応を保持する、keyの追加と削除ができるバイト単位のダブル配列
+/// 配列の形式はTrie::to_double_arrayと同じで、値のノードのbaseに値の番号を格納する
+/// 値は番号ごとに別々に持っておき、to_partsでdata_arrとdata_offsetsにまとめ直す
+/// writeで辞書ファイルと同じ形式のバイト列に書き出し、DoubleArrayRef::newで(mmapしたものなどから)読み込める
+///
+/// keyを追加するときに遷移先が他のノードと重なった場合は、遷移元のノードの子ノードをすべて空いている位置に移動する
+/// checkが0のindex(0, 1を除く)を空きとして扱う
+pub struct DoubleArray<T: Copy> {
+    base_arr    : Vec<u32>,
+    check_arr   : Vec<u32>,
+    values      : Vec<Vec<T>>, // 値の番号 -> 値。削除したkeyの値は空になる
+    free_entries: Vec<u32>,    // 削除したkeyの値の番号。次に追加するkeyで使う
+    search_start: usize,       // これより前のindexはすべて使われている
+    len         : usize,       // 登録されているkeyの数
+}
+
+impl<T: Copy> DoubleArray<T> {
+    pub fn new() -> DoubleArray<T> {
+        DoubleArray {
+            base_arr    : vec![0; MAX_KEY],
+            check_arr   : vec![0; MAX_KEY],
+            values      : Vec::new(),
+            free_entries: Vec::new(),
+            search_start: ROOT + 1,
+            len         : 0,
+        }
+    }
+
+    /// Trie::to_double_arrayなどで構築したダブル配列から生成する
+    ///
+    /// # Arguments
+    ///
+    /// * `parts` - (base_arr, check_arr, data_arr, data_offsets)
+    pub fn from_parts(parts: DoubleArrayParts<T>) -> DoubleArray<T> {
+        let (mut base_arr, mut check_arr, data_arr, data_offsets) = parts;
+        let len = base_arr.len().max(MAX_KEY);
+        base_arr.resize(len, 0);
+        check_arr.resize(len, 0);
+        let values: Vec<Vec<T>> = data_offsets.windows(2).map(|w| data_arr[(w[0] as usize)..(w[1] as usize)].to_vec()).collect();
+        DoubleArray {
+            base_arr,
+            check_arr,
+            len         : values.len(),
+            values,
+            free_entries: Vec::new(),
+            search_start: ROOT + 1,
+        }
+    }
+
+    /// (key, 値)の組からダブル配列を構築する。同じkeyの値は出現順に並ぶ
+    /// 一度トライ木を作ってから配置するので、insertを繰り返すより配列が小さくなる
+    /// 登録できる値の数の上限を超えた場合など、構築に失敗した場合は Error::DoubleArray のエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `iter` - (key, 値)の組
+    pub fn try_from_iter<K: AsRef<str>, I: IntoIterator<Item=(K, T)>>(iter: I) -> Result<DoubleArray<T>> {
+        let mut trie: Trie<T> = Trie::new();
+        for (key, value) in iter {
+            trie.set(key.as_ref(), value);
+        }
+        Ok(DoubleArray::from_parts(trie.to_double_array()?))
+    }
+
+    /// 登録されているkeyの数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// keyに対応する値のスライスを返す。登録されていなければNone
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    pub fn get(&self, key: &str) -> Option<&[T]> {
+        self.view().get(key)
+    }
+
+    /// 共通接頭辞検索を行う
+    /// keyの先頭から一致する単語を短い順に、(一致した部分文字列, 値のスライス)として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    pub fn common_prefix_search<'b>(&self, key: &'b str) -> Vec<(&'b str, &[T])> {
+        self.view().common_prefix_search(key)
+    }
+
+    /// 予測検索(前方一致検索)を行う
+    /// prefixから始まる単語を辞書順(バイト列の順)に最大limit個、(単語, 値のスライス)として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    /// * `limit`  - 返す単語の最大数
+    pub fn predictive_search(&self, prefix: &str, limit: usize) -> Vec<(String, &[T])> {
+        self.view().predictive_search(prefix, limit)
+    }
+
+    /// 探索用の参照を返す
+    pub fn view(&self) -> DoubleArrayRef<'_, T> {
+        DoubleArrayRef {
+            base_arr : &self.base_arr,
+            check_arr: &self.check_arr,
+            values   : Values::Entries(&self.values),
+            key_unit : KeyUnit::Byte,
+            len      : self.len,
+        }
+    }
+
+    /// keyに値を追加する。keyが登録されていなければkeyも追加する
+    /// 一つのkeyに複数の値を登録できる
+    ///
+    /// # Arguments
+    ///
+    /// * `key`   - 追加するキー
+    /// * `value` - キーに対応する値
+    pub fn insert(&mut self, key: &str, value: T) {
+        let mut idx = ROOT;
+        for &byte in key.as_bytes() {
+            idx = match self.transition(idx, byte as usize) {
+                Some(next_idx) => next_idx,
+                None           => self.add_child(idx, byte as usize),
+            };
+        }
+        if let Some(value_idx) = self.transition(idx, BYTE_VALUE_KEY as usize) {
+            self.values[self.base_arr[value_idx] as usize].push(value);
+            return;
+        }
+        let value_idx = self.add_child(idx, BYTE_VALUE_KEY as usize);
+        let entry = match self.free_entries.pop() {
+            Some(entry) => {
+                self.values[entry as usize].push(value);
+                entry
+            },
+            None => {
+                self.values.push(vec![value]);
+                (self.values.len() - 1) as u32
+            },
+        };
+        self.base_arr[value_idx] = entry;
+        self.len += 1;
+    }
+
+    /// keyを削除して、keyに登録されていた値を返す。登録されていなければNone
+    /// keyの削除で子ノードがなくなったノードも削除する
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 削除するキー
+    pub fn delete(&mut self, key: &str) -> Option<Vec<T>> {
+        let mut idx = ROOT;
+        for &byte in key.as_bytes() {
+            idx = self.transition(idx, byte as usize)?;
+        }
+        let value_idx = self.transition(idx, BYTE_VALUE_KEY as usize)?;
+        let entry = self.base_arr[value_idx];
+        let values = mem::replace(&mut self.values[entry as usize], Vec::new());
+        self.free_entries.push(entry);
+        self.free(value_idx);
+        self.len -= 1;
+
+        // 子ノードのないノードをルートに向かって削除する
+        while idx != ROOT && self.children(idx).is_empty() {
+            let parent = self.check_arr[idx] as usize;
+            self.free(idx);
+            idx = parent;
+        }
+        Some(values)
+    }
+
+    /// (base_arr, check_arr, data_arr, data_offsets)を返す
+    /// 値の番号は値のノードのindexの順に振り直し、削除したkeyの値は詰める
+    /// 返した配列はDictionarySet::writeで辞書ファイルに書き込める
+    pub fn to_parts(&self) -> DoubleArrayParts<T> {
+        let mut base_arr = self.base_arr.clone();
+        let mut data_arr: Vec<T> = Vec::new();
+        let mut data_offsets: Vec<u32> = vec![0];
+        let mut last_used = ROOT;
+        for (idx, &check) in self.check_arr.iter().enumerate() {
+            if check == 0 {
+                continue;
+            }
+            last_used = idx;
+            if self.is_value_node(idx) {
+                base_arr[idx] = (data_offsets.len() - 1) as u32;
+                data_arr.extend_from_slice(&self.values[self.base_arr[idx] as usize]);
+                data_offsets.push(data_arr.len() as u32);
+            }
+        }
+        // 配列の長さは最後に使われているindex + MAX_KEYまであればよい
+        let len = last_used + MAX_KEY;
+        base_arr.truncate(len);
+        let mut check_arr = self.check_arr.clone();
+        check_arr.truncate(len);
+        (base_arr, check_arr, data_arr, data_offsets)
+    }
+
+    /// idxが値のノードか
+    fn is_value_node(&self, idx: usize) -> bool {
+        let parent = self.check_arr[idx] as usize;
+        parent != 0 && self.base_arr[parent] as usize + BYTE_VALUE_KEY as usize == idx
+    }
+
+    /// ノードidxからkeyで遷移した先のノードを返す。遷移できない場合はNone
+    fn transition(&self, idx: usize, key: usize) -> Option<usize> {
+        transition(&self.base_arr, &self.check_arr, idx, key)
+    }
+
+    /// ノードidxの子ノードのkeyを昇順に返す
+    fn children(&self, idx: usize) -> Vec<usize> {
+        let base = self.base_arr[idx] as usize;
+        (0..MAX_KEY).filter(|&key| self.check_arr.get(base + key).map(|&check| check as usize) == Some(idx)).collect()
+    }
+
+    /// idxが空いているか
+    fn is_free(&self, idx: usize) -> bool {
+        idx > ROOT && self.check_arr.get(idx).cloned().unwrap_or(0) == 0
+    }
+
+    /// ノードidxにkeyの子ノードを追加して、追加したノードのindexを返す
+    /// 遷移先が空いていなければ、ノードidxの子ノードをすべて移動する
+    fn add_child(&mut self, idx: usize, key: usize) -> usize {
+        let base = self.base_arr[idx] as usize;
+        if !self.is_free(base + key) {
+            let mut keys = self.children(idx);
+            keys.push(key);
+            keys.sort();
+            let new_base = self.find_base(&keys);
+            self.relocate(idx, new_base);
+        }
+        let next_idx = self.base_arr[idx] as usize + key;
+        self.ensure_len(self.base_arr[idx] as usize + MAX_KEY);
+        self.base_arr[next_idx] = 0;
+        self.check_arr[next_idx] = idx as u32;
+        next_idx
+    }
+
+    /// ノードidxの子ノードをnew_baseを基準にした位置に移動する
+    fn relocate(&mut self, idx: usize, new_base: usize) {
+        self.ensure_len(new_base + MAX_KEY);
+        let old_base = self.base_arr[idx] as usize;
+        for key in self.children(idx) {
+            let (old_idx, new_idx) = (old_base + key, new_base + key);
+            self.base_arr[new_idx] = self.base_arr[old_idx];
+            self.check_arr[new_idx] = idx as u32;
+            // 孫ノードのcheckを移動先に付け替える(値のノードのbaseは値の番号なので孫ノードはない)
+            if key != BYTE_VALUE_KEY as usize {
+                for grandchild in self.children(old_idx) {
+                    let grandchild_idx = self.base_arr[old_idx] as usize + grandchild;
+                    self.check_arr[grandchild_idx] = new_idx as u32;
+                }
+            }
+            self.free(old_idx);
+        }
+        self.base_arr[idx] = new_base as u32;
+    }
+
+    /// keysの子ノードをすべて空いている位置に配置できるbase値を探索する
+    /// keysは昇順に並んでいなければならない
+    fn find_base(&mut self, keys: &[usize]) -> usize {
+        while !self.is_free(self.search_start) {
+            self.search_start += 1;
+        }
+        let mut empty_idx = self.search_start;
+        loop {
+            if empty_idx >= keys[0] {
+                let base = empty_idx - keys[0];
+                if keys.iter().all(|&key| self.is_free(base + key)) {
+                    return base;
+                }
+            }
+            empty_idx += 1;
+            while !self.is_free(empty_idx) {
+                empty_idx += 1;
+            }
+        }
+    }
+
+    /// idxを空きにする
+    fn free(&mut self, idx: usize) {
+        self.base_arr[idx] = 0;
+        self.check_arr[idx] = 0;
+        if idx < self.search_start {
+            self.search_start = idx;
+        }
+    }
+
+    /// 配列の長さをlen以上にする
+    fn ensure_len(&mut self, len: usize) {
+        if self.base_arr.len() < len {
+            let new_len = len.max(self.base_arr.len() * 2);
+            self.base_arr.resize(new_len, 0);
+            self.check_arr.resize(new_len, 0);
+        }
+    }
+}
+
+impl<T: Pod> DoubleArray<T> {
+    /// ダブル配列を辞書ファイルと同じ形式のバイト列としてwriterに書き込む
+    /// 連接コスト表、素性、文字種定義は空にする
+    /// 書き込んだバイト列はDoubleArrayRef::new、DoubleArray::from_bytes、DictionarySet::newで読み込める
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - 書き込み先
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let (base_arr, check_arr, data_arr, data_offsets) = self.to_parts();
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+            matrix: &MatrixBuilder::new(0, 0), features: &FeatureBuilder::new(), char_def: &CharDefBuilder::new(),
+        };
+        DictionarySet::write(&parts, writer)
+    }
+
+    /// ダブル配列をバイト列に変換する
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        // Vecへの書き込みは失敗しない
+        self.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// ダブル配列をファイルに書き込む
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - 出力するファイル
+    pub fn serialize(&self, output_path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(output_path)?);
+        self.write(&mut f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    /// writeで書き込んだバイト列から、更新できるダブル配列を生成する
+    /// DoubleArrayRef::newと異なり、bytesのアラインメントが揃っている必要はない
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - writeで書き込んだバイト列
+    pub fn from_bytes(bytes: &[u8]) -> Result<DoubleArray<T>> where T: ContextIds {
+        let aligned = AlignedBytes::new(bytes);
+        DoubleArrayRef::new(&aligned)?.to_double_array()
+    }
+}
+
+impl<T: Copy> Default for DoubleArray<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, K: AsRef<str>> FromIterator<(K, T)> for DoubleArray<T> {
+    /// (key, 値)の組からダブル配列を構築する(try_from_iterと同じ)
+    ///
+    /// # Panics
+    ///
+    /// 構築に失敗した場合(登録できる値の数の上限を超えた場合など)はpanicする。エラーとして扱う場合はtry_from_iterを使う
+    fn from_iter<I: IntoIterator<Item=(K, T)>>(iter: I) -> Self {
+        DoubleArray::try_from_iter(iter).expect("ダブル配列の構築に失敗しました")
+    }
+}
+
+/// ダブル配列の遷移の単位
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum KeyUnit<'a> {
+    /// 1バイトごとに遷移する(Trie::to_double_array)
+    Byte,
+    /// 1文字ごとに文字のコードで遷移する(Trie::to_char_double_array)
+    /// コードポイント -> コード(CharCodes.char_codes)と、コード -> コードポイント(CharCodes.code_chars)の対応表を持つ
+    Char(&'a [u32], &'a [u32]),
+}
+
+impl<'a> KeyUnit<'a> {
+    /// 値のノードに遷移するkey
+    fn value_key(self) -> usize {
+        match self {
+            KeyUnit::Byte       => BYTE_VALUE_KEY as usize,
+            KeyUnit::Char(_, _) => CHAR_VALUE_KEY as usize,
+        }
+    }
+
+    /// keyのposの位置から、次の遷移に使うラベルとそのバイト数を返す
+    /// ラベルはバイト単位ならバイト、文字単位なら文字のコード。辞書に現れない文字の場合はNone
+    fn next(self, key: &[u8], pos: usize) -> Option<(usize, usize)> {
+        match self {
+            KeyUnit::Byte => Some((key[pos] as usize, 1)),
+            KeyUnit::Char(char_codes, _) => {
+                let (c, len) = decode_char(key, pos)?;
+                match char_codes.get(c) {
+                    Some(&code) if code != 0 => Some((code as usize, len)),
+                    _                        => None,
+                }
+            },
+        }
+    }
+}
+
+/// 値の番号から値のスライスを引くための表
+enum Values<'a, T> {
+    /// DoubleArrayが持つ、値の番号ごとの配列
+    Entries(&'a [Vec<T>]),
+    /// 辞書ファイルから読み込んだ(data_arr, data_offsets)
+    Offsets(&'a [T], &'a [u32]),
+}
+
+// deriveするとT: Copyが必要になるので、参照だけのコピーを実装する
+impl<'a, T> Clone for Values<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Values<'a, T> {}
+
+impl<'a, T> Values<'a, T> {
+    fn get(self, entry: usize) -> &'a [T] {
+        match self {
+            Values::Entries(values)                => &values[entry],
+            Values::Offsets(data_arr, data_offsets) => {
+                &data_arr[(data_offsets[entry] as usize)..(data_offsets[entry + 1] as usize)]
+            },
+        }
+    }
+}
+
+/// ダブル配列を探索するための参照
+/// DoubleArray::view、DictionarySet::double_arrayで生成するか、
+/// DoubleArray::writeで書き込んだバイト列(mmapしたものなど)からコピーせずに生成する
+pub struct DoubleArrayRef<'a, T> {
+    base_arr : &'a [u32],
+    check_arr: &'a [u32],
+    values   : Values<'a, T>,
+    key_unit : KeyUnit<'a>,
+    len      : usize, // 登録されているkeyの数
+}
+
+impl<'a, T> Clone for DoubleArrayRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for DoubleArrayRef<'a, T> {}
+
+impl<'a, T: Pod> DoubleArrayRef<'a, T> {
+    /// DoubleArray::write、DictionarySet::writeで書き込んだバイト列を読み込む
+    /// DictionarySet::newと同じ検証を行い、不正な場合は Error::DictionaryFormat のエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - ダブル配列のバイト列(mmapしたものなど)。先頭は8バイト境界に揃っていなければならない
+    pub fn new(bytes: &'a [u8]) -> Result<DoubleArrayRef<'a, T>> where T: ContextIds {
+        Ok(DictionarySet::<T>::new(bytes)?.double_array())
+    }
+}
+
+impl<'a, T: Copy> DoubleArrayRef<'a, T> {
+    /// 辞書ファイルから切り出した配列から生成する
+    /// 探索中に範囲外を参照しないように、配列のサイズと値の番号を検証する
+    ///
+    /// # Arguments
+    ///
+    /// * `base_arr`     - base
+    /// * `check_arr`    - check
+    /// * `data_arr`     - 値の配列
+    /// * `data_offsets` - keyごとのdata_arrの開始位置(末尾はdata_arrの長さ)
+    /// * `key_unit`     - 遷移の単位
+    pub(crate) fn from_sections(base_arr: &'a [u32], check_arr: &'a [u32], data_arr: &'a [T], data_offsets: &'a [u32], key_unit: KeyUnit<'a>) -> Result<DoubleArrayRef<'a, T>> {
+        if base_arr.len() != check_arr.len() || base_arr.len() < MAX_KEY {
+            return Err(invalid_data(format!(
+                "ダブル配列のサイズが不正です。(base={}, check={})", base_arr.len(), check_arr.len()
+            )));
+        }
+        let invalid_offsets = data_offsets.first() != Some(&0) ||
+            data_offsets.last().map(|&last| last as usize) != Some(data_arr.len()) ||
+            data_offsets.windows(2).any(|w| w[0] > w[1]);
+        if invalid_offsets {
+            return Err(invalid_data(format!(
+                "data_offsetsが不正です。(offsets={}, data={})", data_offsets.len(), data_arr.len()
+            )));
+        }
+        // 値の番号がdata_offsetsの範囲内か(Values::getで範囲外を参照しないように)
+        let entries = data_offsets.len() - 1;
+        let value_key = key_unit.value_key();
+        for (idx, &check) in check_arr.iter().enumerate() {
+            let parent = check as usize;
+            if parent == 0 || parent >= base_arr.len() || base_arr[parent] as usize + value_key != idx {
+                continue;
+            }
+            if base_arr[idx] as usize >= entries {
+                return Err(invalid_data(format!(
+                    "値のノードのkeyの番号が範囲外です。(idx={}, entry={}, entries={})", idx, base_arr[idx], entries
+                )));
+            }
+        }
+        Ok(DoubleArrayRef { base_arr, check_arr, values: Values::Offsets(data_arr, data_offsets), key_unit, len: entries })
+    }
+
+    /// 登録されているkeyの数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// keyに対応する値のスライスを返す。登録されていなければNone
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    pub fn get(&self, key: &str) -> Option<&'a [T]> {
+        let idx = self.walk(ROOT, key.as_bytes())?;
+        self.values_of(idx)
+    }
+
+    /// 共通接頭辞検索を行う
+    /// keyの先頭から一致する単語を短い順に、(一致した部分文字列, 値のスライス)として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    pub fn common_prefix_search<'b>(&self, key: &'b str) -> Vec<(&'b str, &'a [T])> {
+        self.common_prefix_iter(key.as_bytes()).map(|(len, values)| (&key[..len], values)).collect()
+    }
+
+    /// 共通接頭辞検索を行うイテレータを返す
+    /// keyの先頭から一致する単語を短い順に、(一致したバイト数, 値のスライス)として返す
+    /// 検索中にメモリの確保は行わない
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象のバイト列
+    pub fn common_prefix_iter<'b>(&self, key: &'b [u8]) -> PrefixSearchIter<'a, 'b, T> {
+        PrefixSearchIter { key, pos: 0, idx: ROOT, double_array: *self }
+    }
+
+    /// 予測検索(前方一致検索)を行う
+    /// prefixから始まる単語を辞書順(バイト列の順)に最大limit個、(単語, 値のスライス)として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    /// * `limit`  - 返す単語の最大数
+    pub fn predictive_search(&self, prefix: &str, limit: usize) -> Vec<(String, &'a [T])> {
+        let mut ret: Vec<(String, &'a [T])> = Vec::new();
+        if let Some(idx) = self.walk(ROOT, prefix.as_bytes()) {
+            let mut key = prefix.as_bytes().to_vec();
+            self.collect_descendants(idx, &mut key, limit, &mut ret);
+        }
+        ret
+    }
+
+    /// 値のノードからルートまで遡って、単語(key)を復元する
+    /// 値のノードでない場合はNoneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `value_node` - 値のノードのindex(entriesで取得したもの)
+    pub fn key_of(&self, value_node: usize) -> Option<String> {
+        if !self.is_value_node(value_node) {
+            return None;
+        }
+        // 子ノードのindexは「親のbase + ラベル」なので、親のbaseを引けばラベルになる
+        let mut labels: Vec<usize> = Vec::new();
+        let mut idx = self.check_arr[value_node] as usize;
+        while idx != ROOT {
+            let parent = self.check_arr[idx] as usize;
+            if parent == 0 || parent >= self.base_arr.len() || labels.len() >= self.base_arr.len() {
+                return None;
+            }
+            // 壊れた配列ではbaseがindexより大きいこともあるので、その場合もNone
+            labels.push(idx.checked_sub(self.base_arr[parent] as usize)?);
+            idx = parent;
+        }
+        labels.reverse();
+        match self.key_unit {
+            KeyUnit::Byte => {
+                let bytes: Option<Vec<u8>> = labels.into_iter().map(|label| if label < BYTE_VALUE_KEY as usize { Some(label as u8) } else { None }).collect();
+                String::from_utf8(bytes?).ok()
+            },
+            KeyUnit::Char(_, code_chars) => labels.into_iter().map(|code| {
+                code_chars.get(code).and_then(|&c| std::char::from_u32(c))
+            }).collect(),
+        }
+    }
+
+    /// 登録されているすべての単語を、(値のノードのindex, 値のスライス)として返すイテレータ
+    /// 順序はダブル配列のindexの順。単語はkey_ofで復元できる
+    pub fn entries(&self) -> EntryIter<'a, T> {
+        EntryIter { double_array: *self, idx: 0 }
+    }
+
+    /// ダブル配列が文字単位(Trie::to_char_double_arrayで構築したもの)か
+    pub fn is_char_double_array(&self) -> bool {
+        match self.key_unit {
+            KeyUnit::Byte       => false,
+            KeyUnit::Char(_, _) => true,
+        }
+    }
+
+    /// base
+    pub fn base_arr(&self) -> &'a [u32] {
+        self.base_arr
+    }
+
+    /// check
+    pub fn check_arr(&self) -> &'a [u32] {
+        self.check_arr
+    }
+
+    /// 文字単位のダブル配列の、コードポイント -> コード(CharCodes.char_codes)。バイト単位の場合は空
+    pub fn char_codes(&self) -> &'a [u32] {
+        match self.key_unit {
+            KeyUnit::Byte                => &[],
+            KeyUnit::Char(char_codes, _) => char_codes,
+        }
+    }
+
+    /// 文字単位のダブル配列の、コード -> コードポイント(CharCodes.code_chars)。バイト単位の場合は空
+    pub fn code_chars(&self) -> &'a [u32] {
+        match self.key_unit {
+            KeyUnit::Byte                => &[],
+            KeyUnit::Char(_, code_chars) => code_chars,
+        }
+    }
+
+    /// 更新できるダブル配列にコピーする
+    /// 文字単位のダブル配列は更新できないので、エラーを返す
+    pub fn to_double_array(&self) -> Result<DoubleArray<T>> {
+        if self.is_char_double_array() {
+            return Err(Error::InvalidInput("文字単位のダブル配列は更新できません。".to_string()));
+        }
+        let (data_arr, data_offsets) = match self.values {
+            Values::Offsets(data_arr, data_offsets) => (data_arr.to_vec(), data_offsets.to_vec()),
+            Values::Entries(values) => {
+                let mut data_arr: Vec<T> = Vec::new();
+                let mut data_offsets: Vec<u32> = vec![0];
+                for values in values.iter() {
+                    data_arr.extend_from_slice(values);
+                    data_offsets.push(data_arr.len() as u32);
+                }
+                (data_arr, data_offsets)
+            },
+        };
+        let mut da = DoubleArray::from_parts((self.base_arr.to_vec(), self.check_arr.to_vec(), data_arr, data_offsets));
+        da.len = self.len;
+        Ok(da)
+    }
+
+    /// idxが値のノードか
+    fn is_value_node(&self, idx: usize) -> bool {
+        if idx >= self.check_arr.len() {
+            return false;
+        }
+        let parent = self.check_arr[idx] as usize;
+        parent != 0 && parent < self.base_arr.len() && self.base_arr[parent] as usize + self.key_unit.value_key() == idx
+    }
+
+    /// ノードidxからkeyで遷移した先のノードを返す。遷移できない場合はNone
+    fn walk(&self, mut idx: usize, key: &[u8]) -> Option<usize> {
+        let mut pos = 0;
+        while pos < key.len() {
+            let (label, len) = self.key_unit.next(key, pos)?;
+            idx = transition(self.base_arr, self.check_arr, idx, label)?;
+            pos += len;
+        }
+        Some(idx)
+    }
+
+    /// ノードidxに値があれば、値のスライスを返す
+    /// 値のノードはノードからvalue_keyで遷移した先にあり、baseに値の番号が格納されている
+    fn values_of(&self, idx: usize) -> Option<&'a [T]> {
+        let value_idx = transition(self.base_arr, self.check_arr, idx, self.key_unit.value_key())?;
+        Some(self.values.get(self.base_arr[value_idx] as usize))
+    }
+
+    /// ノードidx以下の単語を辞書順にretに追加する
+    /// 自身の値を追加してから、子ノードをkeyの小さい順に辿る
+    ///
+    /// # Arguments
+    ///
+    /// * `idx`   - 探索を開始するノード
+    /// * `key`   - ルートからノードidxまでのkey
+    /// * `limit` - retの最大長
+    /// * `ret`   - 見つかった単語を追加する配列
+    fn collect_descendants(&self, idx: usize, key: &mut Vec<u8>, limit: usize, ret: &mut Vec<(String, &'a [T])>) {
+        if ret.len() >= limit {
+            return;
+        }
+        if let Some(values) = self.values_of(idx) {
+            ret.push((String::from_utf8_lossy(key).into_owned(), values));
+        }
+        match self.key_unit {
+            KeyUnit::Byte => {
+                // 値のノード(BYTE_VALUE_KEY)は除く
+                for byte in 0..(BYTE_VALUE_KEY as u8) {
+                    if ret.len() >= limit {
+                        return;
+                    }
+                    if let Some(child) = transition(self.base_arr, self.check_arr, idx, byte as usize) {
+                        key.push(byte);
+                        self.collect_descendants(child, key, limit, ret);
+                        key.pop();
+                    }
+                }
+            },
+            KeyUnit::Char(_, code_chars) => {
+                // 子ノードはbase_arr[idx] + コードの位置にあるので、その範囲のcheck_arrだけを走査する
+                // (値のノード(CHAR_VALUE_KEY = 0)は除く)
+                // コードは出現回数の順なので、子ノードを文字の順に並べ替えてから辿る
+                let base  = self.base_arr[idx] as usize;
+                let end   = base.saturating_add(code_chars.len()).min(self.check_arr.len());
+                let start = (base + 1).min(end);
+                let mut children: Vec<(u32, usize)> = self.check_arr[start..end].iter().enumerate()
+                    .filter(|&(_, &check)| check as usize == idx)
+                    .map(|(i, _)| (code_chars[start + i - base], start + i))
+                    .collect();
+                children.sort();
+                for (c, child) in children {
+                    if ret.len() >= limit {
+                        return;
+                    }
+                    let len = key.len();
+                    let mut buf = [0u8; 4];
+                    key.extend_from_slice(std::char::from_u32(c).unwrap_or('\u{FFFD}').encode_utf8(&mut buf).as_bytes());
+                    self.collect_descendants(child, key, limit, ret);
+                    key.truncate(len);
+                }
+            },
+        }
+    }
+}
+
+impl<'a, T: Copy + Debug> DoubleArrayRef<'a, T> {
+    /// ダブル配列をデバッグ目的で表示するための関数
+    pub fn debug_double_array(&self, len: usize) {
+        let base_arr = self.base_arr;
+        let check_arr = self.check_arr;
+        println!("size: base={}, check={}, keys={}", base_arr.len(), check_arr.len(), self.len);
+        println!("{:-10} | {:-10} | {:-10} |", "index", "base", "check");
+        println!("{:-10} | {:-10} | {:-10} |", 0, base_arr[0], check_arr[0]);
+        println!("{:-10} | {:-10} | {:-10} |", 1, base_arr[1], check_arr[1]);
+        for i in 2..len.min(check_arr.len()) {
+            let check = check_arr[i];
+            if  check != 0 {
+                if self.is_value_node(i) {
+                    println!(
+                        "{:-10} | {:-10} | {:-10} | {:?}",
+                        i,
+                        base_arr[i],
+                        check_arr[i],
+                        self.values.get(base_arr[i] as usize),
+                        );
+                } else {
+                    println!(
+                        "{:-10} | {:-10} | {:-10} |",
+                        i,
+                        base_arr[i],
+                        check_arr[i],
+                        );
+                }
+            }
+        }
+    }
+}
+
+/// ダブル配列で共通接頭辞検索を行うイテレータ
+/// DoubleArrayRef::common_prefix_iter、DictionarySet::common_prefix_iterで生成する
+pub struct PrefixSearchIter<'a, 'b, T> {
+    key         : &'b [u8],
+    pos         : usize, // 次に遷移に使うkeyの位置
+    idx         : usize, // 現在のノード
+    double_array: DoubleArrayRef<'a, T>,
+}
+
+impl<'a, 'b, T: Copy> Iterator for PrefixSearchIter<'a, 'b, T> {
+    type Item = (usize, &'a [T]);
+
+    fn next(&mut self) -> Option<(usize, &'a [T])> {
+        let da = self.double_array;
+        while self.pos < self.key.len() {
+            // 次のノードに遷移
+            let next = da.key_unit.next(self.key, self.pos).and_then(|(label, len)| {
+                transition(da.base_arr, da.check_arr, self.idx, label).map(|next_idx| (next_idx, len))
+            });
+            match next {
+                Some((next_idx, len)) => {
+                    self.idx = next_idx;
+                    self.pos += len;
+                },
+                None => {
+                    self.pos = self.key.len();
+                    return None;
+                },
+            }
+            // value があれば返す
+            if let Some(values) = da.values_of(self.idx) {
+                return Some((self.pos, values));
+            }
+        }
+        None
+    }
+}
+
+/// 登録されているすべての単語を返すイテレータ
+/// DoubleArrayRef::entries、DictionarySet::entriesで生成する
+pub struct EntryIter<'a, T> {
+    double_array: DoubleArrayRef<'a, T>,
+    idx         : usize, // 次に調べるダブル配列のindex
+}
+
+impl<'a, T: Copy> Iterator for EntryIter<'a, T> {
+    type Item = (usize, &'a [T]);
+
+    fn next(&mut self) -> Option<(usize, &'a [T])> {
+        let da = self.double_array;
+        while self.idx < da.check_arr.len() {
+            let idx = self.idx;
+            self.idx += 1;
+            if da.is_value_node(idx) {
+                return Some((idx, da.values.get(da.base_arr[idx] as usize)));
+            }
+        }
+        None
+    }
+}
+
+/// ダブル配列のノードidxからlabel(バイトまたは文字のコード)で遷移した先のノードを返す。遷移できない場合はNone
+fn transition(base_arr: &[u32], check_arr: &[u32], idx: usize, label: usize) -> Option<usize> {
+    // 文字単位の場合、ラベルは256以上になることがあるので配列の範囲を確認する
+    let next_idx = base_arr[idx] as usize + label;
+    match check_arr.get(next_idx) {
+        Some(&check) if check as usize == idx => Some(next_idx),
+        _                                    => None,
+    }
+}
+
+/// バイト列のposの位置のutf8の文字のコードポイントとバイト数を返す
+/// posが文字の先頭でない場合や、文字が途中で切れている場合はNone
+fn decode_char(bytes: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let first = *bytes.get(pos)? as usize;
+    let len = if first < 0x80 {
+        return Some((first, 1));
+    } else if first >= 0xF0 {
+        4
+    } else if first >= 0xE0 {
+        3
+    } else if first >= 0xC0 {
+        2
+    } else {
+        return None;
+    };
+    let mut c = first & (0x7F >> len);
+    for &byte in bytes.get((pos + 1)..(pos + len))? {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        c = (c << 6) | (byte as usize & 0x3F);
+    }
+    Some((c, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_insert_remove() {
+        let mut da: DoubleArray<u32> = DoubleArray::new();
+        assert!(da.is_empty());
+        da.insert("abc", 0);
+        da.insert("abc", 1);
+        da.insert("ab", 2);
+        da.insert("abd", 3);
+        da.insert("お寿司", 4);
+        da.insert("おすし", 5);
+        assert_eq!(5, da.len());
+        assert_eq!(Some(&[0, 1][..]), da.get("abc"));
+        assert_eq!(Some(&[2][..])   , da.get("ab"));
+        assert_eq!(Some(&[3][..])   , da.get("abd"));
+        assert_eq!(Some(&[4][..])   , da.get("お寿司"));
+        assert_eq!(Some(&[5][..])   , da.get("おすし"));
+        assert_eq!(None, da.get("a"));
+        assert_eq!(None, da.get("abcd"));
+
+        // 削除したkeyだけが見つからなくなる
+        assert_eq!(Some(vec![0, 1]), da.delete("abc"));
+        assert_eq!(None, da.delete("abc"));
+        assert_eq!(None, da.delete("a"));
+        assert_eq!(None, da.get("abc"));
+        assert_eq!(Some(&[2][..]), da.get("ab"));
+        assert_eq!(Some(&[3][..]), da.get("abd"));
+        assert_eq!(4, da.len());
+
+        // 削除したkeyの値の番号は使い回す
+        da.insert("abc", 6);
+        assert_eq!(Some(&[6][..]), da.get("abc"));
+        assert_eq!(5, da.values.len());
+    }
+
+    #[test]
+    fn test_insert_remove_many() {
+        // HashMapと同じ結果になることを、追加と削除を繰り返して確認する
+        let mut da: DoubleArray<u32> = DoubleArray::new();
+        let mut expected: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        let chars = ['a', 'b', 'c', 'あ', 'い', '寿', '司', '🍣'];
+        let mut rand: u64 = 1;
+        for i in 0..5000 {
+            // 線形合同法
+            rand = rand.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let len = (rand >> 60) as usize % 5 + 1;
+            let key: String = (0..len).map(|j| chars[(rand >> (j * 3 + 20)) as usize % chars.len()]).collect();
+            if (rand >> 40) % 3 == 1 {
+                assert_eq!(expected.remove(&key), da.delete(&key), "{}", key);
+            } else {
+                da.insert(&key, i);
+                expected.entry(key).or_default().push(i);
+            }
+        }
+        assert_eq!(expected.len(), da.len());
+        for (key, values) in expected.iter() {
+            assert_eq!(Some(&values[..]), da.get(key), "{}", key);
+        }
+
+        // 削除で空いた位置はすべて解放されている
+        let nodes = da.check_arr.iter().filter(|&&check| check != 0).count();
+        let mut trie: Trie<u32> = Trie::new();
+        for (key, values) in expected.iter() {
+            for &value in values.iter() {
+                trie.set(key, value);
+            }
+        }
+        let (_, trie_check, _, _) = trie.to_double_array().unwrap();
+        assert_eq!(trie_check.iter().filter(|&&check| check != 0).count(), nodes);
+    }
+
+    #[test]
+    fn test_from_parts_to_parts() {
+        let mut trie: Trie<u32> = Trie::new();
+        trie.set("すもも", 0);
+        trie.set("もも", 1);
+        trie.set("もも", 2);
+        let mut da = DoubleArray::from_parts(trie.to_double_array().unwrap());
+        assert_eq!(2, da.len());
+        assert_eq!(Some(&[1, 2][..]), da.get("もも"));
+        da.insert("すもももも", 3);
+        da.delete("すもも");
+
+        // 書き出した配列を辞書として読み込んで探索できる
+        let (base_arr, check_arr, data_arr, data_offsets) = da.to_parts();
+        // 削除したkeyの値は詰める
+        assert_eq!(3, data_offsets.len());
+        assert_eq!(3, data_arr.len());
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+            matrix: &MatrixBuilder::new(1, 1), features: &FeatureBuilder::new(), char_def: &CharDefBuilder::new(),
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        DictionarySet::write(&parts, &mut bytes).unwrap();
+        let aligned = AlignedBytes::new(&bytes);
+        let dict_set: DictionarySet<u32> = DictionarySet::new(&aligned).unwrap();
+        assert_eq!(Some(&[1, 2][..]), dict_set.get_trie("もも"));
+        assert_eq!(Some(&[3][..])   , dict_set.get_trie("すもももも"));
+        assert_eq!(None, dict_set.get_trie("すもも"));
+        assert_eq!(2, dict_set.entries().count());
+    }
+
+    #[test]
+    fn test_search() {
+        let da: DoubleArray<u32> = vec![
+            ("す", 0), ("すし", 1), ("すし", 2), ("すしや", 3), ("すもも", 4), ("もも", 5), ("a", 6), ("ab", 7),
+        ].into_iter().collect();
+        assert_eq!(7, da.len());
+        assert_eq!(Some(&[1, 2][..]), da.get("すし"));
+        assert_eq!(None, da.get("すしやさん"));
+
+        // 共通接頭辞検索は短い順
+        let found: Vec<(&str, &[u32])> = da.common_prefix_search("すしやさん");
+        assert_eq!(vec![("す", &[0][..]), ("すし", &[1, 2][..]), ("すしや", &[3][..])], found);
+        assert!(da.common_prefix_search("もう").is_empty());
+        assert!(da.common_prefix_search("").is_empty());
+
+        // 予測検索は辞書順
+        let found: Vec<(String, &[u32])> = da.predictive_search("す", 10);
+        let expected: Vec<(String, &[u32])> = vec![
+            ("す".to_string(), &[0][..]), ("すし".to_string(), &[1, 2][..]), ("すしや".to_string(), &[3][..]), ("すもも".to_string(), &[4][..]),
+        ];
+        assert_eq!(expected, found);
+        assert_eq!(2, da.predictive_search("す", 2).len());
+        assert_eq!(8, da.predictive_search("", 100).iter().map(|(_, values)| values.len()).sum::<usize>());
+        assert!(da.predictive_search("c", 10).is_empty());
+    }
+
+    #[test]
+    fn test_key_of_broken() {
+        // 親のbaseが子のindexより大きい壊れた配列でも、panicせずにNoneを返す
+        let mut base_arr: Vec<u32> = vec![0; MAX_KEY];
+        let mut check_arr: Vec<u32> = vec![0; MAX_KEY];
+        base_arr[ROOT] = 10;
+        check_arr[2] = ROOT as u32;
+        check_arr[BYTE_VALUE_KEY as usize] = 2;
+        let da_ref: DoubleArrayRef<u32> = DoubleArrayRef::from_sections(&base_arr, &check_arr, &[0], &[0, 1], KeyUnit::Byte).unwrap();
+        assert_eq!(vec![BYTE_VALUE_KEY as usize], da_ref.entries().map(|(node, _)| node).collect::<Vec<usize>>());
+        assert_eq!(None, da_ref.key_of(BYTE_VALUE_KEY as usize));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut da: DoubleArray<u32> = vec![("すし", 0), ("すもも", 1), ("もも", 2)].into_iter().collect();
+        let tried = DoubleArray::try_from_iter(vec![("すし", 0), ("すもも", 1), ("もも", 2)]).unwrap();
+        assert_eq!(da.predictive_search("", 10), tried.predictive_search("", 10));
+        da.insert("すしや", 3);
+        da.delete("もも");
+        let bytes = da.to_bytes();
+
+        // バイト列から参照として読み込める
+        let mut aligned = AlignedBytes::new(&bytes);
+        let da_ref: DoubleArrayRef<u32> = DoubleArrayRef::new(&aligned).unwrap();
+        assert_eq!(3, da_ref.len());
+        assert_eq!(Some(&[3][..]), da_ref.get("すしや"));
+        assert_eq!(None, da_ref.get("もも"));
+        assert_eq!(vec![("すし", &[0][..]), ("すしや", &[3][..])], da_ref.common_prefix_search("すしやさん"));
+        assert_eq!(da.predictive_search("", 10), da_ref.predictive_search("", 10));
+
+        // アラインメントが揃っていなくてもコピーして読み込める
+        let mut unaligned = vec![0u8];
+        unaligned.extend_from_slice(&bytes);
+        let mut da2: DoubleArray<u32> = DoubleArray::from_bytes(&unaligned[1..]).unwrap();
+        assert_eq!(3, da2.len());
+        da2.insert("もも", 4);
+        assert_eq!(Some(&[4][..]), da2.get("もも"));
+        assert_eq!(Some(&[0][..]), da2.get("すし"));
+
+        // 値のサイズが異なる場合、壊れている場合はエラー
+        assert!(DoubleArrayRef::<u64>::new(&aligned).is_err());
+        let last = aligned.len() - 1;
+        aligned[last] ^= 1;
+        assert!(DoubleArrayRef::<u32>::new(&aligned).is_err());
+        assert!(DoubleArrayRef::<u32>::new(&aligned[..16]).is_err());
+    }
+}
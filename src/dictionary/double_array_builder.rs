@@ -0,0 +1,265 @@
+use crate::dictionary::bit_cache::BitCache;
+use crate::dictionary::trie::{find_base_of, DoubleArrayParts, BYTE_VALUE_KEY};
+use crate::error::{Error, Result};
+
+use std::mem;
+
+/// data_arrの最大長(data_offsetsにu32で格納するため)
+const MAX_DATA_LEN: usize = u32::max_value() as usize;
+/// 子ノードが取りうるkeyのパターン(値のノードのkeyを含む)
+const MAX_KEY: usize = u8::max_value() as usize + 1;
+
+/// 配置が決まったノード
+/// ノード自身のindexは親ノードの配置が決まるまでわからないので、
+/// 子ノードのcheckは親ノードを配置するときに書き込む
+struct PlacedNode {
+    key : u32,      // 親ノードから遷移するkey
+    base: u32,      // 子ノードのbase値。値のノードの場合はkeyの番号
+    keys: Vec<u32>, // 子ノードのkey(checkを書き込むために使う)
+}
+
+/// 辞書順に並んだkeyから、トライ木を作らずに直接バイト単位のダブル配列を構築する
+/// 子ノードがすべて決まったノードから順に(葉の側から)配置するので、
+/// 構築中に保持するのは直前のkeyの経路上のノードだけになる
+/// 構築したダブル配列はTrie::to_double_arrayと同じ形式で、同じ方法で探索できる
+pub struct DoubleArrayBuilder<T: Copy> {
+    base_arr    : Vec<u32>,
+    check_arr   : Vec<u32>,
+    data_arr    : Vec<T>,
+    data_offsets: Vec<u32>,
+    bit_cache   : BitCache,
+    key         : Vec<u8>,              // 直前に追加したkey
+    values      : Vec<T>,               // 直前に追加したkeyの値
+    path        : Vec<Vec<PlacedNode>>, // path[d]は直前のkeyの先頭dバイトのノードの、配置が決まった子ノード
+}
+
+impl<T: Copy> DoubleArrayBuilder<T> {
+    pub fn new() -> DoubleArrayBuilder<T> {
+        let mut bit_cache = BitCache::new();
+        bit_cache.set(0);
+        bit_cache.set(1);
+        DoubleArrayBuilder {
+            base_arr    : vec![0; MAX_KEY],
+            check_arr   : vec![0; MAX_KEY],
+            data_arr    : Vec::new(),
+            data_offsets: vec![0],
+            bit_cache,
+            key         : Vec::new(),
+            values      : Vec::new(),
+            path        : vec![Vec::new()],
+        }
+    }
+
+    /// keyと値を追加する
+    /// keyはバイト列の昇順に追加しなければならない。同じkeyを続けて追加すると、一つのkeyに複数の値を登録できる
+    ///
+    /// # Arguments
+    ///
+    /// * `key`   - 追加するキー
+    /// * `value` - キーに対応する値
+    pub fn push(&mut self, key: &str, value: T) -> Result<()> {
+        let key = key.as_bytes();
+        if !self.values.is_empty() {
+            if key == &self.key[..] {
+                self.values.push(value);
+                return Ok(());
+            }
+            if key < &self.key[..] {
+                return Err(Error::DoubleArray(format!(
+                    "keyが昇順に並んでいません。({:?} の後に {:?})",
+                    String::from_utf8_lossy(&self.key), String::from_utf8_lossy(key),
+                )));
+            }
+            // 直前のkeyとの共通接頭辞より深いノードは、もう子ノードが増えないので配置する
+            let prefix_len = self.key.iter().zip(key).take_while(|(a, b)| a == b).count();
+            self.flush_values()?;
+            self.place_path(prefix_len)?;
+        }
+        self.key.clear();
+        self.key.extend_from_slice(key);
+        self.path.resize_with(key.len() + 1, Vec::new);
+        self.values.push(value);
+        Ok(())
+    }
+
+    /// 残りのノードを配置して、ダブル配列を返す
+    /// (base_arr, check_arr, data_arr, data_offsets)を返す
+    pub fn finish(mut self) -> Result<DoubleArrayParts<T>> {
+        if !self.values.is_empty() {
+            self.flush_values()?;
+            self.place_path(0)?;
+            // ルートノードの子ノードを配置する
+            let children = mem::replace(&mut self.path[0], Vec::new());
+            let root = self.place_children(0, children)?;
+            self.base_arr[1] = root.base;
+            self.write_checks(&root, 1);
+        }
+
+        // 配列のリサイズ
+        // 配列の長さは最低でも256にする(DictionarySet::newでダブル配列のサイズとして検証する)
+        let new_len = match self.bit_cache.last_index_of_one() {
+            None          => MAX_KEY,
+            Some(new_len) => new_len + MAX_KEY,
+        };
+        self.base_arr.resize(new_len, 0);
+        self.check_arr.resize(new_len, 0);
+        Ok((self.base_arr, self.check_arr, self.data_arr, self.data_offsets))
+    }
+
+    /// 直前のkeyの値をdata_arrに追加して、keyのノードの子ノードに値のノードを追加する
+    fn flush_values(&mut self) -> Result<()> {
+        if self.data_arr.len() + self.values.len() > MAX_DATA_LEN {
+            return Err(Error::DoubleArray(format!("登録できる値は{}個までです。", MAX_DATA_LEN)));
+        }
+        // 値のノードのbaseにはkeyの番号を格納する
+        let entry = (self.data_offsets.len() - 1) as u32;
+        self.data_arr.extend_from_slice(&self.values);
+        self.data_offsets.push(self.data_arr.len() as u32);
+        self.values.clear();
+        self.path[self.key.len()].push(PlacedNode { key: BYTE_VALUE_KEY, base: entry, keys: Vec::new() });
+        Ok(())
+    }
+
+    /// 直前のkeyの経路上で、深さがdepthより深いノードを配置する
+    fn place_path(&mut self, depth: usize) -> Result<()> {
+        while self.path.len() > depth + 1 {
+            let children = self.path.pop().unwrap();
+            let d = self.path.len();
+            let node = self.place_children(self.key[d - 1] as u32, children)?;
+            self.path[d - 1].push(node);
+        }
+        Ok(())
+    }
+
+    /// ノードの子ノードを配置して、配置したノードを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key`      - 親ノードから遷移するkey
+    /// * `children` - 子ノード
+    fn place_children(&mut self, key: u32, mut children: Vec<PlacedNode>) -> Result<PlacedNode> {
+        // 値のノードは他の子ノードより先に追加されるので、keyの昇順に並べ直す
+        children.sort_by_key(|child| child.key);
+        self.bit_cache.update_start();
+        let base = find_base_of(children.iter().map(|child| child.key as usize), &self.bit_cache)?;
+
+        // 配列の長さが足りなければ配列を拡張
+        let mut len = self.base_arr.len();
+        while base + MAX_KEY >= len {
+            len *= 2;
+        }
+        self.base_arr.resize(len, 0);
+        self.check_arr.resize(len, 0);
+
+        for child in children.iter() {
+            let i = base + child.key as usize;
+            self.bit_cache.set(i);
+            self.base_arr[i] = child.base;
+            self.write_checks(child, i);
+        }
+        Ok(PlacedNode { key, base: base as u32, keys: children.iter().map(|child| child.key).collect() })
+    }
+
+    /// ノードの配置先idxが決まったので、子ノードのcheckにidxを書き込む
+    fn write_checks(&mut self, node: &PlacedNode, idx: usize) {
+        for &key in node.keys.iter() {
+            self.check_arr[node.base as usize + key as usize] = idx as u32;
+        }
+    }
+}
+
+impl<T: Copy> Default for DoubleArrayBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::trie::Trie;
+
+    #[test]
+    fn test_double_array_builder() {
+        let entries = [
+            ("a", 0), ("ab", 1), ("ab", 2), ("abc", 3), ("abd", 4), ("b", 5),
+            ("おすし", 6), ("お寿司", 7), ("寿司", 8), ("🍣", 9),
+        ];
+        let mut builder: DoubleArrayBuilder<u32> = DoubleArrayBuilder::new();
+        for &(key, value) in entries.iter() {
+            builder.push(key, value).unwrap();
+        }
+        let (base_arr, check_arr, data_arr, data_offsets) = builder.finish().unwrap();
+
+        // Trie::to_double_arrayと同じ方法で探索できる
+        let mut trie: Trie<u32> = Trie::new();
+        for &(key, value) in entries.iter() {
+            trie.set(key, value);
+        }
+        for &(key, _) in entries.iter() {
+            assert_eq!(trie.get(key), find(key, &base_arr, &check_arr, &data_arr, &data_offsets), "{}", key);
+        }
+        for key in ["", "abcd", "ac", "c", "お", "おす", "寿"].iter() {
+            assert_eq!(None, find(key, &base_arr, &check_arr, &data_arr, &data_offsets), "{}", key);
+        }
+        // 値はkeyの辞書順に格納される
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9], data_arr);
+        assert_eq!(vec![0, 1, 3, 4, 5, 6, 7, 8, 9, 10], data_offsets);
+    }
+
+    #[test]
+    fn test_double_array_builder_many_keys() {
+        // 子ノードの多いノードと深いノードが混在する場合
+        let mut keys: Vec<String> = (0..3000).map(|i| format!("{:x}", i * 7919 % 65536)).collect();
+        keys.extend((1..50).map(|len| "z".repeat(len)));
+        keys.sort();
+        keys.dedup();
+        let mut builder: DoubleArrayBuilder<usize> = DoubleArrayBuilder::new();
+        for (i, key) in keys.iter().enumerate() {
+            builder.push(key, i).unwrap();
+        }
+        let (base_arr, check_arr, data_arr, data_offsets) = builder.finish().unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(Some(&[i][..]), find(key, &base_arr, &check_arr, &data_arr, &data_offsets), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_double_array_builder_empty() {
+        let builder: DoubleArrayBuilder<u32> = DoubleArrayBuilder::new();
+        let (base_arr, check_arr, data_arr, data_offsets) = builder.finish().unwrap();
+        assert_eq!(None, find("a", &base_arr, &check_arr, &data_arr, &data_offsets));
+        assert!(base_arr.len() >= 256);
+        assert_eq!(vec![0], data_offsets);
+    }
+
+    #[test]
+    fn test_double_array_builder_unsorted() {
+        let mut builder: DoubleArrayBuilder<u32> = DoubleArrayBuilder::new();
+        builder.push("b", 0).unwrap();
+        builder.push("b", 1).unwrap();
+        // 昇順でなければエラー
+        assert!(builder.push("a", 2).is_err());
+        // 前のkeyの接頭辞も昇順ではない
+        let mut builder: DoubleArrayBuilder<u32> = DoubleArrayBuilder::new();
+        builder.push("ab", 0).unwrap();
+        assert!(builder.push("a", 1).is_err());
+    }
+
+    fn find<'a, T>(key: &str, base_arr: &[u32], check_arr: &[u32], data_arr: &'a [T], data_offsets: &[u32]) -> Option<&'a [T]> {
+        let mut idx = 1;
+        for &byte in key.as_bytes() {
+            let next_idx = base_arr[idx] as usize + byte as usize;
+            if check_arr[next_idx] as usize != idx {
+                return None;
+            }
+            idx = next_idx;
+        }
+        let value_idx = base_arr[idx] as usize + BYTE_VALUE_KEY as usize;
+        if check_arr[value_idx] as usize != idx {
+            return None;
+        }
+        let entry = base_arr[value_idx] as usize;
+        Some(&data_arr[(data_offsets[entry] as usize)..(data_offsets[entry + 1] as usize)])
+    }
+}
@@ -0,0 +1,103 @@
+use crate::token::Token;
+use crate::dictionary::DictionarySet;
+use crate::dictionary::feature_builder::join_feature;
+use crate::error::Result;
+
+use std::io::prelude::*;
+use std::iter;
+
+/// 辞書に登録されている単語をlex.csvの形式で書き込む
+/// 単語はkeyの辞書順、同じkeyの単語は登録した順に並ぶ
+///
+/// # Arguments
+///
+/// * `dict_set` - 辞書
+/// * `writer`   - 書き込み先
+pub fn write_lex<W: Write>(dict_set: &DictionarySet<Token>, writer: &mut W) -> Result<()> {
+    let mut entries: Vec<(String, &[Token])> = dict_set.entries()
+        .filter_map(|(node, tokens)| dict_set.key_of(node).map(|key| (key, tokens)))
+        .collect();
+    // 同じkeyは存在しないので、keyだけで並べ替えればよい
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    for (key, tokens) in entries {
+        let surface = join_feature(iter::once(key.as_str()));
+        for token in tokens {
+            write!(writer, "{},{},{},{}", surface, token.left_id, token.right_id, token.cost)?;
+            let feature = dict_set.get_feature(token.feature_id as usize);
+            if !feature.is_empty() {
+                write!(writer, ",{}", feature)?;
+            }
+            writer.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// 辞書の連接コスト表をmatrix.defの形式で書き込む
+///
+/// # Arguments
+///
+/// * `dict_set` - 辞書
+/// * `writer`   - 書き込み先
+pub fn write_matrix<W: Write>(dict_set: &DictionarySet<Token>, writer: &mut W) -> Result<()> {
+    let (left_max, right_max) = dict_set.get_matrix_size();
+    writeln!(writer, "{} {}", left_max, right_max)?;
+    for left_id in 0..left_max {
+        for right_id in 0..right_max {
+            writeln!(writer, "{} {} {}", left_id, right_id, dict_set.get_matrix(left_id, right_id))?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::trie::Trie;
+    use crate::dictionary::matrix_builder::MatrixBuilder;
+    use crate::dictionary::feature_builder::FeatureBuilder;
+    use crate::dictionary::char_def::CharDefBuilder;
+    use crate::dictionary::DictionaryParts;
+    use crate::util::AlignedBytes;
+
+    #[test]
+    fn test_dump() {
+        let mut trie: Trie<Token> = Trie::new();
+        let mut features = FeatureBuilder::new();
+        let lex = [
+            ("すもも", 1, 0, 100 , "名詞,一般"),
+            ("a,b"   , 0, 1, -10 , "記号,一般"),
+            ("すもも", 2, 1, 200 , "動詞,自立"),
+            ("も"    , 1, 1, 50  , ""),
+        ];
+        for &(surface, left_id, right_id, cost, feature) in lex.iter() {
+            let feature_id = features.push(feature);
+            trie.set(surface, Token::new(left_id, right_id, cost, feature_id));
+        }
+        let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
+        let mut matrix = MatrixBuilder::new(2, 3);
+        matrix.set(1, 2, -5).unwrap();
+        let mut char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+        char_def.add_unknown("DEFAULT", Token::unknown()).unwrap();
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+            matrix: &matrix, features: &features, char_def: &char_def,
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        DictionarySet::write(&parts, &mut bytes).unwrap();
+        let aligned = AlignedBytes::new(&bytes);
+        let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+
+        let mut lex_csv: Vec<u8> = Vec::new();
+        write_lex(&dict_set, &mut lex_csv).unwrap();
+        assert_eq!(
+            "\"a,b\",0,1,-10,記号,一般\nすもも,1,0,100,名詞,一般\nすもも,2,1,200,動詞,自立\nも,1,1,50\n",
+            String::from_utf8(lex_csv).unwrap(),
+        );
+
+        let mut matrix_def: Vec<u8> = Vec::new();
+        write_matrix(&dict_set, &mut matrix_def).unwrap();
+        assert_eq!("2 3\n0 0 0\n0 1 0\n0 2 0\n1 0 0\n1 1 0\n1 2 -5\n", String::from_utf8(matrix_def).unwrap());
+    }
+}
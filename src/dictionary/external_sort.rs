@@ -0,0 +1,253 @@
+use crate::error::Result;
+use crate::token::Token;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 一時ファイルに書き出す値の形式
+/// メモリ上の表現(パディングやポインタを含みうる)ではなく、フィールドごとにリトルエンディアンで書き出す
+pub trait SortValue: Copy {
+    /// 書き出したときのバイト数
+    const SIZE: usize;
+
+    /// bytes(長さSIZE)に値を書き込む
+    fn encode(&self, bytes: &mut [u8]);
+
+    /// bytes(長さSIZE)から値を読み込む
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+impl SortValue for u32 {
+    const SIZE: usize = 4;
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(bytes);
+        u32::from_le_bytes(buf)
+    }
+}
+
+impl SortValue for usize {
+    const SIZE: usize = 8;
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(&(*self as u64).to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(bytes);
+        u64::from_le_bytes(buf) as usize
+    }
+}
+
+impl SortValue for Token {
+    const SIZE: usize = 10;
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes[0..2].copy_from_slice(&self.left_id.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.right_id.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.cost.to_le_bytes());
+        bytes[6..10].copy_from_slice(&self.feature_id.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let feature_id = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        Token::new(u16_at(0), u16_at(2), u16_at(4) as i16, feature_id)
+    }
+}
+
+/// 一時ファイル名を一意にするための連番
+static SORTER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// (key, 値)の組をkeyのバイト列の昇順に並べ替える
+/// chunk_size個ごとにメモリ上で並べ替えて一時ファイルに書き出し、最後にマージするので、
+/// メモリに保持するのは高々chunk_size個の組になる
+/// 同じkeyの値は追加した順に並ぶ
+///
+/// 一時ファイルは環境変数TMPDIRのディレクトリ(std::env::temp_dir)に作成し、終了時に削除する
+/// 値はSortValueの形式で書き出す
+pub struct ExternalSorter<T: SortValue> {
+    chunk_size: usize,
+    id        : usize,
+    entries   : Vec<(String, T)>,
+    files     : Vec<PathBuf>,
+}
+
+impl<T: SortValue> ExternalSorter<T> {
+    /// # Arguments
+    ///
+    /// * `chunk_size` - メモリ上で並べ替える組の数
+    pub fn new(chunk_size: usize) -> ExternalSorter<T> {
+        ExternalSorter {
+            chunk_size: chunk_size.max(1),
+            id        : SORTER_ID.fetch_add(1, Ordering::SeqCst),
+            entries   : Vec::new(),
+            files     : Vec::new(),
+        }
+    }
+
+    /// (key, 値)の組を追加する
+    ///
+    /// # Arguments
+    ///
+    /// * `key`   - キー
+    /// * `value` - キーに対応する値
+    pub fn push(&mut self, key: &str, value: T) -> Result<()> {
+        self.entries.push((key.to_string(), value));
+        if self.entries.len() >= self.chunk_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// 追加した組をkeyの昇順にfに渡す
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - 組を受け取る関数
+    pub fn finish<F>(mut self, mut f: F) -> Result<()> where F: FnMut(&str, T) -> Result<()> {
+        // 一時ファイルに書き出していなければメモリ上で並べ替えるだけでよい
+        if self.files.is_empty() {
+            self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+            for (key, value) in self.entries.iter() {
+                f(key, *value)?;
+            }
+            return Ok(());
+        }
+        if !self.entries.is_empty() {
+            self.spill()?;
+        }
+
+        // 各一時ファイルの先頭の組を比較してマージする。同じkeyなら先に書き出したファイルを優先する
+        let mut readers: Vec<BufReader<File>> = Vec::with_capacity(self.files.len());
+        for path in self.files.iter() {
+            readers.push(BufReader::new(File::open(path)?));
+        }
+        let mut values: Vec<Option<T>> = vec![None; readers.len()];
+        let mut heap: BinaryHeap<Reverse<(String, usize)>> = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some((key, value)) = read_entry(reader)? {
+                values[i] = Some(value);
+                heap.push(Reverse((key, i)));
+            }
+        }
+        while let Some(Reverse((key, i))) = heap.pop() {
+            f(&key, values[i].take().unwrap())?;
+            if let Some((key, value)) = read_entry(&mut readers[i])? {
+                values[i] = Some(value);
+                heap.push(Reverse((key, i)));
+            }
+        }
+        Ok(())
+    }
+
+    /// メモリ上の組を並べ替えて一時ファイルに書き出す
+    fn spill(&mut self) -> Result<()> {
+        self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let path = env::temp_dir().join(format!("midomoji-sort-{}-{}-{}.tmp", process::id(), self.id, self.files.len()));
+        self.files.push(path.clone());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut value_bytes = vec![0u8; T::SIZE];
+        for (key, value) in self.entries.iter() {
+            value.encode(&mut value_bytes);
+            writer.write_all(&(key.len() as u32).to_le_bytes())?;
+            writer.write_all(key.as_bytes())?;
+            writer.write_all(&value_bytes)?;
+        }
+        writer.flush()?;
+        self.entries.clear();
+        Ok(())
+    }
+}
+
+impl<T: SortValue> Drop for ExternalSorter<T> {
+    fn drop(&mut self) {
+        for path in self.files.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// 一時ファイルから組を一つ読み込む。ファイルの終端ならNone
+fn read_entry<T: SortValue, R: Read>(reader: &mut R) -> Result<Option<(String, T)>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {},
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let mut key = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut key)?;
+    let mut value_bytes = vec![0u8; T::SIZE];
+    reader.read_exact(&mut value_bytes)?;
+    let value = T::decode(&value_bytes);
+    // 書き出したときはStringだったのでutf8として正しい
+    let key = String::from_utf8(key).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some((key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort(entries: &[(&str, u32)], chunk_size: usize) -> Vec<(String, u32)> {
+        let mut sorter: ExternalSorter<u32> = ExternalSorter::new(chunk_size);
+        for &(key, value) in entries.iter() {
+            sorter.push(key, value).unwrap();
+        }
+        let mut ret = Vec::new();
+        sorter.finish(|key, value| {
+            ret.push((key.to_string(), value));
+            Ok(())
+        }).unwrap();
+        ret
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let entries = [
+            ("すもも", 0), ("b", 1), ("a", 2), ("すもも", 3), ("もも", 4), ("a", 5), ("ab", 6), ("", 7),
+        ];
+        let expected: Vec<(String, u32)> = [
+            ("", 7), ("a", 2), ("a", 5), ("ab", 6), ("b", 1), ("すもも", 0), ("すもも", 3), ("もも", 4),
+        ].iter().map(|&(key, value)| (key.to_string(), value)).collect();
+        // メモリ上だけで並べ替える場合と、一時ファイルをマージする場合で結果が同じ
+        for &chunk_size in [100, 8, 3, 1].iter() {
+            assert_eq!(expected, sort(&entries, chunk_size), "chunk_size={}", chunk_size);
+        }
+        assert!(sort(&[], 1).is_empty());
+    }
+
+    #[test]
+    fn test_external_sort_token() {
+        let tokens = [Token::new(1, 2, -3, 4), Token::new(65535, 0, i16::min_value(), Token::NO_FEATURE)];
+        let mut bytes = vec![0u8; Token::SIZE];
+        for token in tokens.iter() {
+            token.encode(&mut bytes);
+            assert_eq!(*token, Token::decode(&bytes));
+        }
+        // 一時ファイルを経由しても値が変わらない
+        let mut sorter: ExternalSorter<Token> = ExternalSorter::new(1);
+        sorter.push("b", tokens[0]).unwrap();
+        sorter.push("a", tokens[1]).unwrap();
+        let mut ret = Vec::new();
+        sorter.finish(|key, token| {
+            ret.push((key.to_string(), token));
+            Ok(())
+        }).unwrap();
+        assert_eq!(vec![("a".to_string(), tokens[1]), ("b".to_string(), tokens[0])], ret);
+    }
+}
@@ -0,0 +1,267 @@
+/// 素性文字列表を構築する構造体
+/// 素性文字列はbytesに連結して格納し、各素性の開始位置をoffsetsで管理する
+/// feature_id番目の素性は bytes[offsets[feature_id]..offsets[feature_id + 1]]
+pub struct FeatureBuilder {
+    offsets: Vec<u32>,
+    bytes  : Vec<u8>,
+}
+
+impl FeatureBuilder {
+    pub fn new() -> FeatureBuilder {
+        FeatureBuilder { offsets: vec![0], bytes: Vec::new() }
+    }
+
+    pub fn get_offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// 登録されている素性の数
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 素性文字列を追加して、その素性のIDを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `feature` - 素性文字列
+    pub fn push(&mut self, feature: &str) -> u32 {
+        let feature_id = self.len() as u32;
+        self.bytes.extend_from_slice(feature.as_bytes());
+        self.offsets.push(self.bytes.len() as u32);
+        feature_id
+    }
+
+    /// 素性文字列を取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `feature_id` - 素性ID
+    pub fn get(&self, feature_id: usize) -> &str {
+        let start = self.offsets[feature_id] as usize;
+        let end   = self.offsets[feature_id + 1] as usize;
+        std::str::from_utf8(&self.bytes[start..end]).unwrap()
+    }
+}
+
+impl Default for FeatureBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// csvのカラムを素性文字列に連結する
+/// カンマ・ダブルクォートを含むカラムはcsvの形式でエスケープする
+///
+/// # Arguments
+///
+/// * `columns` - 素性のカラム(品詞, 活用, 原形, 読み...)
+pub fn join_feature<'a, I: IntoIterator<Item = &'a str>>(columns: I) -> String {
+    let mut feature = String::new();
+    for (i, column) in columns.into_iter().enumerate() {
+        if i > 0 {
+            feature.push(',');
+        }
+        if column.contains(',') || column.contains('"') {
+            feature.push('"');
+            feature.push_str(&column.replace("\"", "\"\""));
+            feature.push('"');
+        } else {
+            feature.push_str(column);
+        }
+    }
+    feature
+}
+
+/// 素性文字列をカラムに分割する
+/// join_featureでエスケープされたカラムは元に戻す
+///
+/// # Arguments
+///
+/// * `feature` - 素性文字列
+pub fn split_feature(feature: &str) -> Vec<String> {
+    let mut columns: Vec<String> = Vec::new();
+    if feature.is_empty() {
+        return columns;
+    }
+    let mut column = String::new();
+    let mut quoted = false;
+    let mut chars = feature.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    column.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                column.push(c);
+            }
+        } else if c == '"' && column.is_empty() {
+            quoted = true;
+        } else if c == ',' {
+            columns.push(column);
+            column = String::new();
+        } else {
+            column.push(c);
+        }
+    }
+    columns.push(column);
+    columns
+}
+
+/// 素性のカラムの位置(辞書の形式ごとに異なる)
+/// 品詞(品詞, 品詞細分類1-3)の位置はどの形式も先頭の4カラム
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureLayout {
+    /// 原形のカラムの位置
+    pub lemma        : usize,
+    /// 読みのカラムの位置
+    pub reading      : usize,
+    /// 発音のカラムの位置
+    pub pronunciation: usize,
+}
+
+impl FeatureLayout {
+    /// IPADIC(品詞, 品詞細分類1-3, 活用型, 活用形, 原形, 読み, 発音)
+    pub const IPADIC: FeatureLayout = FeatureLayout { lemma: 6, reading: 7, pronunciation: 8 };
+    /// UniDic(品詞大分類-小分類, 活用型, 活用形, 語彙素読み, 語彙素, 書字形出現形, 発音形出現形, ...)
+    /// 原形は語彙素、読みは語彙素読み、発音は発音形出現形とする
+    pub const UNIDIC: FeatureLayout = FeatureLayout { lemma: 7, reading: 6, pronunciation: 9 };
+}
+
+/// 素性文字列をカラムに分割して、品詞・原形・読みなどを参照する
+/// 原形・読み・発音のカラムの位置は、parseに渡した辞書の形式(FeatureLayout)に従う
+/// 値のないカラム("*")と、存在しないカラム(未知語など)はNoneを返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    columns: Vec<String>,
+    layout : FeatureLayout,
+}
+
+impl Feature {
+    /// 品詞(品詞, 品詞細分類1-3)のカラム数
+    pub const POS_COLUMNS: usize = 4;
+
+    /// # Arguments
+    ///
+    /// * `feature` - 素性文字列(DictionarySet::get_featureなどで取得したもの)
+    /// * `layout`  - 素性のカラムの位置(FeatureLayout::IPADIC, FeatureLayout::UNIDICなど)
+    pub fn parse(feature: &str, layout: FeatureLayout) -> Feature {
+        Feature { columns: split_feature(feature), layout }
+    }
+
+    /// すべてのカラム
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// i番目のカラム。"*"や存在しないカラムの場合はNone
+    ///
+    /// # Arguments
+    ///
+    /// * `i` - カラムの位置
+    pub fn get(&self, i: usize) -> Option<&str> {
+        match self.columns.get(i) {
+            Some(column) if column != "*" => Some(column),
+            _                             => None,
+        }
+    }
+
+    /// 品詞と品詞細分類のカラム("*"を含む)
+    pub fn pos(&self) -> &[String] {
+        &self.columns[..self.columns.len().min(Self::POS_COLUMNS)]
+    }
+
+    /// 原形
+    pub fn lemma(&self) -> Option<&str> {
+        self.get(self.layout.lemma)
+    }
+
+    /// 読み
+    pub fn reading(&self) -> Option<&str> {
+        self.get(self.layout.reading)
+    }
+
+    /// 発音
+    pub fn pronunciation(&self) -> Option<&str> {
+        self.get(self.layout.pronunciation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_builder_1() {
+        let mut features = FeatureBuilder::new();
+        assert_eq!(0, features.push("名詞,一般,*,*,*,*,すもも,スモモ,スモモ"));
+        assert_eq!(1, features.push(""));
+        assert_eq!(2, features.push("助詞,係助詞,*,*,*,*,も,モ,モ"));
+        assert_eq!(3, features.len());
+        // 登録した順にIDが振られ、同じ文字列が取得できる
+        assert_eq!("名詞,一般,*,*,*,*,すもも,スモモ,スモモ", features.get(0));
+        assert_eq!(""                                       , features.get(1));
+        assert_eq!("助詞,係助詞,*,*,*,*,も,モ,モ"          , features.get(2));
+    }
+
+    #[test]
+    fn test_join_split_feature() {
+        let columns = vec!["記号", "読点", ",", "\"引用\""];
+        let feature = join_feature(columns.iter().cloned());
+        // カンマ・ダブルクォートを含むカラムはエスケープされる
+        assert_eq!("記号,読点,\",\",\"\"\"引用\"\"\"", feature);
+        // 分割すると元のカラムに戻る
+        assert_eq!(columns, split_feature(&feature));
+        // 空文字列はカラムなし
+        assert_eq!(Vec::<String>::new(), split_feature(""));
+    }
+
+    #[test]
+    fn test_feature() {
+        let feature = Feature::parse("名詞,一般,*,*,*,*,すもも,スモモ,スモモ", FeatureLayout::IPADIC);
+        assert_eq!(vec!["名詞", "一般", "*", "*"], feature.pos());
+        assert_eq!(Some("すもも"), feature.lemma());
+        assert_eq!(Some("スモモ"), feature.reading());
+        assert_eq!(Some("スモモ"), feature.pronunciation());
+        assert_eq!(9, feature.columns().len());
+        assert_eq!(None, feature.get(2));
+
+        // 読みのない未知語
+        let feature = Feature::parse("名詞,固有名詞,組織,*,*,*,*", FeatureLayout::IPADIC);
+        assert_eq!(vec!["名詞", "固有名詞", "組織", "*"], feature.pos());
+        assert_eq!(None, feature.lemma());
+        assert_eq!(None, feature.reading());
+
+        // エスケープされたカラム
+        let feature = Feature::parse(&join_feature(vec!["記号", "読点", "*", "*", "*", "*", ",", "、", "、"]), FeatureLayout::IPADIC);
+        assert_eq!(Some(","), feature.lemma());
+        assert_eq!(Some("、"), feature.reading());
+
+        let feature = Feature::parse("", FeatureLayout::IPADIC);
+        assert!(feature.pos().is_empty());
+        assert_eq!(None, feature.reading());
+
+        // UniDicは原形(語彙素)・読み(語彙素読み)・発音の位置が異なる
+        let feature = Feature::parse("名詞,普通名詞,一般,*,*,*,スモモ,李,すもも,スモモ,すもも,スモモ,和,*,*,*,*", FeatureLayout::UNIDIC);
+        assert_eq!(vec!["名詞", "普通名詞", "一般", "*"], feature.pos());
+        assert_eq!(Some("李"), feature.lemma());
+        assert_eq!(Some("スモモ"), feature.reading());
+        assert_eq!(Some("スモモ"), feature.pronunciation());
+        let feature = Feature::parse("動詞,一般,*,*,五段-ワア行,連用形-一般,イウ,言う,言っ,イッ,言う,イウ,和,*,*,*,*", FeatureLayout::UNIDIC);
+        assert_eq!(Some("言う"), feature.lemma());
+        assert_eq!(Some("イウ"), feature.reading());
+        assert_eq!(Some("イッ"), feature.pronunciation());
+    }
+}
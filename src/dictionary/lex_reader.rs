@@ -0,0 +1,60 @@
+use crate::error::{Error, Result};
+
+use std::fs::File;
+use csv::StringRecord;
+
+/// csv(lex.csv, unk.def, ユーザー辞書)を1行ずつ読み込んでfに渡す
+/// fがError::InvalidInputを返した場合は、ファイル名と行番号を付けたError::Parseにする
+///
+/// # Arguments
+///
+/// * `path` - csvのパス
+/// * `f`    - 1行ごとに呼び出す関数
+pub fn read_csv<F>(path: &str, mut f: F) -> Result<()> where F: FnMut(&StringRecord) -> Result<()> {
+    let file = File::open(path)?;
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(file);
+    for result in reader.records() {
+        let record = result.map_err(|e| Error::from_csv(path, e))?;
+        f(&record).map_err(|e| match e {
+            Error::InvalidInput(message) => {
+                let line = record.position().map(|p| p.line()).unwrap_or(0);
+                Error::parse(path, line, message)
+            },
+            e => e,
+        })?;
+    }
+    Ok(())
+}
+
+/// "表層形,左文脈ID,右文脈ID,コスト,素性..." の形式の行から、表層形・左文脈ID・右文脈ID・コストを取得する
+///
+/// # Arguments
+///
+/// * `record` - csvの1行
+pub fn parse_record(record: &StringRecord) -> Result<(&str, u16, u16, i16)> {
+    if record.len() < 4 {
+        return Err(Error::InvalidInput(format!("表層形,左文脈ID,右文脈ID,コスト が必要です。(columns={})", record.len())));
+    }
+    let left_id  = record[1].parse::<u16>().map_err(|_| Error::InvalidInput(format!("左文脈IDが不正です。({})", &record[1])))?;
+    let right_id = record[2].parse::<u16>().map_err(|_| Error::InvalidInput(format!("右文脈IDが不正です。({})", &record[2])))?;
+    let cost     = record[3].parse::<i16>().map_err(|_| Error::InvalidInput(format!("コストが不正です。({})", &record[3])))?;
+    Ok((&record[0], left_id, right_id, cost))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_record() {
+        let record = StringRecord::from(vec!["すもも", "1285", "1285", "7546", "名詞"]);
+        assert_eq!(("すもも", 1285, 1285, 7546), parse_record(&record).unwrap());
+        let record = StringRecord::from(vec!["すもも", "1285", "1285", "a"]);
+        assert!(parse_record(&record).is_err());
+        let record = StringRecord::from(vec!["すもも", "1285", "-1", "7546"]);
+        assert!(parse_record(&record).is_err());
+        let record = StringRecord::from(vec!["すもも", "1285"]);
+        assert!(parse_record(&record).is_err());
+    }
+}
@@ -1,14 +1,203 @@
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI16, AtomicU64, Ordering};
+use std::thread;
+
+/// 辞書ファイルでの連接コスト表の形式
+/// どの形式でもDictionarySet::get_matrixで同じようにコストを引ける
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixEncoding {
+    /// left_max * right_max の表をそのまま持つ。最も速い
+    Dense  = 0,
+    /// 同じ内容の行・列をひとつにまとめた表と、文脈IDから表の行・列への対応表を持つ
+    Dedup  = 1,
+    /// 最も多く現れるコストをデフォルトとして、それ以外のコストだけを行ごとに列の順で持つ。引くときは二分探索する
+    Sparse = 2,
+}
+
+impl MatrixEncoding {
+    /// 名前(dense, dedup, sparse)から形式を取得する
+    pub fn from_name(name: &str) -> Option<MatrixEncoding> {
+        match name {
+            "dense"  => Some(MatrixEncoding::Dense),
+            "dedup"  => Some(MatrixEncoding::Dedup),
+            "sparse" => Some(MatrixEncoding::Sparse),
+            _        => None,
+        }
+    }
+
+    /// 辞書ファイルのヘッダに書き込んだ値から形式を取得する
+    pub fn from_id(id: u64) -> Option<MatrixEncoding> {
+        match id {
+            0 => Some(MatrixEncoding::Dense),
+            1 => Some(MatrixEncoding::Dedup),
+            2 => Some(MatrixEncoding::Sparse),
+            _ => None,
+        }
+    }
+}
+
+/// 辞書ファイルに書き込む形式に変換した連接コスト表
+/// costs, indexの中身は形式によって異なる
+///
+/// * Dense : costsは left_max * right_max の表。indexは空
+/// * Dedup : costsは 行数 * width の表。indexは左文脈IDごとの行番号(left_max個)と右文脈IDごとの列番号(right_max個)
+/// * Sparse: costsはデフォルト以外のコスト。indexは左文脈IDごとのcostsの開始位置(left_max + 1個)と、costsの各要素の右文脈ID
+pub struct EncodedMatrix {
+    pub encoding: MatrixEncoding,
+    pub costs   : Vec<i16>,
+    pub index   : Vec<u32>,
+    pub width   : usize, // Dedupの表の列数
+    pub default : i16,   // Sparseのデフォルトのコスト
+}
+
 /// 連接コスト表を構築する構造体
 #[allow(dead_code)]
 pub struct MatrixBuilder {
     matrix: Vec<i16>,
     left_max: usize,
     right_max: usize,
+    encoding: MatrixEncoding,
 }
 
 impl MatrixBuilder {
     pub fn new(left_max: usize, right_max: usize) -> MatrixBuilder {
-        MatrixBuilder { matrix: vec![0; left_max * right_max], left_max, right_max }
+        MatrixBuilder { matrix: vec![0; left_max * right_max], left_max, right_max, encoding: MatrixEncoding::Dense }
+    }
+
+    /// matrix.defを読み込む
+    /// 1行目: "<LEFT_MAX> <RIGHT_MAX>", 2行目以降: "<LEFT_ID> <RIGHT_ID> <COST>"
+    /// 不正な行があれば行番号付きのエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - matrix.defのreader
+    pub fn from_matrix_def<R: BufRead>(reader: R) -> Result<MatrixBuilder> {
+        let mut matrix_builder: Option<MatrixBuilder> = None;
+        for (i, line) in reader.lines().enumerate() {
+            let line_no = i as u64 + 1;
+            let line = line?;
+            let record: Vec<&str> = line.split_whitespace().collect();
+            if record.is_empty() {
+                continue;
+            }
+            match matrix_builder {
+                None => {
+                    // header
+                    if record.len() != 2 {
+                        return Err(Error::parse("matrix.def", line_no, "1行目は LEFT_MAX RIGHT_MAX の形式で指定してください。"));
+                    }
+                    let left_max  = parse_column::<usize>(&record, 0, line_no, "LEFT_MAX")?;
+                    let right_max = parse_column::<usize>(&record, 1, line_no, "RIGHT_MAX")?;
+                    matrix_builder = Some(MatrixBuilder::new(left_max, right_max));
+                },
+                Some(ref mut matrix_builder) => {
+                    if record.len() != 3 {
+                        return Err(Error::parse("matrix.def", line_no, "LEFT_ID RIGHT_ID COST の形式で指定してください。"));
+                    }
+                    let left_id  = parse_column::<usize>(&record, 0, line_no, "LEFT_ID")?;
+                    let right_id = parse_column::<usize>(&record, 1, line_no, "RIGHT_ID")?;
+                    let cost     = parse_column::<i16>(&record, 2, line_no, "COST")?;
+                    matrix_builder.set(left_id, right_id, cost).map_err(|e| Error::parse("matrix.def", line_no, e.to_string()))?;
+                },
+            }
+        }
+        matrix_builder.ok_or_else(|| Error::parse("matrix.def", 1, "LEFT_MAX RIGHT_MAX がありません。"))
+    }
+
+    /// matrix.defの内容を複数のスレッドで読み込む
+    /// 2行目以降をスレッド数のチャンクに分けて、行をStringにせずバイト列のまま解析する
+    /// すべての(LEFT_ID, RIGHT_ID)の組のコストがちょうど1回ずつ定義されていなければエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`   - matrix.defの内容
+    /// * `threads` - スレッド数
+    pub fn from_matrix_def_bytes(bytes: Vec<u8>, threads: usize) -> Result<MatrixBuilder> {
+        // header
+        let mut pos = 0;
+        let mut line_no = 0;
+        let (left_max, right_max) = loop {
+            if pos >= bytes.len() {
+                return Err(Error::parse("matrix.def", 1, "LEFT_MAX RIGHT_MAX がありません。"));
+            }
+            let end = line_end(&bytes, pos);
+            line_no += 1;
+            let line = String::from_utf8_lossy(&bytes[pos..end]);
+            pos = (end + 1).min(bytes.len());
+            let record: Vec<&str> = line.split_whitespace().collect();
+            if record.is_empty() {
+                continue;
+            }
+            if record.len() != 2 {
+                return Err(Error::parse("matrix.def", line_no, "1行目は LEFT_MAX RIGHT_MAX の形式で指定してください。"));
+            }
+            let left_max  = parse_column::<usize>(&record, 0, line_no, "LEFT_MAX")?;
+            let right_max = parse_column::<usize>(&record, 1, line_no, "RIGHT_MAX")?;
+            break (left_max, right_max);
+        };
+        let header_lines = line_no;
+        let body_start = pos;
+
+        // 行の途中で分割しないように、チャンクの境界は改行の直後にする
+        let threads = threads.max(1);
+        let mut bounds: Vec<usize> = vec![body_start];
+        for i in 1..threads {
+            let mut bound = body_start + (bytes.len() - body_start) * i / threads;
+            bound = bound.max(*bounds.last().unwrap());
+            if bound > body_start && bound < bytes.len() && bytes[bound - 1] != b'\n' {
+                bound = (line_end(&bytes, bound) + 1).min(bytes.len());
+            }
+            bounds.push(bound);
+        }
+        bounds.push(bytes.len());
+
+        let size = left_max * right_max;
+        let bytes  : Arc<Vec<u8>>        = Arc::new(bytes);
+        let matrix : Arc<Vec<AtomicI16>> = Arc::new((0..size).map(|_| AtomicI16::new(0)).collect());
+        let filled : Arc<Vec<AtomicU64>> = Arc::new((0..((size + 63) / 64)).map(|_| AtomicU64::new(0)).collect());
+        let handles: Vec<_> = bounds.windows(2).map(|w| {
+            let (start, end) = (w[0], w[1]);
+            let bytes  = Arc::clone(&bytes);
+            let matrix = Arc::clone(&matrix);
+            let filled = Arc::clone(&filled);
+            thread::spawn(move || parse_chunk(&bytes[start..end], &matrix, &filled, left_max, right_max))
+        }).collect();
+        // 先に失敗したチャンクのエラーを返す
+        let mut error: Option<(usize, usize, String)> = None;
+        for (chunk, handle) in handles.into_iter().enumerate() {
+            let result = handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+            if let (Err((line, message)), None) = (result, &error) {
+                error = Some((chunk, line, message));
+            }
+        }
+        if let Some((chunk, line, message)) = error {
+            // 行番号はエラーの場合だけ、前のチャンクの改行を数えて求める
+            let lines_before = bytes[body_start..bounds[chunk]].iter().filter(|&&b| b == b'\n').count();
+            return Err(Error::parse("matrix.def", (header_lines + lines_before as u64) + line as u64 + 1, message));
+        }
+
+        // すべての組のコストが定義されているか
+        for (i, bits) in filled.iter().enumerate() {
+            let bits = bits.load(Ordering::Relaxed);
+            let valid = (size - i * 64).min(64);
+            let mask = if valid == 64 { u64::max_value() } else { (1 << valid) - 1 };
+            if bits & mask != mask {
+                let idx = i * 64 + (!bits & mask).trailing_zeros() as usize;
+                return Err(Error::InvalidInput(format!(
+                    "matrix.defに連接コストが定義されていない文脈IDの組があります。(left_id={}, right_id={})",
+                    idx / right_max, idx % right_max,
+                )));
+            }
+        }
+
+        let matrix = Arc::try_unwrap(matrix).unwrap_or_else(|_| unreachable!());
+        let matrix: Vec<i16> = matrix.into_iter().map(AtomicI16::into_inner).collect();
+        Ok(MatrixBuilder { matrix, left_max, right_max, encoding: MatrixEncoding::Dense })
     }
 
     pub fn get_matrix(&self) -> &[i16] {
@@ -23,12 +212,94 @@ impl MatrixBuilder {
         self.right_max
     }
 
+    /// 辞書ファイルに書き込むときの形式を指定する(デフォルトはDense)
+    ///
+    /// # Arguments
+    ///
+    /// * `encoding` - 連接コスト表の形式
+    pub fn set_encoding(&mut self, encoding: MatrixEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn get_encoding(&self) -> MatrixEncoding {
+        self.encoding
+    }
+
+    /// set_encodingで指定した形式に変換する
+    pub fn encode(&self) -> EncodedMatrix {
+        match self.encoding {
+            MatrixEncoding::Dense  => EncodedMatrix {
+                encoding: MatrixEncoding::Dense, costs: self.matrix.clone(), index: Vec::new(), width: self.right_max, default: 0,
+            },
+            MatrixEncoding::Dedup  => self.encode_dedup(),
+            MatrixEncoding::Sparse => self.encode_sparse(),
+        }
+    }
+
+    /// 同じ内容の行・列をまとめた表に変換する
+    fn encode_dedup(&self) -> EncodedMatrix {
+        let mut index: Vec<u32> = Vec::with_capacity(self.left_max + self.right_max);
+        // 同じ内容の行をまとめる
+        let mut rows: Vec<&[i16]> = Vec::new();
+        let mut row_ids: HashMap<&[i16], u32> = HashMap::new();
+        for left_id in 0..self.left_max {
+            let row = &self.matrix[(left_id * self.right_max)..((left_id + 1) * self.right_max)];
+            let row_id = *row_ids.entry(row).or_insert_with(|| {
+                rows.push(row);
+                (rows.len() - 1) as u32
+            });
+            index.push(row_id);
+        }
+        // まとめた行の表で、同じ内容の列をまとめる
+        let mut cols: Vec<usize> = Vec::new(); // まとめた列の元の右文脈ID
+        let mut col_ids: HashMap<Vec<i16>, u32> = HashMap::new();
+        for right_id in 0..self.right_max {
+            let col: Vec<i16> = rows.iter().map(|row| row[right_id]).collect();
+            let col_id = *col_ids.entry(col).or_insert_with(|| {
+                cols.push(right_id);
+                (cols.len() - 1) as u32
+            });
+            index.push(col_id);
+        }
+        let mut costs: Vec<i16> = Vec::with_capacity(rows.len() * cols.len());
+        for row in rows.iter() {
+            costs.extend(cols.iter().map(|&right_id| row[right_id]));
+        }
+        EncodedMatrix { encoding: MatrixEncoding::Dedup, costs, index, width: cols.len(), default: 0 }
+    }
+
+    /// 最も多く現れるコストをデフォルトとした疎な表に変換する
+    fn encode_sparse(&self) -> EncodedMatrix {
+        let mut counts: Vec<usize> = vec![0; 1 << 16];
+        for &cost in self.matrix.iter() {
+            counts[cost as u16 as usize] += 1;
+        }
+        let default = (0..counts.len()).max_by_key(|&i| (counts[i], Reverse(i))).unwrap() as u16 as i16;
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(self.left_max + 1);
+        let mut right_ids: Vec<u32> = Vec::new();
+        let mut costs: Vec<i16> = Vec::new();
+        offsets.push(0);
+        for left_id in 0..self.left_max {
+            for right_id in 0..self.right_max {
+                let cost = self.get(left_id, right_id);
+                if cost != default {
+                    right_ids.push(right_id as u32);
+                    costs.push(cost);
+                }
+            }
+            offsets.push(costs.len() as u32);
+        }
+        offsets.extend(right_ids);
+        EncodedMatrix { encoding: MatrixEncoding::Sparse, costs, index: offsets, width: self.right_max, default }
+    }
+
     /// 連接コスト表からコストを引く
     ///
     /// # Arguments
     ///
-    /// * `left_id`  - 左文脈ID
-    /// * `right_id` - 右文脈ID
+    /// * `left_id`  - matrix.defの1列目(左側の単語の右文脈ID)
+    /// * `right_id` - matrix.defの2列目(右側の単語の左文脈ID)
     pub fn get(&self, left_id: usize, right_id: usize) -> i16 {
         self.matrix[(left_id * self.right_max) + right_id]
     }
@@ -37,12 +308,84 @@ impl MatrixBuilder {
     ///
     /// # Arguments
     ///
-    /// * `left_id`  - 左文脈ID
-    /// * `right_id` - 右文脈ID
+    /// * `left_id`  - matrix.defの1列目(左側の単語の右文脈ID)
+    /// * `right_id` - matrix.defの2列目(右側の単語の左文脈ID)
     /// * `cost`     - 連接コスト
-    pub fn set(&mut self, left_id: usize, right_id: usize, cost: i16) {
+    pub fn set(&mut self, left_id: usize, right_id: usize, cost: i16) -> Result<()> {
+        if left_id >= self.left_max || right_id >= self.right_max {
+            return Err(Error::InvalidMatrixId { left_id, right_id, left_max: self.left_max, right_max: self.right_max });
+        }
         self.matrix[(left_id * self.right_max) + right_id] = cost;
+        Ok(())
+    }
+}
+
+/// matrix.defの1行のidx番目のカラムを数値に変換する
+fn parse_column<N: std::str::FromStr>(record: &[&str], idx: usize, line_no: u64, name: &str) -> Result<N> {
+    record[idx].parse::<N>().map_err(|_| Error::parse("matrix.def", line_no, format!("{}が不正です。({})", name, record[idx])))
+}
+
+/// posから始まる行の終端(改行の位置、改行がなければbytesの長さ)
+fn line_end(bytes: &[u8], pos: usize) -> usize {
+    bytes[pos..].iter().position(|&b| b == b'\n').map(|i| pos + i).unwrap_or_else(|| bytes.len())
+}
+
+/// matrix.defのチャンクを解析して連接コスト表にセットする
+/// エラーの場合はチャンクの中での行番号(0始まり)とエラーの内容を返す
+///
+/// # Arguments
+///
+/// * `chunk`     - matrix.defの2行目以降の一部(行の途中で分割されていないこと)
+/// * `matrix`    - 連接コスト表
+/// * `filled`    - コストをセットした組のビット列
+/// * `left_max`  - LEFT_MAX
+/// * `right_max` - RIGHT_MAX
+fn parse_chunk(chunk: &[u8], matrix: &[AtomicI16], filled: &[AtomicU64], left_max: usize, right_max: usize) -> std::result::Result<(), (usize, String)> {
+    for (i, line) in chunk.split(|&b| b == b'\n').enumerate() {
+        let mut fields = line.split(|&b| b == b' ' || b == b'\t' || b == b'\r').filter(|f| !f.is_empty());
+        let (left_id, right_id, cost) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (None, _, _, _) => continue,
+            (Some(l), Some(r), Some(c), None) => (l, r, c),
+            _ => return Err((i, "LEFT_ID RIGHT_ID COST の形式で指定してください。".to_string())),
+        };
+        let column = |field: &[u8], name: &str, min: i64, max: i64| match parse_int(field) {
+            Some(n) if min <= n && n <= max => Ok(n),
+            _ => Err((i, format!("{}が不正です。({})", name, String::from_utf8_lossy(field)))),
+        };
+        let left_id  = column(left_id , "LEFT_ID" , 0, i64::from(u32::max_value()))? as usize;
+        let right_id = column(right_id, "RIGHT_ID", 0, i64::from(u32::max_value()))? as usize;
+        let cost     = column(cost    , "COST"    , i64::from(i16::min_value()), i64::from(i16::max_value()))? as i16;
+        if left_id >= left_max || right_id >= right_max {
+            return Err((i, Error::InvalidMatrixId { left_id, right_id, left_max, right_max }.to_string()));
+        }
+        let idx = (left_id * right_max) + right_id;
+        matrix[idx].store(cost, Ordering::Relaxed);
+        let bit = 1 << (idx % 64);
+        if filled[idx / 64].fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+            return Err((i, format!("同じ文脈IDの組の連接コストが複数あります。(left_id={}, right_id={})", left_id, right_id)));
+        }
+    }
+    Ok(())
+}
+
+/// 10進数の整数(先頭に-があれば負数)をバイト列のまま数値に変換する
+/// 桁数が多すぎる場合はNone
+fn parse_int(field: &[u8]) -> Option<i64> {
+    let (negative, digits) = match field.first() {
+        Some(b'-') => (true, &field[1..]),
+        _          => (false, field),
+    };
+    if digits.is_empty() || digits.len() > 18 {
+        return None;
+    }
+    let mut n: i64 = 0;
+    for &b in digits {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        n = n * 10 + i64::from(b - b'0');
     }
+    Some(if negative { -n } else { n })
 }
 
 
@@ -55,7 +398,7 @@ mod tests {
         let mut matrix = MatrixBuilder::new(100, 100);
         for l in 0i16..100 {
             for r in 0i16..100 {
-                matrix.set(l as usize, r as usize, l * 100 + r);
+                matrix.set(l as usize, r as usize, l * 100 + r).unwrap();
             }
         }
 
@@ -65,4 +408,73 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn test_matrix_out_of_range() {
+        let mut matrix = MatrixBuilder::new(2, 3);
+        assert!(matrix.set(1, 2, 10).is_ok());
+        assert!(matrix.set(2, 0, 10).is_err());
+        assert!(matrix.set(0, 3, 10).is_err());
+    }
+
+    #[test]
+    fn test_from_matrix_def() {
+        let matrix = MatrixBuilder::from_matrix_def("2 3\n0 0 -10\n1 2 300\n".as_bytes()).unwrap();
+        assert_eq!((2, 3), (matrix.get_left_max(), matrix.get_right_max()));
+        assert_eq!(-10, matrix.get(0, 0));
+        assert_eq!(300, matrix.get(1, 2));
+        assert_eq!(0  , matrix.get(1, 1));
+
+        // 不正な行は行番号付きのエラー
+        let line_of = |text: &str| match MatrixBuilder::from_matrix_def(text.as_bytes()) {
+            Err(Error::Parse { line, .. }) => line,
+            _ => 0,
+        };
+        assert_eq!(3, line_of("2 3\n0 0 -10\n1 2 abc\n"));
+        assert_eq!(2, line_of("2 3\n2 0 10\n"));
+        assert_eq!(1, line_of("2\n"));
+        assert_eq!(1, line_of(""));
+    }
+
+    #[test]
+    fn test_from_matrix_def_bytes() {
+        let mut text = String::from("\n3 4\n");
+        for l in 0..3 {
+            for r in (0..4).rev() {
+                text.push_str(&format!("{} {}\t{}\r\n", l, r, l as i16 * 100 - r as i16));
+            }
+        }
+        // スレッド数によらず同じ結果になる
+        for &threads in [1, 2, 5, 100].iter() {
+            let matrix = MatrixBuilder::from_matrix_def_bytes(text.clone().into_bytes(), threads).unwrap();
+            assert_eq!((3, 4), (matrix.get_left_max(), matrix.get_right_max()));
+            for l in 0..3 {
+                for r in 0..4 {
+                    assert_eq!(l as i16 * 100 - r as i16, matrix.get(l, r));
+                }
+            }
+        }
+
+        // 不正な行はどのチャンクにあっても行番号付きのエラー
+        let line_of = |text: &str, threads: usize| match MatrixBuilder::from_matrix_def_bytes(text.as_bytes().to_vec(), threads) {
+            Err(Error::Parse { line, .. }) => line,
+            _ => 0,
+        };
+        let valid = "1 2\n0 0 1\n0 1 2\n";
+        for &threads in [1, 3].iter() {
+            assert_eq!(3, line_of("1 2\n0 0 1\n0 1 x\n", threads));
+            assert_eq!(3, line_of("1 2\n0 0 1\n0 1 40000\n", threads));
+            assert_eq!(3, line_of("1 2\n0 0 1\n0 2 1\n", threads));
+            assert_eq!(3, line_of("1 2\n0 0 1\n0 1\n", threads));
+            // 同じ組が複数回
+            assert_eq!(4, line_of(&format!("{}0 1 3\n", valid), threads));
+            assert_eq!(1, line_of("1\n", threads));
+            assert_eq!(1, line_of("", threads));
+            assert!(MatrixBuilder::from_matrix_def_bytes(valid.as_bytes().to_vec(), threads).is_ok());
+        }
+        // 定義されていない組がある
+        match MatrixBuilder::from_matrix_def_bytes(b"2 2\n0 0 1\n0 1 1\n1 1 1\n".to_vec(), 2) {
+            Err(Error::InvalidInput(message)) => assert!(message.contains("left_id=1, right_id=0")),
+            r => panic!("{:?}", r.map(|m| m.get_matrix().to_vec())),
+        }
+    }
 }
@@ -1,11 +1,49 @@
 use crate::dictionary::bit_cache::BitCache;
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 struct Node<T> {
-    key   : u8,
+    key   : u32, // バイト単位のダブル配列ではバイト、文字単位のダブル配列では文字のコード
     values: Vec<T>,
     nexts : Vec<Node<T>>,
 }
 
+/// data_arrの最大長(data_offsetsにu32で格納するため)
+const MAX_DATA_LEN: usize = u32::max_value() as usize;
+/// バイト単位のダブル配列で値のノードに遷移するkey(utf8に現れないバイト)
+pub const BYTE_VALUE_KEY: u32 = 255;
+/// 文字単位のダブル配列で値のノードに遷移するkey(文字のコードは1から振る)
+pub const CHAR_VALUE_KEY: u32 = 0;
+
+/// ダブル配列を構成する配列(base_arr, check_arr, data_arr, data_offsets)
+pub type DoubleArrayParts<T> = (Vec<u32>, Vec<u32>, Vec<T>, Vec<u32>);
+
+/// 文字単位のダブル配列で使う、文字とコードの対応表
+/// コードは辞書での出現回数の多い文字から順に1, 2, ...と振る(0は値のノード)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharCodes {
+    pub char_codes: Vec<u32>, // コードポイント -> コード(辞書に現れない文字は0)
+    pub code_chars: Vec<u32>, // コード -> コードポイント(code_chars[0]は使わない)
+}
+
+impl CharCodes {
+    /// 文字のコードを取得する。辞書に現れない文字の場合はNone
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - 文字
+    pub fn get(&self, c: char) -> Option<u32> {
+        match self.char_codes.get(c as usize) {
+            Some(&code) if code != 0 => Some(code),
+            _                        => None,
+        }
+    }
+}
+
 pub struct Trie<T: Copy> {
     root: Node<T>,
     len: usize,
@@ -20,8 +58,7 @@ impl<T: Copy> Trie<T> {
     }
 
     /// trieにノードを追加する
-    /// 一つのkeyにつき256個までの値を登録できる
-    /// 超えた場合はpanic
+    /// 一つのkeyに複数の値を登録できる
     ///
     /// # Arguments
     ///
@@ -30,21 +67,21 @@ impl<T: Copy> Trie<T> {
     pub fn set(&mut self, key: &str, value: T) {
         let mut node = &mut self.root;
         for &k in key.as_bytes() {
-            match node.nexts.binary_search_by(|probe| probe.key.cmp(&k)) {
-                Ok(i) => {
-                    node = &mut node.nexts[i];
-                },
-                Err(i) => {
-                    node.nexts.insert(i, Node { key: k, values: Vec::new(), nexts: Vec::new() });
-                    node = &mut node.nexts[i];
-                }
-            }
+            node = Self::child_or_insert(node, k as u32);
         }
-        if node.values.len() < 256 {
-            self.len += 1;
-            node.values.push(value);
-        } else {
-            panic!("登録できる値は1つのkeyに256個までです。")
+        self.len += 1;
+        node.values.push(value);
+    }
+
+    /// keyの子ノードを返す。なければ追加する
+    /// 子ノードはkeyの昇順に並べておく
+    fn child_or_insert(node: &mut Node<T>, key: u32) -> &mut Node<T> {
+        match node.nexts.binary_search_by(|probe| probe.key.cmp(&key)) {
+            Ok(i) => &mut node.nexts[i],
+            Err(i) => {
+                node.nexts.insert(i, Node { key, values: Vec::new(), nexts: Vec::new() });
+                &mut node.nexts[i]
+            }
         }
     }
 
@@ -57,7 +94,7 @@ impl<T: Copy> Trie<T> {
     pub fn get(&self, key: &str) -> Option<&[T]> {
         let mut node = &self.root;
         for &k in key.as_bytes() {
-            match node.nexts.binary_search_by(|probe| probe.key.cmp(&k)) {
+            match node.nexts.binary_search_by(|probe| probe.key.cmp(&(k as u32))) {
                 Ok(i) => {
                     node = &node.nexts[i];
                 },
@@ -74,40 +111,107 @@ impl<T: Copy> Trie<T> {
     }
 
 
+    /// トライ木をダブル配列に変換する
+    /// (base_arr, check_arr, data_arr, data_offsets)を返す
+    /// valueノードのbaseにはkeyの番号eを格納し、keyの値は data_arr[data_offsets[e]..data_offsets[e + 1]] に格納する
+    pub fn to_double_array(self) -> Result<DoubleArrayParts<T>> {
+        Self::build_double_array(self.root, self.len, u8::max_value() as usize + 1, BYTE_VALUE_KEY)
+    }
+
+    /// トライ木を文字単位のダブル配列に変換する
+    /// 文字は辞書での出現回数の多い順に振ったコードで遷移するので、
+    /// バイト単位のダブル配列より遷移の回数が少なく、よく使う文字の子ノードが密に配置される
+    /// (base_arr, check_arr, data_arr, data_offsets)と文字とコードの対応表を返す
+    /// valueノードはkey=CHAR_VALUE_KEYで遷移した先にあり、値の格納方法はto_double_arrayと同じ
+    pub fn to_char_double_array(self) -> Result<(DoubleArrayParts<T>, CharCodes)> {
+        // 登録されている(key, values)を取り出して、文字の出現回数を数える
+        let mut entries: Vec<(String, Vec<T>)> = Vec::with_capacity(self.len);
+        let mut key: Vec<u8> = Vec::new();
+        Self::collect_entries(self.root, &mut key, &mut entries)?;
+        let mut counts: HashMap<char, usize> = HashMap::new();
+        for (key, values) in entries.iter() {
+            for c in key.chars() {
+                *counts.entry(c).or_insert(0) += values.len();
+            }
+        }
+        // 出現回数の多い順(同じ場合はコードポイントの順)にコードを振る
+        let mut chars: Vec<(char, usize)> = counts.into_iter().collect();
+        chars.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let max_char = chars.iter().map(|&(c, _)| c as usize).max().unwrap_or(0);
+        let mut char_codes = CharCodes { char_codes: vec![0; max_char + 1], code_chars: vec![0] };
+        for (c, _) in chars {
+            char_codes.char_codes[c as usize] = char_codes.code_chars.len() as u32;
+            char_codes.code_chars.push(c as u32);
+        }
+
+        // コードをkeyにしたトライ木を作り直す
+        let mut root: Node<T> = Node { key: 0, values: Vec::new(), nexts: Vec::new() };
+        for (key, values) in entries {
+            let mut node = &mut root;
+            for c in key.chars() {
+                node = Self::child_or_insert(node, char_codes.char_codes[c as usize]);
+            }
+            node.values = values;
+        }
+        let parts = Self::build_double_array(root, self.len, char_codes.code_chars.len(), CHAR_VALUE_KEY)?;
+        Ok((parts, char_codes))
+    }
+
+    /// ノード以下に登録されている(key, values)を取り出す
+    /// keyはutf8の文字列でなければならない
+    fn collect_entries(node: Node<T>, key: &mut Vec<u8>, entries: &mut Vec<(String, Vec<T>)>) -> Result<()> {
+        if !node.values.is_empty() {
+            let key = String::from_utf8(key.clone()).map_err(|_| Error::DoubleArray(format!("keyがutf8ではありません。({:?})", key)))?;
+            entries.push((key, node.values));
+        }
+        for next in node.nexts {
+            key.push(next.key as u8);
+            Self::collect_entries(next, key, entries)?;
+            key.pop();
+        }
+        Ok(())
+    }
+
     /// トライ木をダブル配列に変換する
     ///
     /// # Arguments
     ///
-    /// * `len` - ダブル配列の初期サイズ
-    pub fn to_double_array(self) -> (Vec<u32>, Vec<u32>, Vec<T>) {
-        let max_key = u8::max_value() as usize + 1;      // keyが取りうる値のパターン
-        let mut len = if max_key > (4 * self.len) { max_key } else { 4 * self.len };
+    /// * `root`      - トライ木のルート
+    /// * `count`     - 登録されている値の数
+    /// * `max_key`   - keyが取りうる値のパターン(値のノードのkeyを含む)
+    /// * `value_key` - 値のノードに遷移するkey
+    fn build_double_array(root: Node<T>, count: usize, max_key: usize, value_key: u32) -> Result<DoubleArrayParts<T>> {
+        let mut len = if max_key > (4 * count) { max_key } else { 4 * count };
         let mut base_arr: Vec<u32>  = vec![0; len];
         let mut check_arr: Vec<u32> = vec![0; len];
-        let mut data_arr: Vec<T>    = Vec::with_capacity(self.len);
-        let mut bit_cache: BitCache = BitCache::new();
+        let mut data_arr: Vec<T>    = Vec::with_capacity(count);
+        let mut data_offsets: Vec<u32> = vec![0];
+        let mut bit_cache: BitCache = BitCache::with_len(len);
         bit_cache.set(0);
         bit_cache.set(1);
-        let mut stack: Vec<(usize, Node<T>)> = Vec::with_capacity(self.len);
-        if !self.root.nexts.is_empty() {
-            stack.push((1, self.root));
-        }
+        let mut stack: Vec<(usize, Node<T>)> = Vec::with_capacity(count);
+        stack.push((1, root));
 
         while !stack.is_empty() {
             let (curr_idx, mut node) = stack.pop().unwrap();
+            if node.nexts.is_empty() && node.values.is_empty() {
+                // 空のトライ木のルートと、並列に構築する部分木の位置(to_double_array_parallel)は配置するノードがない
+                continue;
+            }
             bit_cache.update_start();
 
             // base値を探索・セット
             if !node.values.is_empty() {
-                // valuesが存在する場合はkey=255のノードとして計算する
-                node.nexts.push(Node { key: u8::max_value(), values: vec![], nexts: vec![] });
+                // valuesが存在する場合はkey=value_keyのノードとして計算する(nextsはkeyの昇順に保つ)
+                let i = if value_key == 0 { 0 } else { node.nexts.len() };
+                node.nexts.insert(i, Node { key: value_key, values: vec![], nexts: vec![] });
             }
 
-            let base: usize = Self::find_base(&node.nexts, &bit_cache);
+            let base: usize = Self::find_base(&node.nexts, &bit_cache)?;
             base_arr[curr_idx] = base as u32;
 
             // 配列の長さが足りなければ配列を拡張
-            if base + max_key >= len {
+            while base + max_key >= len {
                 len = len * 2;
                 base_arr.resize(len, 0);
                 check_arr.resize(len, 0);
@@ -118,12 +222,16 @@ impl<T: Copy> Trie<T> {
                 let i = base + (n.key as usize);
                 bit_cache.set(i);
                 check_arr[i] = curr_idx as u32;
-                if n.key == u8::max_value() {
+                if n.key == value_key {
+                    if data_arr.len() + node.values.len() > MAX_DATA_LEN {
+                        return Err(Error::DoubleArray(format!("登録できる値は{}個までです。", MAX_DATA_LEN)));
+                    }
                     // valueノードの登録
-                    // baseには「24bit: dataのindex, 8bit: 長さ」を格納する
-                    base_arr[i]  = ((data_arr.len() << 8) | node.values.len() & 0b11111111) as u32;
-                    // dataには末尾にvaluesを追加する
+                    // baseにはkeyの番号を格納する
+                    base_arr[i] = (data_offsets.len() - 1) as u32;
+                    // dataには末尾にvaluesを追加して、終了位置をdata_offsetsに追加する
                     data_arr.extend_from_slice(&node.values);
+                    data_offsets.push(data_arr.len() as u32);
                 } else {
                     // 通常ノードの登録
                     stack.push((i, n));
@@ -132,13 +240,14 @@ impl<T: Copy> Trie<T> {
         }
 
         // 配列のりサイズ
+        // 配列の長さは最低でも256にする(DictionarySet::newでダブル配列のサイズとして検証する)
         let new_len = match bit_cache.last_index_of_one() {
             None          => max_key,
             Some(new_len) => new_len + max_key,
-        };
+        }.max(u8::max_value() as usize + 1);
         base_arr.resize(new_len, 0);
         check_arr.resize(new_len, 0);
-        (base_arr, check_arr, data_arr)
+        Ok((base_arr, check_arr, data_arr, data_offsets))
     }
 
     /// 新しいbase値を探索するメソッド
@@ -148,34 +257,180 @@ impl<T: Copy> Trie<T> {
     /// * `nodes`     - 追加対象のノード
     /// * `bit_cache` - BitCacheのインスタンス
     /// * `with_zero` - key=0のノードも考慮してbase値を探す
-    fn find_base(nodes: &[Node<T>], bit_cache: &BitCache) -> usize {
-        if nodes.is_empty() {
-                panic!("探索すべきノードがありません");
+    fn find_base(nodes: &[Node<T>], bit_cache: &BitCache) -> Result<usize> {
+        find_base_of(nodes.iter().map(|n| n.key as usize), bit_cache)
+    }
+}
+
+impl<T: Copy + Send + 'static> Trie<T> {
+    /// トライ木を複数のスレッドでダブル配列に変換する
+    /// keyの先頭の1文字ごとの部分木を、それぞれのスレッドで別々のダブル配列に変換してから、
+    /// 重ならない領域に並べて1つのダブル配列にする
+    /// 返す配列の形式はto_double_arrayと同じ。ただし、ノードの配置と値の順番はto_double_arrayと異なる
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - スレッド数
+    pub fn to_double_array_parallel(self, threads: usize) -> Result<DoubleArrayParts<T>> {
+        if threads <= 1 {
+            return self.to_double_array();
+        }
+        let max_key = u8::max_value() as usize + 1;
+
+        // 先頭の1文字より下の部分木を取り出す。取り出した位置には空のノードを残す
+        let mut root = self.root;
+        let mut tasks: Vec<(Vec<u8>, Node<T>)> = Vec::new();
+        let mut prefix: Vec<u8> = Vec::new();
+        for next in root.nexts.iter_mut() {
+            prefix.push(next.key as u8);
+            Self::take_subtrees(next, utf8_len(next.key as u8) - 1, &mut prefix, &mut tasks);
+            prefix.pop();
+        }
+
+        // 先頭の1文字までの部分(空のkeyの値を含む)を変換する
+        let (mut base_arr, mut check_arr, mut data_arr, mut data_offsets) = Self::build_double_array(root, 1, max_key, BYTE_VALUE_KEY)?;
+        let subtree_indices: Vec<usize> = tasks.iter().map(|(prefix, _)| {
+            prefix.iter().fold(1, |idx, &byte| base_arr[idx] as usize + byte as usize)
+        }).collect();
+
+        // 部分木を空いているスレッドから順に取って変換する
+        let task_len = tasks.len();
+        let queue: Vec<(usize, Node<T>)> = tasks.into_iter().map(|(_, node)| node).enumerate().collect();
+        let queue = Arc::new(Mutex::new(queue));
+        let handles: Vec<_> = (0..threads.min(task_len)).map(|_| {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                let mut results: Vec<(usize, Result<DoubleArrayParts<T>>)> = Vec::new();
+                loop {
+                    let task = queue.lock().unwrap().pop();
+                    match task {
+                        Some((i, node)) => {
+                            // 配列を最初から十分な大きさで確保しておくほうが速い
+                            let count = Self::count_values(&node);
+                            results.push((i, Self::build_double_array(node, count, max_key, BYTE_VALUE_KEY)));
+                        },
+                        None => return results,
+                    }
+                }
+            })
+        }).collect();
+        let mut subtrees: Vec<Option<DoubleArrayParts<T>>> = (0..task_len).map(|_| None).collect();
+        for handle in handles {
+            for (i, result) in handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)) {
+                subtrees[i] = Some(result?);
+            }
         }
-        let first_key = nodes[0].key as usize;
-        let mut offset = 0;
-        'outer: loop {
-            let empty_idx = bit_cache.find_empty_idx(offset);
-            let new_base = empty_idx - first_key;
-            if empty_idx < 256 {
-                panic!("empty_idx={}, first_key={}", empty_idx, first_key);
+
+        // 部分木のダブル配列を、使っているindexが重ならないように後ろに並べる
+        // 部分木の中のノードのbase, checkは領域の先頭の分だけずらし、値のノードのbaseはkeyの番号の分だけずらす
+        // 部分木のルート(部分木のindex 1)は、先頭の1文字までの部分に残した空のノードの位置になる
+        // 他の領域から遷移してきても、checkが遷移元のindexと一致することはないので、
+        // 部分木の配列の先頭と末尾の使っていないindexは前後の領域と重ねてよい
+        let mut used_len = used_len_of(&check_arr);
+        for (subtree, root_idx) in subtrees.into_iter().zip(subtree_indices) {
+            let (sub_base, sub_check, sub_data, sub_offsets) = subtree.unwrap();
+            let first_used = sub_check.iter().position(|&check| check != 0).unwrap_or(0);
+            let offset = used_len.saturating_sub(first_used);
+            used_len = offset + used_len_of(&sub_check);
+            let entry_offset = data_offsets.len() - 1;
+            if data_arr.len() + sub_data.len() > MAX_DATA_LEN {
+                return Err(Error::DoubleArray(format!("登録できる値は{}個までです。", MAX_DATA_LEN)));
             }
-            // すべてのノードが重複せずに配置できるかをチェック
-            'inner: for next in nodes {
-                if bit_cache.get(new_base + next.key as usize) != 0 {
-                    // 空じゃなかった場合はnew_baseを探すとこからやり直し
-                    offset += 1;
-                    continue 'outer;
+            base_arr[root_idx] = (sub_base[1] as usize + offset) as u32;
+            base_arr.resize(used_len.max(base_arr.len()), 0);
+            check_arr.resize(used_len.max(check_arr.len()), 0);
+            for (i, (&base, &check)) in sub_base.iter().zip(sub_check.iter()).enumerate().take(used_len - offset) {
+                if check == 0 {
+                    continue;
                 }
+                let parent = check as usize;
+                let is_value = sub_base[parent] as usize + BYTE_VALUE_KEY as usize == i;
+                base_arr[offset + i] = if is_value { base as usize + entry_offset } else { base as usize + offset } as u32;
+                check_arr[offset + i] = if parent == 1 { root_idx } else { parent + offset } as u32;
             }
-            return new_base;
+            let data_len = data_arr.len();
+            data_arr.extend_from_slice(&sub_data);
+            data_offsets.extend(sub_offsets[1..].iter().map(|&end| (end as usize + data_len) as u32));
+        }
+        base_arr.resize(used_len + max_key, 0);
+        check_arr.resize(used_len + max_key, 0);
+        Ok((base_arr, check_arr, data_arr, data_offsets))
+    }
+
+    /// ノード以下に登録されている値の数
+    fn count_values(node: &Node<T>) -> usize {
+        node.values.len() + node.nexts.iter().map(Self::count_values).sum::<usize>()
+    }
+
+    /// nodeからdepthバイト下のノードを部分木として取り出して、元の位置には空のノードを残す
+    fn take_subtrees(node: &mut Node<T>, depth: usize, prefix: &mut Vec<u8>, tasks: &mut Vec<(Vec<u8>, Node<T>)>) {
+        if depth == 0 {
+            let subtree = Node { key: node.key, values: mem::replace(&mut node.values, Vec::new()), nexts: mem::replace(&mut node.nexts, Vec::new()) };
+            tasks.push((prefix.clone(), subtree));
+            return;
+        }
+        for next in node.nexts.iter_mut() {
+            prefix.push(next.key as u8);
+            Self::take_subtrees(next, depth - 1, prefix, tasks);
+            prefix.pop();
         }
     }
 }
 
+/// 最後に使われているindex + 1(ルートのindex 1は常に使われているものとする)
+fn used_len_of(check_arr: &[u32]) -> usize {
+    check_arr.iter().rposition(|&check| check != 0).map_or(2, |i| (i + 1).max(2))
+}
+
+/// utf8の文字の先頭のバイトから、文字のバイト数を返す
+fn utf8_len(first: u8) -> usize {
+    if first < 0xC0 {
+        1
+    } else if first < 0xE0 {
+        2
+    } else if first < 0xF0 {
+        3
+    } else {
+        4
+    }
+}
+
+/// keysの子ノードをすべて空きindexに配置できるbase値を探索する
+/// keysは昇順に並んでいなければならない
+///
+/// # Arguments
+///
+/// * `keys`      - 追加対象のノードのkey
+/// * `bit_cache` - BitCacheのインスタンス
+pub(crate) fn find_base_of<I: Iterator<Item = usize> + Clone>(keys: I, bit_cache: &BitCache) -> Result<usize> {
+    let first_key = match keys.clone().next() {
+        Some(first_key) => first_key,
+        None            => return Err(Error::DoubleArray("探索すべきノードがありません".to_string())),
+    };
+    let mut offset = 0;
+    'outer: loop {
+        let empty_idx = bit_cache.find_empty_idx(offset);
+        if empty_idx < first_key {
+            // baseが負にならない位置まで進める
+            offset += first_key - empty_idx;
+            continue;
+        }
+        let new_base = empty_idx - first_key;
+        // すべてのノードが重複せずに配置できるかをチェック
+        'inner: for key in keys.clone() {
+            if bit_cache.get(new_base + key) != 0 {
+                // 空じゃなかった場合はnew_baseを探すとこからやり直し
+                offset += 1;
+                continue 'outer;
+            }
+        }
+        return Ok(new_base);
+    }
+}
+
 /// ダブル配列をデバッグ目的で表示するための関数
 #[allow(dead_code)]
-fn debug_double_array<T: std::fmt::Debug>(base_arr: &[u32], check_arr: &[u32], data_arr: &[T]) {
+fn debug_double_array<T: std::fmt::Debug>(base_arr: &[u32], check_arr: &[u32], data_arr: &[T], data_offsets: &[u32]) {
     println!("size: base={}, check={}, data={}", base_arr.len(), check_arr.len(), data_arr.len());
     println!("{:-10} | {:-10} | {:-10} |", "index", "base", "check");
     println!("{:-10} | {:-10} | {:-10} |", 0, base_arr[0], check_arr[0]);
@@ -183,15 +438,14 @@ fn debug_double_array<T: std::fmt::Debug>(base_arr: &[u32], check_arr: &[u32], d
     for i in 2..base_arr.len() {
         let check = check_arr[i];
         if  check != 0 {
-            if i == base_arr[check as usize] as usize {
-                let data_idx = (base_arr[i] >> 8) as usize;
-                let data_len = (base_arr[i] & 0b11111111) as usize;
+            if i == base_arr[check as usize] as usize + BYTE_VALUE_KEY as usize {
+                let entry = base_arr[i] as usize;
                 println!(
                     "{:-10} | {:-10} | {:-10} | {:?}",
                     i,
                     base_arr[i],
                     check_arr[i],
-                    &data_arr[data_idx..(data_idx + data_len)],
+                    &data_arr[(data_offsets[entry] as usize)..(data_offsets[entry + 1] as usize)],
                 );
             } else {
                 println!(
@@ -275,7 +529,7 @@ mod tests {
 
         // 探索開始位置 = 256。空きindex = 256
         // base値 = 空きindex - 先頭ノードのkey = 256 - 2 = 254
-        assert_eq!(254, Trie::find_base(&nodes, &bit_cache));
+        assert_eq!(254, Trie::find_base(&nodes, &bit_cache).unwrap());
 
         // 0 ~ 399, 500 ~ 999 を埋める
         (256..400).for_each(|i| bit_cache.set(i));
@@ -283,7 +537,7 @@ mod tests {
 
         // 探索開始位置 = 256。空きindex = 1000
         // base値 = 空きindex - 先頭ノードのkey = 1000 - 2 = 998
-        assert_eq!(998, Trie::find_base(&nodes, &bit_cache));
+        assert_eq!(998, Trie::find_base(&nodes, &bit_cache).unwrap());
 
         //1000..1002, 1003..1005, 1006..1255 を埋める
         (1000..1002).for_each(|i| bit_cache.set(i));
@@ -292,7 +546,7 @@ mod tests {
 
         // 探索開始位置 = 256。空きindex = 1002
         // base値 = 空きindex - 先頭ノードのkey = 1002 - 2 = 1000
-        assert_eq!(1000, Trie::find_base(&nodes, &bit_cache));
+        assert_eq!(1000, Trie::find_base(&nodes, &bit_cache).unwrap());
 
         // 400 ~ 500 を埋める
         (400..500).for_each(|i| bit_cache.set(i));
@@ -300,16 +554,28 @@ mod tests {
         // 探索開始位置=1216。空きindex = 1255
         // base値 = 空きindex - 先頭ノードのkey = 1255 - 2 = 1253
         bit_cache.update_start();
-        assert_eq!(1253, Trie::find_base(&nodes, &bit_cache));
+        assert_eq!(1253, Trie::find_base(&nodes, &bit_cache).unwrap());
     }
 
     #[test]
-    #[should_panic(expected = "探索すべきノードがありません")]
     fn test_find_base_2() {
         let nodes: Vec<Node<u32>> = vec![];
         let bit_cache = BitCache::new();
-        // nodesが空でwith_zero=falseの場合は、base値を求められないのでpanic
-        Trie::find_base(&nodes, &bit_cache);
+        // nodesが空でwith_zero=falseの場合は、base値を求められないのでエラー
+        assert!(Trie::<u32>::find_base(&nodes, &bit_cache).is_err());
+    }
+
+    #[test]
+    fn test_trie_many_values() {
+        let mut trie: Trie<u32> = Trie::new();
+        // 1つのkeyに256個以上の値を登録できる
+        for i in 0..1000 {
+            trie.set("abc", i);
+        }
+        trie.set("ab", 1000);
+        let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
+        assert_eq!((0..1000).collect::<Vec<u32>>(), find("abc", &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([1000], find("ab", &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
     }
 
     #[test]
@@ -326,26 +592,26 @@ mod tests {
         trie.set(&s3, 4);
         trie.set(&s4, 5);
         trie.set(&s5, 6);
-        let (base_arr, check_arr, data_arr) = trie.to_double_array();
-        // debug_double_array(&base_arr, &check_arr, &data_arr);
+        let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
+        // debug_double_array(&base_arr, &check_arr, &data_arr, &data_offsets);
         // 登録されていて、data_arrに値が存在するkeyは対応する値を返す
-        assert_eq!([1, 2], find(&s1, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([3], find(&s2, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([4], find(&s3, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([5], find(&s4, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([6], find(&s5, &base_arr, &check_arr, &data_arr).unwrap());
+        assert_eq!([1, 2], find(&s1, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([3], find(&s2, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([4], find(&s3, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([5], find(&s4, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([6], find(&s5, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
         // 登録されているが、data_arrに値が存在しないkeyはNoneを返す
-        assert_eq!(None, find("ab", &base_arr, &check_arr, &data_arr));
+        assert_eq!(None, find("ab", &base_arr, &check_arr, &data_arr, &data_offsets));
     }
 
     #[test]
     #[should_panic (expected = "(idx=1, base=0, check=0)から(idx=97, base=0, check=0)に遷移できません。(key=abc, i=0, byte=97)")]
     fn test_to_double_array_2() {
         let trie: Trie<u32> = Trie::new();
-        let (base_arr, check_arr, data_arr) = trie.to_double_array();
+        let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
         let s1 = String::from("abc");
         // 遷移できない場合はpanicする
-        find(&s1, &base_arr, &check_arr, &data_arr).unwrap();
+        find(&s1, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap();
     }
 
     #[test]
@@ -359,15 +625,94 @@ mod tests {
         trie.set(&s1, 2);
         trie.set(&s2, 3);
         trie.set(&s3, 4);
-        let (base_arr, check_arr, data_arr) = trie.to_double_array();
+        let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
         // 登録されていて、data_arrに値が存在するkeyは対応する値を返す
-        assert_eq!([1, 2], find(&s1, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([3], find(&s2, &base_arr, &check_arr, &data_arr).unwrap());
-        assert_eq!([4], find(&s3, &base_arr, &check_arr, &data_arr).unwrap());
+        assert_eq!([1, 2], find(&s1, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([3], find(&s2, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
+        assert_eq!([4], find(&s3, &base_arr, &check_arr, &data_arr, &data_offsets).unwrap());
         // 登録されているが、data_arrに値が存在しないkeyはNoneを返す
-        assert_eq!(None, find("お寿", &base_arr, &check_arr, &data_arr));
+        assert_eq!(None, find("お寿", &base_arr, &check_arr, &data_arr, &data_offsets));
+    }
+
+    #[test]
+    fn test_to_double_array_parallel() {
+        let keys: Vec<String> = vec![
+            "", "a", "ab", "abc", "b", "é", "éa", "お", "おすし", "お寿司", "寿司", "🍣", "🍣🍺", "🍺",
+        ].into_iter().map(String::from).chain((0..500).map(|i| format!("{}の{}", i % 7, i))).collect();
+        let build = || {
+            let mut trie: Trie<usize> = Trie::new();
+            for (i, key) in keys.iter().enumerate() {
+                trie.set(key, i);
+                trie.set(key, i + 1000);
+            }
+            trie
+        };
+        let (base_arr, check_arr, data_arr, data_offsets) = build().to_double_array_parallel(3).unwrap();
+        // すべてのkeyについてto_double_arrayと同じ値を返す
+        let trie = build();
+        for key in keys.iter() {
+            assert_eq!(trie.get(key), find(key, &base_arr, &check_arr, &data_arr, &data_offsets), "{}", key);
+        }
+        // 途中まで遷移できるが値がないkey
+        for key in ["おす", "お寿", "寿", "0の"].iter() {
+            assert_eq!(None, find(key, &base_arr, &check_arr, &data_arr, &data_offsets), "{}", key);
+        }
+        assert_eq!(keys.len() * 2, data_arr.len());
+        assert_eq!(keys.len() + 1, data_offsets.len());
+        // スレッド数によらず同じダブル配列になる
+        assert_eq!((base_arr, check_arr), {
+            let (base_arr, check_arr, _, _) = build().to_double_array_parallel(8).unwrap();
+            (base_arr, check_arr)
+        });
     }
 
+    #[test]
+    fn test_to_char_double_array() {
+        let mut trie: Trie<u32> = Trie::new();
+        let words = ["おすしとビール", "お寿司とビール", "🍣🍺", "お", "おすし", "a"];
+        for (i, w) in words.iter().enumerate() {
+            trie.set(w, i as u32);
+        }
+        trie.set("お", 10);
+        let ((base_arr, check_arr, data_arr, data_offsets), char_codes) = trie.to_char_double_array().unwrap();
+        // 出現回数の多い文字から順にコードが振られる
+        assert_eq!(Some(1), char_codes.get('お'));
+        assert_eq!('お' as u32, char_codes.code_chars[1]);
+        assert_eq!(None, char_codes.get('x'));
+        for (i, w) in words.iter().enumerate() {
+            let expected: Vec<u32> = if i == 3 { vec![3, 10] } else { vec![i as u32] };
+            assert_eq!(Some(&expected[..]), find_char(w, &base_arr, &check_arr, &data_arr, &data_offsets, &char_codes));
+        }
+        assert_eq!(None, find_char("お寿", &base_arr, &check_arr, &data_arr, &data_offsets, &char_codes));
+        assert_eq!(None, find_char("x", &base_arr, &check_arr, &data_arr, &data_offsets, &char_codes));
+        // 1文字が1回の遷移になるので、バイト単位のダブル配列より小さい
+        let mut trie: Trie<u32> = Trie::new();
+        for (i, w) in words.iter().enumerate() {
+            trie.set(w, i as u32);
+        }
+        let (byte_base_arr, _, _, _) = trie.to_double_array().unwrap();
+        assert!(base_arr.len() < byte_base_arr.len());
+    }
+
+    /// 文字単位のダブル配列から指定されたkeyを探索する関数
+    /// 遷移できない場合、値が存在しない場合はNoneを返す
+    fn find_char<'a, T>(key: &str, base_arr: &[u32], check_arr: &[u32], data_arr: &'a [T], data_offsets: &[u32], char_codes: &CharCodes) -> Option<&'a [T]> {
+        let mut idx = 1;
+        for c in key.chars() {
+            let next_idx = base_arr[idx] as usize + char_codes.get(c)? as usize;
+            if check_arr[next_idx] as usize != idx {
+                return None;
+            }
+            idx = next_idx;
+        }
+        let value_idx = base_arr[idx] as usize + CHAR_VALUE_KEY as usize;
+        if check_arr[value_idx] as usize == idx {
+            let entry = base_arr[value_idx] as usize;
+            Some(&data_arr[(data_offsets[entry] as usize)..(data_offsets[entry + 1] as usize)])
+        } else {
+            None
+        }
+    }
 
     /// ダブル配列から指定されたkeyを探索する関数
     /// 途中で遷移できなくなった場合はpanicする
@@ -380,7 +725,8 @@ mod tests {
     /// * `base_arr`  - base配列
     /// * `check_arr` - check配列
     /// * `data_arr`  - data配列
-    fn find<'a, T>(key: &str, base_arr: &[u32], check_arr: &[u32], data_arr: &'a [T]) -> Option<&'a [T]> {
+    /// * `data_offsets` - keyごとのdata配列の開始位置
+    fn find<'a, T>(key: &str, base_arr: &[u32], check_arr: &[u32], data_arr: &'a [T], data_offsets: &[u32]) -> Option<&'a [T]> {
         let mut idx  = 1;
         let mut base = base_arr[idx] as usize;
 
@@ -398,11 +744,10 @@ mod tests {
                 );
             }
         }
-        let value_idx = base + (u8::max_value() as usize);
+        let value_idx = base + BYTE_VALUE_KEY as usize;
         if check_arr[value_idx] as usize == idx {
-            let data_idx = (base_arr[value_idx] >> 8) as usize;
-            let data_len = (base_arr[value_idx] & 0b11111111) as usize;
-            Some(&data_arr[data_idx..(data_idx + data_len)])
+            let entry = base_arr[value_idx] as usize;
+            Some(&data_arr[(data_offsets[entry] as usize)..(data_offsets[entry + 1] as usize)])
         } else {
             None
         }
@@ -0,0 +1,136 @@
+use crate::token::Token;
+use crate::dictionary::{DictionaryParts, DictionarySet};
+use crate::dictionary::trie::Trie;
+use crate::dictionary::matrix_builder::MatrixBuilder;
+use crate::dictionary::feature_builder::{Feature, FeatureBuilder, join_feature, split_feature};
+use crate::dictionary::char_def::CharDefBuilder;
+
+use crate::error::{Error, Result};
+
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+
+/// 品詞とみなす素性の先頭のカラム数(品詞, 品詞細分類1, 品詞細分類2, 品詞細分類3)
+const POS_COLUMNS: usize = Feature::POS_COLUMNS;
+
+/// ユーザー辞書を構築する構造体
+/// ユーザー辞書はシステム辞書と同じ形式のファイルで、連接コスト表と未知語定義を持たない
+/// 文脈IDはシステム辞書の連接コスト表のものを使う
+pub struct UserDictBuilder {
+    left_max : usize,
+    right_max: usize,
+    pos_ids  : HashMap<String, (u16, u16)>,
+    trie     : Trie<Token>,
+    features : FeatureBuilder,
+}
+
+impl UserDictBuilder {
+    /// システム辞書の連接コスト表のサイズと、品詞ごとの文脈IDを読み込む
+    /// 同じ品詞に複数の文脈IDがある場合は、最も多くの単語で使われている文脈IDを使う
+    ///
+    /// # Arguments
+    ///
+    /// * `system_dict` - ユーザー辞書と組み合わせて使うシステム辞書
+    pub fn new(system_dict: &DictionarySet<Token>) -> UserDictBuilder {
+        let mut counts: HashMap<String, HashMap<(u16, u16), usize>> = HashMap::new();
+        for token in system_dict.entries().flat_map(|(_, tokens)| tokens.iter()) {
+            let columns = split_feature(system_dict.get_feature(token.feature_id as usize));
+            if columns.len() < POS_COLUMNS {
+                continue;
+            }
+            let pos = join_feature(columns.iter().take(POS_COLUMNS).map(|c| c.as_str()));
+            *counts.entry(pos).or_default().entry((token.left_id, token.right_id)).or_insert(0) += 1;
+        }
+        let pos_ids = counts.into_iter().map(|(pos, ids)| {
+            // 件数が同じ場合は文脈IDの小さい方
+            let (&ids, _) = ids.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))).unwrap();
+            (pos, ids)
+        }).collect();
+        let (left_max, right_max) = system_dict.get_matrix_size();
+        UserDictBuilder {
+            left_max,
+            right_max,
+            pos_ids,
+            trie    : Trie::new(),
+            features: FeatureBuilder::new(),
+        }
+    }
+
+    /// 単語を追加する
+    /// recordは "表層形,左文脈ID,右文脈ID,コスト,素性..." の形式
+    /// 左文脈ID・右文脈IDが空の場合は、素性の先頭の品詞からシステム辞書の文脈IDを求める
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - ユーザー辞書のcsvの1行
+    pub fn add(&mut self, record: &[&str]) -> Result<()> {
+        if record.len() < 4 {
+            return Err(invalid_record(record, "表層形,左文脈ID,右文脈ID,コスト が必要です。"));
+        }
+        let surface = record[0];
+        if surface.is_empty() {
+            return Err(invalid_record(record, "表層形が空です。"));
+        }
+        let cost = record[3].parse::<i16>().map_err(|_| invalid_record(record, "コストが不正です。"))?;
+        let (left_id, right_id) = match (record[1], record[2]) {
+            ("", "") => {
+                if record.len() < 4 + POS_COLUMNS {
+                    return Err(invalid_record(record, "文脈IDを省略する場合は素性に品詞が必要です。"));
+                }
+                let pos = join_feature(record[4..(4 + POS_COLUMNS)].iter().cloned());
+                match self.pos_ids.get(&pos) {
+                    Some(&ids) => ids,
+                    None       => return Err(invalid_record(record, &format!("品詞 {} がシステム辞書にありません。", pos))),
+                }
+            },
+            (left_id, right_id) => {
+                let left_id  = left_id.parse::<u16>().map_err(|_| invalid_record(record, "左文脈IDが不正です。"))?;
+                let right_id = right_id.parse::<u16>().map_err(|_| invalid_record(record, "右文脈IDが不正です。"))?;
+                // matrix.defの1列目は左側の単語の右文脈ID、2列目は右側の単語の左文脈ID
+                if right_id as usize >= self.left_max || left_id as usize >= self.right_max {
+                    return Err(invalid_record(record, &format!(
+                        "文脈IDがシステム辞書の連接コスト表の範囲外です。(left_max={}, right_max={})", self.left_max, self.right_max
+                    )));
+                }
+                (left_id, right_id)
+            },
+        };
+        let token = Token::new(left_id, right_id, cost, self.features.push(&join_feature(record[4..].iter().cloned())));
+        self.trie.set(surface, token);
+        Ok(())
+    }
+
+    /// ユーザー辞書をファイルに書き込む
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - 出力するファイル
+    pub fn serialize(self, output_path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(output_path)?);
+        self.write(&mut f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    /// ユーザー辞書をバイト列としてwriterに書き込む
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - 書き込み先
+    pub fn write<W: Write>(self, writer: &mut W) -> Result<()> {
+        let (base_arr, check_arr, data_arr, data_offsets) = self.trie.to_double_array()?;
+        let matrix = MatrixBuilder::new(0, 0);
+        let char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+        let parts = DictionaryParts {
+            base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+            matrix: &matrix, features: &self.features, char_def: &char_def,
+        };
+        DictionarySet::write(&parts, writer)
+    }
+}
+
+fn invalid_record(record: &[&str], msg: &str) -> Error {
+    Error::InvalidInput(format!("{} ({})", msg, record.join(",")))
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+
+/// midomoji_clone のエラー
+#[derive(Debug)]
+pub enum Error {
+    /// ファイルの読み書きのエラー
+    Io(io::Error),
+    /// lex.csv, matrix.def, char.def, unk.def などの解析エラー
+    Parse { file: String, line: u64, message: String },
+    /// 連接コスト表の範囲外の文脈ID
+    InvalidMatrixId { left_id: usize, right_id: usize, left_max: usize, right_max: usize },
+    /// 辞書ファイルの形式が不正
+    DictionaryFormat(String),
+    /// ダブル配列の構築に失敗した
+    DoubleArray(String),
+    /// 引数・入力の値が不正
+    InvalidInput(String),
+}
+
+/// midomoji_clone のResult
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// 解析エラーを生成する
+    ///
+    /// # Arguments
+    ///
+    /// * `file`    - 解析していたファイル
+    /// * `line`    - 行番号(1始まり)
+    /// * `message` - エラーの内容
+    pub fn parse<S: Into<String>>(file: &str, line: u64, message: S) -> Error {
+        Error::Parse { file: file.to_string(), line, message: message.into() }
+    }
+
+    /// csvの読み込みエラーを変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `file`  - 読み込んでいたファイル
+    /// * `error` - csvのエラー
+    pub fn from_csv(file: &str, error: csv::Error) -> Error {
+        let line = error.position().map(|p| p.line()).unwrap_or(0);
+        match error.into_kind() {
+            csv::ErrorKind::Io(e) => Error::Io(e),
+            kind => Error::parse(file, line, format!("{:?}", kind)),
+        }
+    }
+
+    /// コマンドの終了コード(sysexits.hに合わせる)
+    /// 74: 入出力エラー, 65: 入力データのエラー
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_) => 74,
+            _            => 65,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "入出力エラー: {}", e),
+            Error::Parse { file, line, message } => write!(f, "{}(line={}): {}", file, line, message),
+            Error::InvalidMatrixId { left_id, right_id, left_max, right_max } => write!(
+                f, "文脈IDが連接コスト表の範囲外です。(left_id={}, right_id={}, left_max={}, right_max={})",
+                left_id, right_id, left_max, right_max,
+            ),
+            Error::DictionaryFormat(message) => write!(f, "辞書ファイルが不正です: {}", message),
+            Error::DoubleArray(message) => write!(f, "ダブル配列の構築に失敗しました: {}", message),
+            Error::InvalidInput(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _            => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error() {
+        // エラーメッセージは1行で、ファイル名と行番号を含む
+        let e = Error::parse("lex.csv", 870_000, "コストが不正です。");
+        assert_eq!("lex.csv(line=870000): コストが不正です。", e.to_string());
+        assert_eq!(65, e.exit_code());
+        let e: Error = io::Error::new(io::ErrorKind::NotFound, "not found").into();
+        assert_eq!(74, e.exit_code());
+        // csvのエラーは行番号付きの解析エラーになる
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader("a,1\nb,2,3\n".as_bytes());
+        let e = reader.records().filter_map(|r| r.err()).next().unwrap();
+        match Error::from_csv("lex.csv", e) {
+            Error::Parse { line, .. } => assert_eq!(2, line),
+            e => panic!("{:?}", e),
+        }
+    }
+}
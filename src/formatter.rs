@@ -0,0 +1,327 @@
+use crate::tokenizer::Morpheme;
+use crate::dictionary::feature_builder::split_feature;
+use crate::error::{Error, Result};
+
+use std::io;
+use std::io::prelude::*;
+
+/// 解析結果をテンプレートに従って出力する構造体(MeCabの--node-formatなどと同じ)
+/// テンプレートで使える書式は以下の通り
+///
+/// * `%m`            - 表層形
+/// * `%H`            - 素性
+/// * `%f[N]`         - 素性のN番目(0始まり)のカラム。範囲外の場合は空文字列
+/// * `%f[N1,N2,...]` - 素性の複数のカラムをカンマ区切りで
+/// * `%FC[N1,N2,...]`- 素性の複数のカラムを文字Cで区切って
+/// * `%c`, `%pw`     - 単語の生起コスト
+/// * `%pc`           - BOSからの累積コスト
+/// * `%phl`, `%phr`  - 左文脈ID, 右文脈ID
+/// * `%ps`, `%pe`    - 文頭からの開始位置・終了位置(バイト)
+/// * `%Ps`, `%Pe`    - 文頭からの開始位置・終了位置(文字数)
+/// * `%pl`           - 表層形の長さ(バイト)
+/// * `%s`            - 0: 辞書の単語, 1: 未知語
+/// * `%S`            - 解析した文字列
+/// * `%%`            - %
+///
+/// `\t`, `\n`, `\r`, `\s`(空白), `\\` はエスケープシーケンスとして扱う
+/// BOS, EOSのテンプレートでは形態素に関する書式は空文字列になる
+pub struct Formatter {
+    node: Vec<Item>,
+    unk : Option<Vec<Item>>,
+    bos : Vec<Item>,
+    eos : Vec<Item>,
+}
+
+/// テンプレートの要素
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Literal(String),
+    Surface,
+    Feature,
+    FeatureColumns(Vec<usize>, char),
+    WordCost,
+    TotalCost,
+    LeftId,
+    RightId,
+    ByteStart,
+    ByteEnd,
+    CharStart,
+    CharEnd,
+    ByteLength,
+    Stat,
+    Sentence,
+}
+
+impl Formatter {
+    /// 形態素のテンプレートを指定して生成する
+    /// 未知語はnode_formatと同じ、BOSは出力なし、EOSは "EOS\n" を出力する
+    ///
+    /// # Arguments
+    ///
+    /// * `node_format` - 形態素のテンプレート
+    pub fn new(node_format: &str) -> Result<Formatter> {
+        Ok(Formatter {
+            node: parse(node_format)?,
+            unk : None,
+            bos : Vec::new(),
+            eos : parse("EOS\\n")?,
+        })
+    }
+
+    /// MeCabのデフォルトと同じ "表層形\t素性" の形式
+    pub fn mecab() -> Formatter {
+        Self::new("%m\\t%H\\n").unwrap()
+    }
+
+    /// 未知語のテンプレートを指定する
+    ///
+    /// # Arguments
+    ///
+    /// * `unk_format` - 未知語のテンプレート
+    pub fn set_unk_format(&mut self, unk_format: &str) -> Result<()> {
+        self.unk = Some(parse(unk_format)?);
+        Ok(())
+    }
+
+    /// 文頭(BOS)のテンプレートを指定する
+    ///
+    /// # Arguments
+    ///
+    /// * `bos_format` - 文頭のテンプレート
+    pub fn set_bos_format(&mut self, bos_format: &str) -> Result<()> {
+        self.bos = parse(bos_format)?;
+        Ok(())
+    }
+
+    /// 文末(EOS)のテンプレートを指定する
+    ///
+    /// # Arguments
+    ///
+    /// * `eos_format` - 文末のテンプレート
+    pub fn set_eos_format(&mut self, eos_format: &str) -> Result<()> {
+        self.eos = parse(eos_format)?;
+        Ok(())
+    }
+
+    /// 1文の解析結果を出力する
+    ///
+    /// # Arguments
+    ///
+    /// * `text`      - 解析した文字列
+    /// * `morphemes` - 解析結果
+    /// * `writer`    - 出力先
+    pub fn write<W: Write>(&self, text: &str, morphemes: &[Morpheme], writer: &mut W) -> io::Result<()> {
+        write_items(&self.bos, text, None, writer)?;
+        for morpheme in morphemes {
+            let items = match self.unk {
+                Some(ref unk) if morpheme.is_unknown => unk,
+                _ => &self.node,
+            };
+            write_items(items, text, Some(morpheme), writer)?;
+        }
+        write_items(&self.eos, text, None, writer)
+    }
+}
+
+/// テンプレートの要素を出力する
+fn write_items<W: Write>(items: &[Item], text: &str, morpheme: Option<&Morpheme>, writer: &mut W) -> io::Result<()> {
+    // 素性のカラムは必要になったときに一度だけ分割する
+    let mut columns: Option<Vec<String>> = None;
+    for item in items {
+        match (item, morpheme) {
+            (Item::Literal(s), _) => writer.write_all(s.as_bytes())?,
+            (Item::Sentence, _)   => writer.write_all(text.as_bytes())?,
+            (_, None)             => {},
+            (Item::Surface, Some(m))    => writer.write_all(m.surface.as_bytes())?,
+            (Item::Feature, Some(m))    => writer.write_all(m.feature.as_bytes())?,
+            (Item::FeatureColumns(ids, sep), Some(m)) => {
+                if columns.is_none() {
+                    columns = Some(split_feature(m.feature));
+                }
+                let columns = columns.as_ref().unwrap();
+                for (i, &id) in ids.iter().enumerate() {
+                    if i > 0 {
+                        write!(writer, "{}", sep)?;
+                    }
+                    if let Some(column) = columns.get(id) {
+                        writer.write_all(column.as_bytes())?;
+                    }
+                }
+            },
+            (Item::WordCost, Some(m))   => write!(writer, "{}", m.cost)?,
+            (Item::TotalCost, Some(m))  => write!(writer, "{}", m.total_cost)?,
+            (Item::LeftId, Some(m))     => write!(writer, "{}", m.left_id)?,
+            (Item::RightId, Some(m))    => write!(writer, "{}", m.right_id)?,
+            (Item::ByteStart, Some(m))  => write!(writer, "{}", m.byte_start)?,
+            (Item::ByteEnd, Some(m))    => write!(writer, "{}", m.byte_end)?,
+            (Item::CharStart, Some(m))  => write!(writer, "{}", m.char_start)?,
+            (Item::CharEnd, Some(m))    => write!(writer, "{}", m.char_end)?,
+            (Item::ByteLength, Some(m)) => write!(writer, "{}", m.surface.len())?,
+            (Item::Stat, Some(m))       => write!(writer, "{}", if m.is_unknown { 1 } else { 0 })?,
+        }
+    }
+    Ok(())
+}
+
+/// テンプレートを要素に分解する
+///
+/// # Arguments
+///
+/// * `format` - テンプレート
+fn parse(format: &str) -> Result<Vec<Item>> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        let item = match c {
+            '\\' => {
+                literal.push(unescape(chars.next()));
+                continue;
+            },
+            '%' => match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                },
+                Some('m') => Item::Surface,
+                Some('H') => Item::Feature,
+                Some('c') => Item::WordCost,
+                Some('s') => Item::Stat,
+                Some('S') => Item::Sentence,
+                Some('f') => Item::FeatureColumns(parse_columns(&mut chars, format)?, ','),
+                Some('F') => {
+                    let sep = match chars.next() {
+                        Some('\\') => unescape(chars.next()),
+                        Some(sep)  => sep,
+                        None       => return Err(invalid_format(format)),
+                    };
+                    Item::FeatureColumns(parse_columns(&mut chars, format)?, sep)
+                },
+                Some('p') => match chars.next() {
+                    Some('w') => Item::WordCost,
+                    Some('c') => Item::TotalCost,
+                    Some('s') => Item::ByteStart,
+                    Some('e') => Item::ByteEnd,
+                    Some('l') => Item::ByteLength,
+                    Some('h') => match chars.next() {
+                        Some('l') => Item::LeftId,
+                        Some('r') => Item::RightId,
+                        _ => return Err(invalid_format(format)),
+                    },
+                    _ => return Err(invalid_format(format)),
+                },
+                Some('P') => match chars.next() {
+                    Some('s') => Item::CharStart,
+                    Some('e') => Item::CharEnd,
+                    _ => return Err(invalid_format(format)),
+                },
+                _ => return Err(invalid_format(format)),
+            },
+            c => {
+                literal.push(c);
+                continue;
+            },
+        };
+        if !literal.is_empty() {
+            items.push(Item::Literal(literal));
+            literal = String::new();
+        }
+        items.push(item);
+    }
+    if !literal.is_empty() {
+        items.push(Item::Literal(literal));
+    }
+    Ok(items)
+}
+
+/// "[N1,N2,...]" を素性のカラム番号の配列にする
+fn parse_columns<I: Iterator<Item = char>>(chars: &mut I, format: &str) -> Result<Vec<usize>> {
+    if chars.next() != Some('[') {
+        return Err(invalid_format(format));
+    }
+    let mut list = String::new();
+    loop {
+        match chars.next() {
+            Some(']') => break,
+            Some(c)   => list.push(c),
+            None      => return Err(invalid_format(format)),
+        }
+    }
+    list.split(',')
+        .map(|n| n.trim().parse::<usize>().map_err(|_| invalid_format(format)))
+        .collect()
+}
+
+/// エスケープシーケンス "\c" の c を文字にする
+fn unescape(c: Option<char>) -> char {
+    match c {
+        Some('t') => '\t',
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('s') => ' ',
+        Some(c)   => c,
+        None      => '\\',
+    }
+}
+
+fn invalid_format(format: &str) -> Error {
+    Error::InvalidInput(format!("書式が不正です: {}", format))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn morphemes() -> Vec<Morpheme<'static>> {
+        vec![
+            Morpheme {
+                surface: "すもも", byte_start: 0, byte_end: 9, char_start: 0, char_end: 3,
+                left_id: 1, right_id: 2, cost: 100, total_cost: 150, dict_id: 0, is_unknown: false,
+                feature: "名詞,一般,*,*,*,*,すもも,スモモ,スモモ",
+            },
+            Morpheme {
+                surface: "🍑", byte_start: 9, byte_end: 13, char_start: 3, char_end: 4,
+                left_id: 3, right_id: 4, cost: 1000, total_cost: 1200, dict_id: 0, is_unknown: true,
+                feature: "名詞,\"一般,未知語\"",
+            },
+        ]
+    }
+
+    fn format(formatter: &Formatter) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        formatter.write("すもも🍑", &morphemes(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_formatter() {
+        // MeCabのデフォルトの形式
+        assert_eq!("すもも\t名詞,一般,*,*,*,*,すもも,スモモ,スモモ\n🍑\t名詞,\"一般,未知語\"\nEOS\n", format(&Formatter::mecab()));
+
+        // 素性のカラム。範囲外は空文字列、エスケープされたカラムは元に戻す
+        let formatter = Formatter::new("%m\\t%f[7]\\t%F/[0,1]\\t%f[1,0]\\n").unwrap();
+        assert_eq!("すもも\tスモモ\t名詞/一般\t一般,名詞\n🍑\t\t名詞/一般,未知語\t一般,未知語,名詞\nEOS\n", format(&formatter));
+
+        // 文脈ID、コスト、位置
+        let mut formatter = Formatter::new("%m %phl %phr %c %pw %pc %ps %pe %Ps %Pe %pl %s 100%%\\n").unwrap();
+        formatter.set_bos_format("BOS %S %m\\n").unwrap();
+        formatter.set_eos_format("").unwrap();
+        assert_eq!("BOS すもも🍑 \nすもも 1 2 100 100 150 0 9 0 3 9 0 100%\n🍑 3 4 1000 1000 1200 9 13 3 4 4 1 100%\n", format(&formatter));
+
+        // 未知語のテンプレート
+        let mut formatter = Formatter::new("%m\\n").unwrap();
+        formatter.set_unk_format("%m\\s(unk)\\n").unwrap();
+        assert_eq!("すもも\n🍑 (unk)\nEOS\n", format(&formatter));
+    }
+
+    #[test]
+    fn test_formatter_invalid() {
+        assert!(Formatter::new("%x").is_err());
+        assert!(Formatter::new("%f[a]").is_err());
+        assert!(Formatter::new("%f[1").is_err());
+        assert!(Formatter::new("%f").is_err());
+        assert!(Formatter::new("%ph").is_err());
+        assert!(Formatter::new("%").is_err());
+    }
+}
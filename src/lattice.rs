@@ -1,66 +1,105 @@
 use crate::token::Token;
 use crate::dictionary::DictionarySet;
+use crate::dictionary::feature_builder::{Feature, FeatureLayout};
+use crate::dictionary::char_def::CharInfo;
 
-// TODO: NBestを実装する
-pub struct Lattice<'a> {
-    start   : Vec<Vec<LatticeNode<'a>>>,
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::mem;
+
+/// 同じ文字種の文字をまとめて未知語にする場合の最大文字数(MeCabのmax-grouping-size)
+const MAX_GROUPING_SIZE: usize = 24;
+/// システム辞書のノードのdict_id
+pub const SYSTEM_DICT_ID: usize = 0;
+
+/// ラティス構造
+/// start, endは確保した領域を使いまわすため、解析中の文より長いことがある(size以降は常に空)
+/// ノードは文字列を参照せずに位置だけを持つので、別の文字列の解析にそのまま使いまわせる
+pub struct Lattice {
+    start   : Vec<Vec<LatticeNode>>,
     end     : Vec<Vec<(usize, usize)>>,
+    size    : usize, // 解析中の文のバイト数 + 2(BOS, EOS)
+    ends    : Vec<usize>, // 未知語処理で使う作業領域
 }
 
-impl<'a> Lattice<'a> {
-    pub fn new(size: usize) -> Lattice<'a> {
-        // TODO: start, endを使いまわして、malloc の回数を減らしたい
+impl Lattice {
+    pub fn new(size: usize) -> Lattice {
         let mut ret = Lattice {
             start   : Vec::with_capacity(size),
             end     : Vec::with_capacity(size),
+            size    : 0,
+            ends    : Vec::new(),
         };
-        ret.start.resize_with(size, Default::default);
-        ret.end.resize_with(size, Default::default);
+        ret.resize(size);
         ret
     }
 
+    /// ノードをすべて破棄する
+    /// 確保した領域は解放せずに残しておく
+    pub fn clear(&mut self) {
+        for nodes in self.start[..self.size].iter_mut() {
+            nodes.clear();
+        }
+        for nodes in self.end[..self.size].iter_mut() {
+            nodes.clear();
+        }
+        self.size = 0;
+    }
+
+    /// 空のラティス構造の大きさを変える
+    /// 小さくする場合も確保した領域は残しておく
+    fn resize(&mut self, size: usize) {
+        if self.start.len() < size {
+            self.start.resize_with(size, Default::default);
+            self.end.resize_with(size, Default::default);
+        }
+        self.size = size;
+    }
+
     /**
      * ラティス構造に複数のトークンをセットする
+     * pos は直前のノードが終わる位置。表層形は空白を読み飛ばした idx_start から始まる
+     * dict_id はトークンを引いた辞書(0: システム辞書, n: n番目のユーザー辞書)
+     * is_unknown は未知語処理で生成したトークンかどうか
      */
-    fn set_tokens(&mut self, idx_start: usize, idx_end: usize, surface: &'a [u8], tokens: &[Token]) {
-        let idx_start = idx_start + 1;
+    fn set_tokens(&mut self, pos: usize, idx_start: usize, idx_end: usize, tokens: &[Token], dict_id: usize, is_unknown: bool) {
+        let (start, len) = (idx_start, idx_end - idx_start);
+        let idx_start = pos + 1;
         let idx_end   = idx_end + 1;
         for &token in tokens {
-            self.start[idx_start].push(LatticeNode::new(surface, token));
+            let mut node = LatticeNode::new(start, len, token, dict_id);
+            node.is_unknown = is_unknown;
+            self.start[idx_start].push(node);
             self.end[idx_end].push((idx_start, self.start[idx_start].len() - 1));
         }
     }
 
-    /**
-     * ラティス構造にトークンをセットする
-     */
-    fn set_token(&mut self, idx_start: usize, idx_end: usize, surface: &'a [u8], token: Token) {
-        let idx_start = idx_start + 1;
-        let idx_end   = idx_end + 1;
-        self.start[idx_start].push(LatticeNode::new(surface, token));
-        self.end[idx_end].push((idx_start, self.start[idx_start].len() - 1));
-    }
-
     /**
      * eos, bosノードをセットする
      */
-    fn set_bos_eos(&mut self, surface: &'a [u8], eos_bos: Token) {
+    fn set_bos_eos(&mut self, eos_bos: Token) {
         // bosノード
         self.start[0].push(LatticeNode {
             token     : eos_bos,
-            surface   : surface,
+            start     : 0,
+            len       : 0,
             total_cost: 0,
             prev      : None,
+            dict_id   : SYSTEM_DICT_ID,
+            is_unknown: false,
         });
         self.end[1].push((0, 0));
 
         // eosノード: startにだけ登録すればいい
-        let idx_eos = self.start.len() - 1;
+        let idx_eos = self.size - 1;
         self.start[idx_eos].push(LatticeNode {
             token     : eos_bos,
-            surface   : surface,
+            start     : idx_eos - 1,
+            len       : 0,
             total_cost: i32::max_value(),
             prev      : None,
+            dict_id   : SYSTEM_DICT_ID,
+            is_unknown: false,
         });
     }
 
@@ -69,85 +108,186 @@ impl<'a> Lattice<'a> {
     /// # Arguments
     ///
     /// * `bytes`    - 解析する文字列(バイト列)
-    pub fn build(dict_set: &DictionarySet<'a, Token>, bytes: &'a [u8]) -> Lattice<'a> {
-        // ラティス構造の初期化
+    pub fn build(dict_set: &DictionarySet<Token>, bytes: &[u8]) -> Lattice {
+        Self::build_with_user_dicts(dict_set, &[], bytes)
+    }
+
+    /// システム辞書とユーザー辞書からラティス構造を構築する
+    /// ユーザー辞書の単語もシステム辞書の単語と同じように候補になる
+    /// 未知語処理はどの辞書にも単語が見つからなかったものとして、システム辞書の未知語定義で行う
+    /// ユーザー辞書は、読み込み時にcheck_context_idsでシステム辞書の連接コスト表に対して検証しておくこと
+    /// (Tokenizer::add_user_dictは検証する)
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`   - システム辞書
+    /// * `user_dicts` - ユーザー辞書(UserDictBuilderで構築したもの)
+    /// * `bytes`      - 解析する文字列(バイト列)
+    pub fn build_with_user_dicts(dict_set: &DictionarySet<Token>, user_dicts: &[DictionarySet<Token>], bytes: &[u8]) -> Lattice {
         let mut lattice = Self::new(bytes.len() + 2);
-        // ダブル配列での探索開始index
-        let mut idx = 1;
-        // idxにおけるbase値
-        let mut base = dict_set.base_arr[idx] as usize;
+        lattice.clear_and_build(dict_set, user_dicts, bytes);
+        lattice
+    }
+
+    /// ノードをすべて破棄してから、ラティス構造を構築し直す
+    /// 前回までに確保した領域を使いまわすので、文ごとにラティス構造を生成するよりmallocの回数が少ない
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`   - システム辞書
+    /// * `user_dicts` - ユーザー辞書(UserDictBuilderで構築したもの)
+    /// * `bytes`      - 解析する文字列(バイト列)
+    pub fn clear_and_build(&mut self, dict_set: &DictionarySet<Token>, user_dicts: &[DictionarySet<Token>], bytes: &[u8]) {
+        // ラティス構造の初期化
+        self.clear();
+        self.resize(bytes.len() + 2);
 
         // BOS・EOSの登録
-        lattice.set_bos_eos(&bytes[0..0], Token::eos_bos());
-        // 未知語ノードはとりあえずデフォルトで挿入
+        self.set_bos_eos(Token::eos_bos());
 
-        for (i, &byte) in bytes.iter().enumerate() { // スタート位置
-            // 未知語の終了ノードを求める
-            // TODO: graphemeを考慮する(https://doc.rust-lang.org/1.3.0/std/str/struct.GraphemeIndices.html)
-            let unknown_end_idx = if (byte & 0b11111000) == 0b11110000 { // 4byte文字
-                i + 4
-            } else if (byte & 0b11110000) == 0b11100000 { // 3byte文字
-                i + 3
-            } else if (byte & 0b11100000) == 0b11000000 { // 2byte文字
-                i + 2
-            } else if (byte & 0b11000000) == 0b10000000 { // スタート位置がutf8の文字として適切でなければスキップ
+        let space = dict_set.get_space_char_info();
+        let idx_eos = self.size - 1;
+        for i in 0..bytes.len() { // スタート位置
+            // MeCabと同じく、直前のノードが終わる位置からだけ探索する
+            // utf8の文字の先頭でない位置や、空白の途中で終わるノードはない
+            if self.end[i + 1].is_empty() {
                 continue;
-            } else { // if (byte & 0b10000000) == 0b00000000 // 1byte文字
-                i + 1
+            }
+            // 空白は読み飛ばして、次の文字からノードを始める(MeCabのseekToOtherType)
+            let start_idx = skip_spaces(dict_set, space, bytes, i);
+            // TODO: graphemeを考慮する(https://doc.rust-lang.org/1.3.0/std/str/struct.GraphemeIndices.html)
+            let char_info = match char_at(bytes, start_idx) {
+                Some((c, _)) => dict_set.get_char_info(c),
+                None         => {
+                    // 末尾の空白はノードにせず、直前のノードをEOSにつなげる
+                    for j in 0..self.end[i + 1].len() {
+                        let prev = self.end[i + 1][j];
+                        self.end[idx_eos].push(prev);
+                    }
+                    continue;
+                },
             };
-            // println!("{:?}", std::str::from_utf8(&bytes[i..unknown_end_idx]));
-            // 未知語ノードの登録
-            lattice.set_token(i, unknown_end_idx, &bytes[i..unknown_end_idx], Token::unknown());
 
             // 通常ノードの登録
-            for (j, &byte) in bytes[i..].iter().enumerate() { // スタート位置から文字をループ
-                // 次のノードに遷移
-                let next_idx = base + (byte as usize);
-                if dict_set.check_arr[next_idx] as usize != idx {
-                    break;
-                }
-                idx = next_idx;
-                base = dict_set.base_arr[idx] as usize;
-
-                // 値があればlatticeにセット
-                let value_idx = base + (u8::max_value() as usize);
-                if dict_set.check_arr[value_idx] as usize == idx {
-                    let start_idx = i;         // 包含
-                    let end_idx   = i + j + 1; // 排他
-                    let data_idx = (dict_set.base_arr[value_idx] >> 8) as usize;
-                    let data_len = (dict_set.base_arr[value_idx] & 0b11111111) as usize;
-                    lattice.set_tokens(
-                        start_idx,
-                        end_idx,
-                        &bytes[start_idx..end_idx],
-                        &dict_set.data_arr[data_idx..(data_idx + data_len)]
-                    );
-                }
+            let mut found = self.set_knowns(dict_set, SYSTEM_DICT_ID, bytes, i, start_idx);
+            for (n, user_dict) in user_dicts.iter().enumerate() {
+                found |= self.set_knowns(user_dict, n + 1, bytes, i, start_idx);
             }
-            // idxとbaseを初期値に戻す
-            idx  = 1;
-            base = dict_set.base_arr[idx] as usize;
+
+            // 未知語ノードの登録
+            self.set_unknowns(dict_set, bytes, i, start_idx, char_info, found);
         }
-        lattice
+    }
+
+    /// 開始位置から始まる辞書の単語のノードをセットする
+    /// 単語がひとつでも見つかればtrueを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`  - 辞書
+    /// * `dict_id`   - 辞書のID(0: システム辞書, n: n番目のユーザー辞書)
+    /// * `bytes`     - 解析する文字列(バイト列)
+    /// * `pos`       - 直前のノードが終わる位置
+    /// * `start_idx` - 開始位置(posから空白を読み飛ばした位置)
+    fn set_knowns(&mut self, dict_set: &DictionarySet<Token>, dict_id: usize, bytes: &[u8], pos: usize, start_idx: usize) -> bool {
+        let mut found = false;
+        for (len, tokens) in dict_set.common_prefix_iter(&bytes[start_idx..]) {
+            // 値があればlatticeにセット
+            let end_idx = start_idx + len; // 排他
+            self.set_tokens(pos, start_idx, end_idx, tokens, dict_id, false);
+            found = true;
+        }
+        found
+    }
+
+    /// 未知語ノードをセットする(MeCabの未知語処理と同じ)
+    /// 辞書に単語が見つからなかった場合か、文字種のINVOKEが1の場合に未知語を生成する
+    /// GROUPが1なら同じ文字種の文字をまとめた未知語を、LENGTHがnなら1~n文字の未知語を生成する
+    /// いずれも生成されなかった場合は1文字の未知語を生成する
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`  - 辞書
+    /// * `bytes`     - 解析する文字列(バイト列)
+    /// * `pos`       - 直前のノードが終わる位置
+    /// * `start_idx` - 未知語の開始位置(posから空白を読み飛ばした位置)
+    /// * `char_info` - 開始位置の文字の情報
+    /// * `found`     - 開始位置から始まる単語が辞書に見つかったか
+    fn set_unknowns(&mut self, dict_set: &DictionarySet<Token>, bytes: &[u8], pos: usize, start_idx: usize, char_info: CharInfo, found: bool) {
+        let category = dict_set.get_char_category(char_info.default_category());
+        if found && category.invoke == 0 {
+            return;
+        }
+        let mut tokens = dict_set.get_unknowns(category);
+        let unknown = [Token::unknown()];
+        if tokens.is_empty() {
+            // 文字種に未知語が定義されていない場合
+            tokens = &unknown;
+        }
+
+        // 同じ文字種の文字が続く間の、各文字の終了位置を求める
+        // GROUPの場合はMAX_GROUPING_SIZEを超えたかどうかが分かるまで、それ以外はLENGTH文字まで
+        let max_len = if category.group == 1 { MAX_GROUPING_SIZE + 1 } else { category.length as usize };
+        // set_tokensで&mut selfを借用するので、作業領域を一時的に取り出す
+        let mut ends = mem::replace(&mut self.ends, Vec::new());
+        ends.clear();
+        let mut end_idx = start_idx;
+        while let Some((c, len)) = char_at(bytes, end_idx) {
+            if end_idx != start_idx && !char_info.is_kind_of(dict_set.get_char_info(c)) {
+                break;
+            }
+            end_idx += len;
+            ends.push(end_idx);
+            if ends.len() >= max_len {
+                break;
+            }
+        }
+
+        let mut set = false;
+        // 同じ文字種の文字をまとめた未知語
+        let mut group_end_idx = None;
+        if category.group == 1 && ends.len() <= MAX_GROUPING_SIZE {
+            if let Some(&end_idx) = ends.last() {
+                self.set_tokens(pos, start_idx, end_idx, tokens, SYSTEM_DICT_ID, true);
+                group_end_idx = Some(end_idx);
+                set = true;
+            }
+        }
+        // 1~LENGTH文字の未知語
+        for &end_idx in ends.iter().take(category.length as usize) {
+            if group_end_idx != Some(end_idx) {
+                self.set_tokens(pos, start_idx, end_idx, tokens, SYSTEM_DICT_ID, true);
+                set = true;
+            }
+        }
+        // 開始位置から始まるノードがひとつもなければ1文字の未知語
+        if !set && !found {
+            if let Some((_, len)) = char_at(bytes, start_idx) {
+                self.set_tokens(pos, start_idx, start_idx + len, tokens, SYSTEM_DICT_ID, true);
+            }
+        }
+        self.ends = ends;
     }
 
     /**
      * ラティス構造を解析する
      * 文字列の前方から解析していく
      */
-    pub fn analyze(&mut self, dict_set: &DictionarySet<'a, Token>) {
-        for si in 1..self.start.len() {
+    pub fn analyze(&mut self, dict_set: &DictionarySet<Token>) {
+        for si in 1..self.size {
             for sj in 0..self.start[si].len() {
-                // 右側ノードの左文脈IDと生起コスト
-                let left_id  = self.start[si][sj].token.left_id as usize;
+                // 右側ノードの生起コスト
                 let cost     = self.start[si][sj].token.cost as i32;
                 // 直前につながるノードを求める
                 for &(ei, ej) in self.end[si].iter() {
-                    // 左側ノードの右文脈IDとトータルコスト
-                    let right_id  = self.start[ei][ej].token.left_id as usize;
+                    // 左側ノードのトータルコスト
                     let prev_cost = self.start[ei][ej].total_cost;
+                    if prev_cost == i32::max_value() {
+                        // BOSから到達できないノード
+                        continue;
+                    }
                     // 接続コスト
-                    let conn_cost = dict_set.get_matrix(left_id, right_id) as i32;
+                    let conn_cost = Self::connection_cost(dict_set, &self.start[ei][ej], &self.start[si][sj]);
                     let total_cost = prev_cost + cost  + conn_cost;
                     if total_cost < self.start[si][sj].total_cost {
                         self.start[si][sj].total_cost = total_cost;
@@ -159,14 +299,86 @@ impl<'a> Lattice<'a> {
     }
 
 
+    /// 左側ノードと右側ノードの連接コスト
+    /// matrix.defと同じく、左側ノードの右文脈IDと右側ノードの左文脈IDの組で引く
+    ///
+    /// # Arguments
+    ///
+    /// * `left`  - 左側ノード
+    /// * `right` - 右側ノード
+    fn connection_cost(dict_set: &DictionarySet<Token>, left: &LatticeNode, right: &LatticeNode) -> i32 {
+        dict_set.get_matrix(left.token.right_id as usize, right.token.left_id as usize) as i32
+    }
+
+    /// コストの小さい順にn個の解析結果を求める(N-Best解)
+    /// analyzeで求めたBOSから各ノードまでの最小コストをヒューリスティックとして、EOSからBOSに向かってA*探索を行う
+    /// 解析結果はget_resultと同じくEOSからBOSの順に並んだノードの配列と、その経路の合計コストの組
+    /// analyzeを呼び出した後に呼び出すこと
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set` - 辞書
+    /// * `n`        - 求める解析結果の数
+    pub fn nbest(&self, dict_set: &DictionarySet<Token>, n: usize) -> Vec<(i32, Vec<&LatticeNode>)> {
+        let mut results: Vec<(i32, Vec<&LatticeNode>)> = Vec::with_capacity(n);
+        let idx_eos = self.size - 1;
+        let eos = &self.start[idx_eos][0];
+        if n == 0 || eos.total_cost == i32::max_value() {
+            return results;
+        }
+        // 探索途中の経路: (ノードの位置, ノードからEOSまでのコスト, EOS側の次の経路)
+        let mut paths: Vec<((usize, usize), i32, Option<usize>)> = vec![((idx_eos, 0), 0, None)];
+        // (BOSからEOSまでの推定コスト, 経路のindex) の最小ヒープ
+        let mut queue: BinaryHeap<Reverse<(i32, usize)>> = BinaryHeap::new();
+        queue.push(Reverse((eos.total_cost, 0)));
+
+        while let Some(Reverse((cost, path_idx))) = queue.pop() {
+            let ((si, sj), suffix_cost, _) = paths[path_idx];
+            if si == 0 {
+                // BOSまで到達したら経路を復元する
+                let mut nodes: Vec<&LatticeNode> = Vec::new();
+                let mut next = Some(path_idx);
+                while let Some(idx) = next {
+                    let ((i, j), _, n) = paths[idx];
+                    nodes.push(&self.start[i][j]);
+                    next = n;
+                }
+                nodes.reverse();
+                results.push((cost, nodes));
+                if results.len() >= n {
+                    break;
+                }
+                continue;
+            }
+            // 直前につながるノードに経路を伸ばす
+            let node = &self.start[si][sj];
+            for &(ei, ej) in self.end[si].iter() {
+                let prev = &self.start[ei][ej];
+                if prev.total_cost == i32::max_value() {
+                    // BOSから到達できないノード
+                    continue;
+                }
+                let suffix_cost = suffix_cost + node.token.cost as i32 + Self::connection_cost(dict_set, prev, node);
+                paths.push(((ei, ej), suffix_cost, Some(path_idx)));
+                queue.push(Reverse((prev.total_cost + suffix_cost, paths.len() - 1)));
+            }
+        }
+        results
+    }
+
+    /// ラティス構造のノード数(BOS, EOSを含む)
+    pub fn node_count(&self) -> usize {
+        self.start[..self.size].iter().map(|nodes| nodes.len()).sum()
+    }
+
     /**
      * 解析結果を配列にまとめる。
      * TODO: イテレータにしたい
      */
-    pub fn get_result(&self) -> Vec<&LatticeNode<'a>> {
+    pub fn get_result(&self) -> Vec<&LatticeNode> {
         // eosノードから前方のノードをさかのぼっていく
-        let mut result: Vec<&LatticeNode<'a>> = vec![];
-        let mut node = &self.start[self.start.len() - 1][0]; // eosノード
+        let mut result: Vec<&LatticeNode> = vec![];
+        let mut node = &self.start[self.size - 1][0]; // eosノード
         loop {
             result.push(node);
             if let Some((i, j)) = node.prev {
@@ -179,7 +391,7 @@ impl<'a> Lattice<'a> {
     }
 
     pub fn debug(&self) {
-        for i in 0..self.start.len() {
+        for i in 0..self.size {
             println!("index: {}", i);
             for (j, node) in self.start[i].iter().enumerate() {
                 if j == 0 {
@@ -199,30 +411,71 @@ impl<'a> Lattice<'a> {
 }
 
 #[derive(Debug)]
-pub struct LatticeNode<'a> {
+pub struct LatticeNode {
     pub token     : Token,
-    pub surface   : &'a [u8],
+    pub start     : usize, // 解析した文字列での開始位置(バイト)
+    pub len       : usize, // 表層形のバイト数(BOS, EOSは0)
     pub total_cost: i32,
     pub prev      : Option<(usize, usize)>,
+    pub dict_id   : usize, // 0: システム辞書, n: n番目のユーザー辞書
+    pub is_unknown: bool,  // 未知語処理で生成したノードか
 }
 
-impl<'a> LatticeNode<'a> {
-    pub fn new(surface: &'a [u8], token: Token) -> LatticeNode<'a> {
+impl LatticeNode {
+    pub fn new(start: usize, len: usize, token: Token, dict_id: usize) -> LatticeNode {
         LatticeNode {
             token     : token,
-            surface   : surface,
+            start     : start,
+            len       : len,
             total_cost: i32::max_value(),
             prev      : Some((0, 0)),
+            dict_id   : dict_id,
+            is_unknown: false,
         }
     }
+
+    /// ノードの表層形を取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - ラティス構造の構築に利用した文字列(バイト列)
+    pub fn surface<'t>(&self, bytes: &'t [u8]) -> &'t [u8] {
+        &bytes[self.start..(self.start + self.len)]
+    }
+
+    /// ノードの素性文字列を取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`   - ラティス構造の構築に利用したシステム辞書
+    /// * `user_dicts` - ラティス構造の構築に利用したユーザー辞書
+    pub fn feature<'b>(&self, dict_set: &DictionarySet<'b, Token>, user_dicts: &[DictionarySet<'b, Token>]) -> &'b str {
+        let dict = match self.dict_id {
+            SYSTEM_DICT_ID => dict_set,
+            n              => &user_dicts[n - 1],
+        };
+        dict.get_feature(self.token.feature_id as usize)
+    }
+
+    /// ノードの素性をカラムに分割して取得する
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_set`   - ラティス構造の構築に利用したシステム辞書
+    /// * `user_dicts` - ラティス構造の構築に利用したユーザー辞書
+    /// * `layout`     - 素性のカラムの位置(辞書の形式)
+    pub fn feature_columns(&self, dict_set: &DictionarySet<Token>, user_dicts: &[DictionarySet<Token>], layout: FeatureLayout) -> Feature {
+        Feature::parse(self.feature(dict_set, user_dicts), layout)
+    }
 }
 
-impl<'a> std::fmt::Display for LatticeNode<'a> {
+impl std::fmt::Display for LatticeNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "(surface: {:?}, left_id: {}, right_id: {}, cost: {}, total_cost: {}, prev: {:?})",
-            std::str::from_utf8(self.surface),
+            "(start: {}, len: {}, left_id: {}, right_id: {}, cost: {}, total_cost: {}, prev: {:?})",
+            self.start,
+            self.len,
             self.token.left_id,
             self.token.right_id,
             self.token.cost,
@@ -233,4 +486,46 @@ impl<'a> std::fmt::Display for LatticeNode<'a> {
 
 }
 
+/// idxから続く空白の文字を読み飛ばした位置を返す(MeCabのseekToOtherType)
+/// 空白の文字種と共通の文字種に属する文字が続く間、直前の文字と比べながら読み飛ばす
+///
+/// # Arguments
+///
+/// * `dict_set` - 辞書
+/// * `space`    - 空白の文字の情報(DictionarySet::get_space_char_info)
+/// * `bytes`    - 解析する文字列(バイト列)
+/// * `idx`      - 読み飛ばしを始める位置
+fn skip_spaces(dict_set: &DictionarySet<Token>, space: Option<CharInfo>, bytes: &[u8], mut idx: usize) -> usize {
+    let mut prev = match space {
+        Some(space) => space,
+        None        => return idx,
+    };
+    while let Some((c, len)) = char_at(bytes, idx) {
+        let char_info = dict_set.get_char_info(c);
+        if !prev.is_kind_of(char_info) {
+            break;
+        }
+        prev = char_info;
+        idx += len;
+    }
+    idx
+}
 
+/// バイト列のidxの位置の文字とそのバイト数を返す
+/// idxがutf8の文字の先頭でない場合はNone
+fn char_at(bytes: &[u8], idx: usize) -> Option<(char, usize)> {
+    let byte = *bytes.get(idx)?;
+    let len = if (byte & 0b11111000) == 0b11110000 { // 4byte文字
+        4
+    } else if (byte & 0b11110000) == 0b11100000 { // 3byte文字
+        3
+    } else if (byte & 0b11100000) == 0b11000000 { // 2byte文字
+        2
+    } else if (byte & 0b11000000) == 0b10000000 { // utf8の文字の先頭ではない
+        return None;
+    } else { // if (byte & 0b10000000) == 0b00000000 // 1byte文字
+        1
+    };
+    let c = std::str::from_utf8(bytes.get(idx..(idx + len))?).ok()?.chars().next()?;
+    Some((c, len))
+}
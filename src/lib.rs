@@ -3,3 +3,6 @@ pub mod token;
 pub mod util;
 pub mod lattice;
 
+pub mod tokenizer;
+pub mod formatter;
+pub mod error;
@@ -1,21 +1,47 @@
+use crate::dictionary::{ContextIds, Pod};
+
+/// 辞書に格納する単語の情報
+/// 辞書ファイルにはメモリ上の表現のまま書き出すので、フィールドの順序を固定し、
+/// パディングを明示的なフィールドにして常に0で初期化する(未初期化のバイトを書き出さないため)
 #[derive(Debug, Clone, Copy)]
+#[repr(C)]
 pub struct Token {
-    pub left_id : u16,
-    pub right_id: u16,
-    pub cost    : i16,
+    pub left_id   : u16,
+    pub right_id  : u16,
+    pub cost      : i16,
+    _pad          : u16,
+    pub feature_id: u32,
+}
+
+// u16, i16, u32だけからなり、パディングは_padとして明示している
+unsafe impl Pod for Token {}
+
+impl ContextIds for Token {
+    fn context_ids(&self) -> Option<(usize, usize)> {
+        Some((self.left_id as usize, self.right_id as usize))
+    }
 }
 
 impl Token {
+    /// 素性を持たないトークンのfeature_id
+    pub const NO_FEATURE: u32 = u32::max_value();
+
+    /// # Arguments
+    ///
+    /// * `left_id`    - 左文脈ID
+    /// * `right_id`   - 右文脈ID
+    /// * `cost`       - 生起コスト
+    /// * `feature_id` - 素性文字列表のID
+    pub fn new(left_id: u16, right_id: u16, cost: i16, feature_id: u32) -> Token {
+        Token { left_id, right_id, cost, _pad: 0, feature_id }
+    }
+
     /**
      * EOS/BOSトークンを生成する。
      * とりあえず決め打ちで値を入れておく。
      */
     pub fn eos_bos() -> Self {
-        Token {
-            left_id : 0,
-            right_id: 0,
-            cost    : 0,
-        }
+        Token::new(0, 0, 0, Self::NO_FEATURE)
     }
 
     /**
@@ -23,11 +49,7 @@ impl Token {
      * とりあえず決め打ちで値を入れておく。
      */
     pub fn unknown() -> Self {
-        Token {
-            left_id : u16::max_value(),
-            right_id: u16::max_value(),
-            cost    : i16::max_value(),
-        }
+        Token::new(u16::max_value(), u16::max_value(), i16::max_value(), Self::NO_FEATURE)
     }
 }
 
@@ -35,8 +57,23 @@ impl PartialEq for Token {
     fn eq(&self, other: &Token) -> bool {
         self.left_id == other.left_id && 
         self.right_id == other.right_id &&
-        self.cost == other.cost
+        self.cost == other.cost &&
+        self.feature_id == other.feature_id
     }
 }
 
 impl Eq for Token {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_layout() {
+        // 辞書ファイルの形式と対応するので、パディングのない12バイトでなければならない
+        assert_eq!(12, std::mem::size_of::<Token>());
+        let token = Token::new(1, 2, -3, 4);
+        let bytes: [u8; 12] = unsafe { std::mem::transmute(token) };
+        assert_eq!([1, 0, 2, 0, 0xFD, 0xFF, 0, 0, 4, 0, 0, 0], bytes);
+    }
+}
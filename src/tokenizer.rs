@@ -0,0 +1,210 @@
+use crate::token::Token;
+use crate::dictionary::DictionarySet;
+use crate::dictionary::feature_builder::{Feature, FeatureLayout};
+use crate::lattice::{Lattice, LatticeNode};
+use crate::error::Result;
+
+use std::io;
+use std::fs::File;
+use std::mem;
+use memmap::{Mmap, MmapOptions};
+
+/// 形態素解析器
+/// 辞書ファイルをmmapして保持し、文字列を形態素の配列に分割する
+pub struct Tokenizer {
+    // dict_set, user_dictsはmmapの領域を参照しているので、mmapより先に破棄されるように先に宣言する
+    dict_set  : DictionarySet<'static, Token>,
+    user_dicts: Vec<DictionarySet<'static, Token>>,
+    _mmaps    : Vec<Mmap>,
+}
+
+impl Tokenizer {
+    /// システム辞書を読み込んで形態素解析器を生成する
+    ///
+    /// # Arguments
+    ///
+    /// * `dict_path` - システム辞書ファイルのパス
+    pub fn new(dict_path: &str) -> Result<Tokenizer> {
+        let mmap = map_file(dict_path)?;
+        let dict_set = unsafe { DictionarySet::new(extend_lifetime(&mmap))? };
+        Ok(Tokenizer { dict_set, user_dicts: Vec::new(), _mmaps: vec![mmap] })
+    }
+
+    /// ユーザー辞書を追加する
+    /// 追加した順にdict_idが1, 2, ...となる
+    /// 単語の文脈IDがシステム辞書の連接コスト表の範囲外の場合は Error::InvalidMatrixId のエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `user_dict_path` - ユーザー辞書ファイル(UserDictBuilderで構築したもの)のパス
+    pub fn add_user_dict(&mut self, user_dict_path: &str) -> Result<()> {
+        let mmap = map_file(user_dict_path)?;
+        let user_dict = unsafe { DictionarySet::new(extend_lifetime(&mmap))? };
+        // 別のシステム辞書で構築したユーザー辞書だと、解析時に連接コスト表の範囲外を参照してしまう
+        let (left_max, right_max) = self.dict_set.get_matrix_size();
+        user_dict.check_context_ids(left_max, right_max)?;
+        self.user_dicts.push(user_dict);
+        self._mmaps.push(mmap);
+        Ok(())
+    }
+
+    /// システム辞書
+    pub fn get_dict_set<'a>(&'a self) -> &'a DictionarySet<'a, Token> {
+        &self.dict_set
+    }
+
+    /// ユーザー辞書
+    pub fn get_user_dicts<'a>(&'a self) -> &'a [DictionarySet<'a, Token>] {
+        &self.user_dicts
+    }
+
+    /// 文字列を形態素解析して、形態素の配列を返す
+    /// BOS, EOSは含まない
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 解析する文字列
+    pub fn tokenize<'a>(&'a self, text: &'a str) -> Vec<Morpheme<'a>> {
+        self.tokenize_with(&mut Worker::new(), text)
+    }
+
+    /// 作業領域を使いまわして文字列を形態素解析する
+    /// 同じWorkerで繰り返し解析すると、ラティス構造の領域を確保し直さずに済む
+    ///
+    /// # Arguments
+    ///
+    /// * `worker` - 作業領域
+    /// * `text`   - 解析する文字列
+    pub fn tokenize_with<'a>(&'a self, worker: &mut Worker, text: &'a str) -> Vec<Morpheme<'a>> {
+        let lattice = worker.build(self, text);
+        lattice.analyze(self.get_dict_set());
+        self.to_morphemes(text, &lattice.get_result())
+    }
+
+    /// 文字列を形態素解析して、コストの小さい順にn個の解析結果を返す(N-Best解)
+    /// 解析結果は経路の合計コストと形態素の配列の組
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 解析する文字列
+    /// * `n`    - 求める解析結果の数
+    pub fn tokenize_nbest<'a>(&'a self, text: &'a str, n: usize) -> Vec<(i32, Vec<Morpheme<'a>>)> {
+        self.tokenize_nbest_with(&mut Worker::new(), text, n)
+    }
+
+    /// 作業領域を使いまわして、コストの小さい順にn個の解析結果を返す(N-Best解)
+    ///
+    /// # Arguments
+    ///
+    /// * `worker` - 作業領域
+    /// * `text`   - 解析する文字列
+    /// * `n`      - 求める解析結果の数
+    pub fn tokenize_nbest_with<'a>(&'a self, worker: &mut Worker, text: &'a str, n: usize) -> Vec<(i32, Vec<Morpheme<'a>>)> {
+        let lattice = worker.build(self, text);
+        lattice.analyze(self.get_dict_set());
+        lattice.nbest(self.get_dict_set(), n).into_iter().map(|(cost, nodes)| {
+            (cost, self.to_morphemes(text, &nodes))
+        }).collect()
+    }
+
+    /// ラティス構造の解析結果(EOSからBOSの順に並んだノード)を、文字列の先頭から順に並んだ形態素の配列にする
+    fn to_morphemes<'a>(&'a self, text: &'a str, nodes: &[&LatticeNode]) -> Vec<Morpheme<'a>> {
+        let mut morphemes: Vec<Morpheme<'a>> = Vec::with_capacity(nodes.len());
+        let (mut byte_end, mut char_end) = (0, 0);
+        // BOS, EOSは含まない
+        for node in nodes.iter().rev().filter(|node| node.len > 0) {
+            let feature = node.feature(self.get_dict_set(), self.get_user_dicts());
+            // 形態素の前の空白は読み飛ばされているので、その文字数も数える
+            let char_start = char_end + text[byte_end..node.start].chars().count();
+            let morpheme = Morpheme::new(text, node, char_start, feature);
+            byte_end = morpheme.byte_end;
+            char_end = morpheme.char_end;
+            morphemes.push(morpheme);
+        }
+        morphemes
+    }
+}
+
+/// 形態素解析の作業領域
+/// スレッドごとにひとつ生成して、Tokenizer::tokenize_withなどに渡す
+pub struct Worker {
+    lattice: Lattice,
+}
+
+impl Worker {
+    pub fn new() -> Worker {
+        Worker { lattice: Lattice::new(0) }
+    }
+
+    /// 作業領域のラティス構造で文字列のラティス構造を構築する
+    fn build(&mut self, tokenizer: &Tokenizer, text: &str) -> &mut Lattice {
+        self.lattice.clear_and_build(tokenizer.get_dict_set(), tokenizer.get_user_dicts(), text.as_bytes());
+        &mut self.lattice
+    }
+}
+
+impl Default for Worker {
+    fn default() -> Worker {
+        Worker::new()
+    }
+}
+
+/// 形態素
+/// surface, featureは解析した文字列・辞書を参照する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Morpheme<'a> {
+    pub surface   : &'a str,
+    pub byte_start: usize, // 解析した文字列での開始位置(バイト, 包含)
+    pub byte_end  : usize, // 解析した文字列での終了位置(バイト, 排他)
+    pub char_start: usize, // 解析した文字列での開始位置(文字数, 包含)
+    pub char_end  : usize, // 解析した文字列での終了位置(文字数, 排他)
+    pub left_id   : u16,
+    pub right_id  : u16,
+    pub cost      : i16,
+    pub total_cost: i32,   // BOSからこの形態素までの最小の累積コスト
+    pub dict_id   : usize, // 0: システム辞書, n: n番目のユーザー辞書
+    pub is_unknown: bool,  // 未知語か
+    pub feature   : &'a str,
+}
+
+impl<'a> Morpheme<'a> {
+    fn new(text: &'a str, node: &LatticeNode, char_start: usize, feature: &'a str) -> Morpheme<'a> {
+        let byte_start = node.start;
+        let byte_end   = node.start + node.len;
+        let surface  = &text[byte_start..byte_end];
+        Morpheme {
+            surface,
+            byte_start,
+            byte_end,
+            char_start,
+            char_end  : char_start + surface.chars().count(),
+            left_id   : node.token.left_id,
+            right_id  : node.token.right_id,
+            cost      : node.token.cost,
+            total_cost: node.total_cost,
+            dict_id   : node.dict_id,
+            is_unknown: node.is_unknown,
+            feature,
+        }
+    }
+
+    /// 素性をカラムに分割して、品詞・原形・読みなどを参照できるようにする
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - 素性のカラムの位置(辞書の形式)
+    pub fn feature_columns(&self, layout: FeatureLayout) -> Feature {
+        Feature::parse(self.feature, layout)
+    }
+}
+
+fn map_file(path: &str) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    unsafe { MmapOptions::new().map(&file) }
+}
+
+/// mmapした領域の参照を'staticにする
+/// mmapの領域はMmapをmoveしても移動しないので、Mmapを破棄するまでは参照が有効
+unsafe fn extend_lifetime(mmap: &Mmap) -> &'static [u8] {
+    mem::transmute::<&[u8], &'static [u8]>(&mmap[..])
+}
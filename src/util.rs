@@ -1,3 +1,7 @@
+use crate::error::Error;
+
+use std::ops::{Deref, DerefMut};
+use std::slice;
 use std::time::{Duration, Instant};
 
 pub struct Timer {
@@ -29,7 +33,54 @@ impl Timer {
         self.duration = Duration::new(0, 0);
     }
 
+    /// 計測した時間の合計
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
     pub fn print(&self) {
         println!("{:?}", self.duration)
     }
 }
+
+/// エラーを1行で標準エラー出力に出力して、エラーの種類に応じた終了コードで終了する
+///
+/// # Arguments
+///
+/// * `e` - エラー
+pub fn exit_with_error(e: &Error) -> ! {
+    eprintln!("error: {}", e);
+    std::process::exit(e.exit_code())
+}
+
+/// バイト列を8バイト境界に揃えた領域にコピーして保持する
+/// mmapせずに読み込んだ辞書ファイルのバイト列をDictionarySet::newに渡すときに使う
+pub struct AlignedBytes {
+    buf: Vec<u64>,
+    len: usize,
+}
+
+impl AlignedBytes {
+    /// # Arguments
+    ///
+    /// * `bytes` - コピーするバイト列
+    pub fn new(bytes: &[u8]) -> AlignedBytes {
+        let mut aligned = AlignedBytes { buf: vec![0; (bytes.len() + 7) / 8], len: bytes.len() };
+        aligned.copy_from_slice(bytes);
+        aligned
+    }
+}
+
+impl Deref for AlignedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buf[..].as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.buf[..].as_mut_ptr() as *mut u8, self.len) }
+    }
+}
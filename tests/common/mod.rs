@@ -0,0 +1,100 @@
+//! 結合テストで共通して使う、テスト用の辞書の構築
+//! 辞書ファイルをtests/以下に残さないように、辞書はメモリ上に構築するか一時ディレクトリに書き込む
+
+// テストごとに使う関数が異なる
+#![allow(dead_code)]
+
+use midomoji_clone::dictionary::*;
+use midomoji_clone::dictionary::trie::Trie;
+use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::feature_builder::FeatureBuilder;
+use midomoji_clone::dictionary::char_def::CharDefBuilder;
+use midomoji_clone::dictionary::user_dict_builder::UserDictBuilder;
+use midomoji_clone::token::Token;
+use midomoji_clone::util::AlignedBytes;
+
+use std::path::PathBuf;
+
+/// テスト用の単語(表層形, 左文脈ID, 右文脈ID, コスト, 素性)
+/// 未知語の定義では、表層形の代わりに文字種の名前を指定する
+pub type Word<'a> = (&'a str, u16, u16, i16, &'a str);
+
+/// 単語と未知語の定義から(バイト単位の)辞書を構築して、DictionarySet::newで読み込めるバイト列を返す
+///
+/// # Arguments
+///
+/// * `matrix`   - 連接コスト表
+/// * `words`    - 単語
+/// * `char_def` - char.defの内容。Noneの場合は文字種はDEFAULTだけ
+/// * `unknowns` - 未知語の定義
+pub fn build_dictionary(matrix: &MatrixBuilder, words: &[Word], char_def: Option<&str>, unknowns: &[Word]) -> AlignedBytes {
+    let mut features = FeatureBuilder::new();
+    let mut trie: Trie<Token> = Trie::new();
+    for &(surface, left_id, right_id, cost, feature) in words.iter() {
+        let feature_id = features.push(feature);
+        trie.set(surface, Token::new(left_id, right_id, cost, feature_id));
+    }
+    let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
+    let mut char_def: CharDefBuilder<Token> = match char_def {
+        Some(text) => CharDefBuilder::from_char_def(text).unwrap(),
+        None       => CharDefBuilder::new(),
+    };
+    for &(category, left_id, right_id, cost, feature) in unknowns.iter() {
+        let feature_id = features.push(feature);
+        char_def.add_unknown(category, Token::new(left_id, right_id, cost, feature_id)).unwrap();
+    }
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+        matrix, features: &features, char_def: &char_def,
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes).unwrap();
+    AlignedBytes::new(&bytes)
+}
+
+/// ユーザー辞書を構築して、DictionarySet::newで読み込めるバイト列を返す
+///
+/// # Arguments
+///
+/// * `builder` - 単語を追加したUserDictBuilder
+pub fn build_user_dictionary(builder: UserDictBuilder) -> AlignedBytes {
+    let mut bytes: Vec<u8> = Vec::new();
+    builder.write(&mut bytes).unwrap();
+    AlignedBytes::new(&bytes)
+}
+
+/// 一時ディレクトリのファイルのパス
+/// 同時に実行する他のテストと重ならないようにファイル名にプロセスIDを含め、
+/// スコープを抜けると(アサーションが失敗した場合も)ファイルを削除する
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// # Arguments
+    ///
+    /// * `name` - ファイル名
+    pub fn new(name: &str) -> TempPath {
+        TempPath(std::env::temp_dir().join(format!("midomoji-{}-{}", std::process::id(), name)))
+    }
+
+    /// バイト列をファイルに書き込んで、そのパスを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `name`  - ファイル名
+    /// * `bytes` - 書き込む内容(build_dictionaryなどで構築した辞書)
+    pub fn write(name: &str, bytes: &[u8]) -> TempPath {
+        let path = TempPath::new(name);
+        std::fs::write(&path.0, bytes).unwrap();
+        path
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -3,6 +3,9 @@ extern crate midomoji_clone;
 use midomoji_clone::dictionary::*;
 use midomoji_clone::dictionary::trie::Trie;
 use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::feature_builder::FeatureBuilder;
+use midomoji_clone::dictionary::char_def::CharDefBuilder;
+use midomoji_clone::util::AlignedBytes;
 use memmap::*;
 use std::fs::File;
 
@@ -13,11 +16,12 @@ fn test_build_load_dictionary() {
     let mut matrix_builder = MatrixBuilder::new(100, 100);
     for l in 0..100 {
         for r in 0..100 {
-            matrix_builder.set(l, r, (l as i16) * 100 + (r as i16));
+            matrix_builder.set(l, r, (l as i16) * 100 + (r as i16)).unwrap();
         }
     }
     // 単語登録
     let mut trie: Trie<usize> = Trie::new();
+    let mut features = FeatureBuilder::new();
     let words: Vec<String> = vec![
         String::from("abc"),
         String::from("abc"),
@@ -28,15 +32,21 @@ fn test_build_load_dictionary() {
         String::from("お寿司ビール"),
         String::from("🍣🍺"),
     ];
-    for (i, w) in words.into_iter().enumerate() {
-        trie.set(&w, i);
+    for w in words.into_iter() {
+        // 素性IDを値として登録する
+        let feature_id = features.push(&format!("{},名詞", w)) as usize;
+        trie.set(&w, feature_id);
     }
 
     // ダブル配列構築
-    let (base_arr, check_arr, data_arr) = trie.to_double_array();
+    let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
 
     // 辞書書き込み
-    DictionarySet::serialize(&base_arr, &check_arr, &data_arr, matrix_builder, "tests/test.dic").ok().unwrap();
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+        matrix: &matrix_builder, features: &features, char_def: &CharDefBuilder::new(),
+    };
+    DictionarySet::serialize(&parts, "tests/test.dic").ok().unwrap();
 
     // --- --- --- 読み込み --- --- ---
     // 辞書読み込み
@@ -44,7 +54,7 @@ fn test_build_load_dictionary() {
     let mmap: Mmap = unsafe {
         MmapOptions::new().map(&file).ok().unwrap()
     };
-    let dict_set: DictionarySet<usize> = DictionarySet::new(&mmap);
+    let dict_set: DictionarySet<usize> = DictionarySet::new(&mmap).unwrap();
 
     // ダブル配列の探索
     assert_eq!([0, 1], dict_set.get_trie("abc").unwrap());
@@ -57,6 +67,55 @@ fn test_build_load_dictionary() {
     assert_eq!(None, dict_set.get_trie("ahoge"));
     assert_eq!(None, dict_set.get_trie("お寿"));
 
+    // 共通接頭辞検索
+    let found: Vec<(usize, &[usize])> = dict_set.common_prefix_iter(b"acdc").collect();
+    assert_eq!(vec![(2, &[3][..]), (3, &[4][..])], found);
+    let found: Vec<(usize, &[usize])> = dict_set.common_prefix_iter("お寿司ビールください".as_bytes()).collect();
+    assert_eq!(vec![("お寿司ビール".len(), &[6][..])], found);
+    assert_eq!(0, dict_set.common_prefix_iter(b"ab").count());
+    assert_eq!(0, dict_set.common_prefix_iter(b"").count());
+    assert_eq!(vec![("ac", &[3][..]), ("acd", &[4][..])], dict_set.prefix_search("acdc"));
+
+    // 予測検索
+    let found = dict_set.predictive_search("a", 10);
+    assert_eq!(vec!["abc", "abd", "ac", "acd"], found.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>());
+    assert_eq!([0, 1], found[0].1);
+    assert_eq!([4]   , found[3].1);
+    let found = dict_set.predictive_search("a", 3);
+    assert_eq!(vec!["abc", "abd", "ac"], found.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>());
+    let found = dict_set.predictive_search("お", 10);
+    assert_eq!(vec!["おすしビール", "お寿司ビール"], found.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>());
+    let found = dict_set.predictive_search("", 10);
+    assert_eq!(7, found.len()); // "abc" は1つのkey
+    assert_eq!("🍣🍺", found[6].0);
+    assert!(dict_set.predictive_search("b", 10).is_empty());
+    assert!(dict_set.predictive_search("a", 0).is_empty());
+
+    // 全単語の列挙とkeyの復元
+    let mut entries: Vec<(String, Vec<usize>)> = dict_set.entries().map(|(node, values)| {
+        (dict_set.key_of(node).unwrap(), values.to_vec())
+    }).collect();
+    entries.sort();
+    assert_eq!(vec![
+        (String::from("abc")         , vec![0, 1]),
+        (String::from("abd")         , vec![2]),
+        (String::from("ac")          , vec![3]),
+        (String::from("acd")         , vec![4]),
+        (String::from("おすしビール"), vec![5]),
+        (String::from("お寿司ビール"), vec![6]),
+        (String::from("🍣🍺")        , vec![7]),
+    ], entries);
+    // 値のノードでなければNone
+    assert_eq!(None, dict_set.key_of(0));
+    assert_eq!(None, dict_set.key_of(1));
+    assert_eq!(None, dict_set.key_of(usize::max_value()));
+
+    // 素性文字列の探索
+    assert_eq!("abc,名詞"         , dict_set.get_feature(dict_set.get_trie("abc").unwrap()[1]));
+    assert_eq!("abd,名詞"         , dict_set.get_feature(dict_set.get_trie("abd").unwrap()[0]));
+    assert_eq!("お寿司ビール,名詞", dict_set.get_feature(dict_set.get_trie("お寿司ビール").unwrap()[0]));
+    assert_eq!("🍣🍺,名詞"        , dict_set.get_feature(dict_set.get_trie("🍣🍺").unwrap()[0]));
+
     // 連接コスト表の探索
     for l in 0..100 {
         for r in 0..100 {
@@ -67,3 +126,87 @@ fn test_build_load_dictionary() {
 
     std::fs::remove_file("tests/test.dic").ok().unwrap();
 }
+
+#[test]
+fn test_build_load_char_dictionary() {
+    // --- --- --- 構築 --- --- ---
+    let mut trie: Trie<usize> = Trie::new();
+    let mut features = FeatureBuilder::new();
+    let words = ["abc", "abc", "abd", "ac", "acd", "おすしビール", "お寿司ビール", "🍣🍺"];
+    for w in words.iter() {
+        let feature_id = features.push(&format!("{},名詞", w)) as usize;
+        trie.set(w, feature_id);
+    }
+
+    // 文字単位のダブル配列構築
+    let ((base_arr, check_arr, data_arr, data_offsets), char_codes) = trie.to_char_double_array().unwrap();
+
+    // 辞書書き込み
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: Some(&char_codes),
+        matrix: &MatrixBuilder::new(1, 1), features: &features, char_def: &CharDefBuilder::new(),
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes).unwrap();
+    let aligned = AlignedBytes::new(&bytes);
+
+    // --- --- --- 読み込み --- --- ---
+    let dict_set: DictionarySet<usize> = DictionarySet::new(&aligned).unwrap();
+    assert!(dict_set.is_char_double_array());
+    assert_eq!(char_codes.code_chars, dict_set.double_array().code_chars());
+
+    // ダブル配列の探索
+    assert_eq!([0, 1], dict_set.get_trie("abc").unwrap());
+    assert_eq!([5]   , dict_set.get_trie("おすしビール").unwrap());
+    assert_eq!([6]   , dict_set.get_trie("お寿司ビール").unwrap());
+    assert_eq!([7]   , dict_set.get_trie("🍣🍺").unwrap());
+    assert_eq!(None, dict_set.get_trie("お寿"));
+    assert_eq!(None, dict_set.get_trie("お鮨")); // 辞書に現れない文字
+
+    // 共通接頭辞検索
+    let found: Vec<(usize, &[usize])> = dict_set.common_prefix_iter(b"acdc").collect();
+    assert_eq!(vec![(2, &[3][..]), (3, &[4][..])], found);
+    let found: Vec<(usize, &[usize])> = dict_set.common_prefix_iter("お寿司ビールください".as_bytes()).collect();
+    assert_eq!(vec![("お寿司ビール".len(), &[6][..])], found);
+    // 文字の途中から始まる場合は何も見つからない
+    assert_eq!(0, dict_set.common_prefix_iter(&"🍣🍺".as_bytes()[1..]).count());
+
+    // 予測検索はバイト単位と同じ順に並ぶ
+    let found = dict_set.predictive_search("", 10);
+    assert_eq!(
+        vec!["abc", "abd", "ac", "acd", "おすしビール", "お寿司ビール", "🍣🍺"],
+        found.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>(),
+    );
+    let found = dict_set.predictive_search("お", 1);
+    assert_eq!(vec!["おすしビール"], found.iter().map(|(k, _)| k.as_str()).collect::<Vec<&str>>());
+
+    // 全単語の列挙とkeyの復元
+    let mut entries: Vec<(String, Vec<usize>)> = dict_set.entries().map(|(node, values)| {
+        (dict_set.key_of(node).unwrap(), values.to_vec())
+    }).collect();
+    entries.sort();
+    assert_eq!(7, entries.len());
+    assert_eq!((String::from("🍣🍺"), vec![7]), entries[6]);
+    assert_eq!("お寿司ビール,名詞", dict_set.get_feature(dict_set.get_trie("お寿司ビール").unwrap()[0]));
+}
+
+#[test]
+fn test_double_array_mmap() {
+    let entries = vec![("東京", 1u32), ("東京都", 2), ("京都", 3), ("京都府", 4), ("京都", 5)];
+    let da: double_array::DoubleArray<u32> = entries.into_iter().collect();
+    let path = std::env::temp_dir().join(format!("midomoji-double-array-{}.bin", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    da.serialize(&path).unwrap();
+
+    // mmapした領域をコピーせずに探索できる
+    let file = File::open(&path).unwrap();
+    let mmap: Mmap = unsafe { MmapOptions::new().map(&file).unwrap() };
+    let da_ref: double_array::DoubleArrayRef<u32> = double_array::DoubleArrayRef::new(&mmap).unwrap();
+    assert_eq!(4, da_ref.len());
+    assert_eq!(Some(&[3, 5][..]), da_ref.get("京都"));
+    let found: Vec<(&str, &[u32])> = da_ref.common_prefix_search("東京都庁");
+    assert_eq!(vec![("東京", &[1][..]), ("東京都", &[2][..])], found);
+    let found: Vec<String> = da_ref.predictive_search("京", 10).into_iter().map(|(key, _)| key).collect();
+    assert_eq!(vec!["京都".to_string(), "京都府".to_string()], found);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,61 +1,382 @@
 extern crate midomoji_clone;
 
+mod common;
+
 use midomoji_clone::dictionary::*;
+use midomoji_clone::dictionary::trie::Trie;
+use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::feature_builder::FeatureBuilder;
+use midomoji_clone::dictionary::char_def::CharDefBuilder;
+use midomoji_clone::dictionary::user_dict_builder::UserDictBuilder;
+use midomoji_clone::dictionary::feature_builder::join_feature;
+use midomoji_clone::dictionary::lex_reader::{read_csv, parse_record};
 use midomoji_clone::token::Token;
 use midomoji_clone::lattice::Lattice;
+use midomoji_clone::util::AlignedBytes;
 
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::fs::File;
 use memmap::*;
 
+/// MeCab(IPADIC)の解析結果と、解析結果のトークン・経路の合計コストが一致するかを検証する
+/// IPADICの辞書ファイルとMeCabの解析結果はリポジトリに含めないので、通常のcargo testでは実行しない
+///
+/// 実行するには、MeCabとIPADICを用意して次の手順で辞書と期待値を生成してから
+/// cargo test --test lattice_test -- --ignored を実行する
+///
+/// 1. IPADICのlex.csv(*.csvを連結してutf8にしたもの)、matrix.def、char.def、unk.defから辞書を構築する
+///    未知語の文脈IDとコストもMeCabと比較するので、char.defとunk.defは必ず指定すること
+///    cargo run --bin build-dict --release -- <IPADIC>/lex.csv <IPADIC>/matrix.def tests/lattice_test/ipa.dic --char-def <IPADIC>/char.def --unk-def <IPADIC>/unk.def
+/// 2. 1行1文のファイル(数百文程度。文脈IDの左右が異なる単語を含むもの)を用意して、MeCabの解析結果から期待値を生成する
+///    tests/lattice_test/make_input.sh <SENTENCES_PATH> <MECAB_IPADIC_DIR> > tests/lattice_test/input.tsv
+///    MeCabの辞書は1.と同じIPADICを指定すること。3列目に経路の合計コストが出力される
 #[test]
+#[ignore]
 fn test_lattice() {
-    // 辞書構築
-    // 辞書ファイルは予め↓で作っておく
-    // cargo run --bin build-dict --release -- tests/lattice_test/lex.csv tests/lattice_test/matrix.def tests/lattice_test/ipa.dic
     let dict_file: File = File::open("tests/lattice_test/ipa.dic").unwrap();
     let mmap: Mmap = unsafe {
         MmapOptions::new().map(&dict_file).ok().unwrap()
     };
-    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap);
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&mmap).unwrap();
+    assert_mecab_result(&dict_set, "tests/lattice_test/input.tsv");
+}
+
+/// リポジトリに含めた小さな辞書(tests/lattice_test/fixture)で、MeCabの解析結果と一致するかを検証する
+/// 辞書はMeCabの辞書と同じ形式のlex.csv, matrix.def, char.def, unk.defから構築する
+///
+/// 期待値を生成し直す場合は、MeCabの辞書を構築してからmake_input.shを実行する
+///    mecab-dict-index -d tests/lattice_test/fixture -o <OUTPUT_DIR> -f utf8 -t utf8
+///    cut -f 1 tests/lattice_test/fixture/input.tsv > <SENTENCES_PATH>
+///    tests/lattice_test/make_input.sh <SENTENCES_PATH> <OUTPUT_DIR> > tests/lattice_test/fixture/input.tsv
+#[test]
+fn test_lattice_fixture() {
+    for &char_unit in [false, true].iter() {
+        let bytes = build_fixture_dictionary(char_unit);
+        let aligned = AlignedBytes::new(&bytes);
+        let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+        assert_mecab_result(&dict_set, "tests/lattice_test/fixture/input.tsv");
+    }
+}
+
+#[test]
+fn test_lattice_space() {
+    let bytes = build_fixture_dictionary(false);
+    let aligned = AlignedBytes::new(&bytes);
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+    let analyze = |query: &str| -> Vec<(usize, String)> {
+        let mut lattice = Lattice::build(&dict_set, query.as_bytes());
+        lattice.analyze(&dict_set);
+        let result = lattice.get_result();
+        result[1..(result.len() - 1)].iter().rev().map(|node| {
+            (node.start, std::str::from_utf8(node.surface(query.as_bytes())).unwrap().to_string())
+        }).collect()
+    };
+    // 空白(SPACE)はノードにならず、ノードは空白の後ろから始まる
+    let expected = vec![(2, "すもも".to_string()), (12, "もも".to_string())];
+    assert_eq!(expected, analyze("  すもも\tもも　"));
+    // 空白を挟んでも、空白がない場合と同じ単語の並びとコストになる
+    let mut lattice = Lattice::build(&dict_set, "私は 猫が　好きです".as_bytes());
+    lattice.analyze(&dict_set);
+    let mut expected = Lattice::build(&dict_set, "私は猫が好きです".as_bytes());
+    expected.analyze(&dict_set);
+    assert_eq!(expected.get_result()[0].total_cost, lattice.get_result()[0].total_cost);
+    assert_eq!(8, lattice.get_result().len());
+    // 空白だけの文はBOSとEOSだけ
+    assert!(analyze("   ").is_empty());
 
+    // char.defで空白の文字種を定義しない場合は、空白も未知語になる
+    let mut char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+    char_def.add_unknown("DEFAULT", Token::new(0, 0, 100, 0)).unwrap();
+    let parts = DictionaryParts {
+        base_arr: &[0; 256], check_arr: &[0; 256], data_arr: &[], data_offsets: &[0], char_codes: None,
+        matrix: &MatrixBuilder::new(1, 1), features: &FeatureBuilder::new(), char_def: &char_def,
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes).unwrap();
+    let aligned = AlignedBytes::new(&bytes);
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+    assert_eq!(None, dict_set.get_space_char_info());
+    let mut lattice = Lattice::build(&dict_set, b"a b");
+    lattice.analyze(&dict_set);
+    assert_eq!(5, lattice.get_result().len());
+}
+
+/// tests/lattice_test/fixture の辞書を構築して、辞書ファイルのバイト列を返す
+///
+/// # Arguments
+///
+/// * `char_unit` - trueなら文字単位、falseならバイト単位のダブル配列にする
+fn build_fixture_dictionary(char_unit: bool) -> Vec<u8> {
+    let dir = "tests/lattice_test/fixture";
+    let matrix_builder = MatrixBuilder::from_matrix_def(BufReader::new(File::open(format!("{}/matrix.def", dir)).unwrap())).unwrap();
+    let mut features = FeatureBuilder::new();
+    let mut trie: Trie<Token> = Trie::new();
+    read_csv(&format!("{}/lex.csv", dir), |record| {
+        let (surface, left_id, right_id, cost) = parse_record(record)?;
+        trie.set(surface, Token::new(left_id, right_id, cost, features.push(&join_feature(record.iter().skip(4)))));
+        Ok(())
+    }).unwrap();
+    let char_def_text = std::fs::read_to_string(format!("{}/char.def", dir)).unwrap();
+    let mut char_def: CharDefBuilder<Token> = CharDefBuilder::from_char_def(&char_def_text).unwrap();
+    read_csv(&format!("{}/unk.def", dir), |record| {
+        let (name, left_id, right_id, cost) = parse_record(record)?;
+        char_def.add_unknown(name, Token::new(left_id, right_id, cost, features.push(&join_feature(record.iter().skip(4)))))
+    }).unwrap();
+    let ((base_arr, check_arr, data_arr, data_offsets), char_codes) = if char_unit {
+        let (double_array, char_codes) = trie.to_char_double_array().unwrap();
+        (double_array, Some(char_codes))
+    } else {
+        (trie.to_double_array().unwrap(), None)
+    };
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: char_codes.as_ref(),
+        matrix: &matrix_builder, features: &features, char_def: &char_def,
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes).unwrap();
+    bytes
+}
+
+/// make_input.shで生成したMeCabの解析結果と、解析結果のトークン・経路の合計コストが一致するかを検証する
+/// 1行は「文\tトークン...\t経路の合計コスト」で、トークンは「表層形 US 左文脈ID US 右文脈ID US コスト RS」
+/// 表層形に「,」などが現れても分割できるように、区切り文字は制御文字(US: 0x1F, RS: 0x1E)
+///
+/// # Arguments
+///
+/// * `dict_set`   - 期待値の生成に使ったMeCabの辞書と同じ内容の辞書
+/// * `input_path` - make_input.shの出力
+fn assert_mecab_result(dict_set: &DictionarySet<Token>, input_path: &str) {
     // reader
-    let reader: BufReader<File> = BufReader::new(File::open("tests/lattice_test/input.tsv").unwrap());
+    let reader: BufReader<File> = BufReader::new(File::open(input_path).unwrap());
 
     for line in reader.lines() {
         let line = line.unwrap();
-        let mut split = line.trim().split("\t");
+        // 文の前後の空白も解析対象なので、改行以外は取り除かない
+        let mut split = line.trim_end_matches('\r').split('\t');
         // クエリ文字列
         let query: &str    = split.next().unwrap();
         // 結果文字列
         let result: &str = split.next().unwrap();
+        // 経路の合計コスト(MeCabのEOSの%pc)。省略可能
+        let total_cost: Option<i32> = split.next().map(|c| c.parse::<i32>().unwrap());
 
         // 期待するトークンを格納する配列
-        let mut expected: Vec<(String, Token)> = Vec::new();
-        for e in result.split(";") {
-            let mut token_str = e.split(",");
+        let mut expected: Vec<(String, u16, u16, i16)> = Vec::new();
+        for e in result.split('\x1e').filter(|e| !e.is_empty()) {
+            let mut token_str = e.split('\x1f');
             let surface  = token_str.next().unwrap().to_string();
             let left_id  = token_str.next().unwrap().parse::<u16>().unwrap();
             let right_id = token_str.next().unwrap().parse::<u16>().unwrap();
             let cost     = token_str.next().unwrap().parse::<i16>().unwrap();
-            let expected_token = Token { left_id, right_id, cost }; // 期待するトークン
-            expected.push((surface, expected_token));
+            expected.push((surface, left_id, right_id, cost)); // 期待するトークン
         }
 
         // ラティス構造構築
-        let mut lattice = Lattice::build(&dict_set, query.as_bytes());
-        lattice.analyze(&dict_set);
+        let mut lattice = Lattice::build(dict_set, query.as_bytes());
+        lattice.analyze(dict_set);
         let actual = lattice.get_result();
+        assert_eq!(expected.len(), actual.len() - 2, "{}", query);
+        if let Some(total_cost) = total_cost {
+            assert_eq!(total_cost, actual[0].total_cost, "{}", query);
+        }
 
         // 解析結果が期待する値になっているかを検証
         for (i, node) in actual[1..(actual.len() - 1)].iter().rev().enumerate() {
             // 解析結果のトークン
-            let actual_token = (std::str::from_utf8(node.surface).unwrap().to_string(), node.token);
-            assert_eq!(actual_token, expected[i]);
+            let token = node.token;
+            let actual_token = (std::str::from_utf8(node.surface(query.as_bytes())).unwrap().to_string(), token.left_id, token.right_id, token.cost);
+            assert_eq!(actual_token, expected[i], "{}", query);
         }
     }
+}
+
+#[test]
+fn test_lattice_connection_cost() {
+    // --- --- --- 構築 --- --- ---
+    // 連接コストは左側の単語の右文脈IDと右側の単語の左文脈IDで引く(matrix.defの1列目, 2列目)
+    // 文脈IDの向きを間違えると「a」「b」に分割されてしまう
+    let mut matrix_builder = MatrixBuilder::new(3, 3);
+    matrix_builder.set(0, 1, 5).unwrap();    // BOS -> 左文脈ID=1
+    matrix_builder.set(1, 0, 7).unwrap();    // 右文脈ID=1 -> EOS
+    matrix_builder.set(2, 1, 1000).unwrap(); // 右文脈ID=2 -> 左文脈ID=1
+    let mut features = FeatureBuilder::new();
+    let mut trie: Trie<Token> = Trie::new();
+    for &(surface, left_id, right_id, cost) in [("a", 1, 2, 10), ("b", 1, 1, 10), ("ab", 1, 1, 100)].iter() {
+        let feature_id = features.push(surface);
+        trie.set(surface, Token::new(left_id, right_id, cost, feature_id));
+    }
+    let (base_arr, check_arr, data_arr, data_offsets) = trie.to_double_array().unwrap();
+    let mut char_def: CharDefBuilder<Token> = CharDefBuilder::new();
+    char_def.add_unknown("DEFAULT", Token::unknown()).unwrap();
+    let parts = DictionaryParts {
+        base_arr: &base_arr, check_arr: &check_arr, data_arr: &data_arr, data_offsets: &data_offsets, char_codes: None,
+        matrix: &matrix_builder, features: &features, char_def: &char_def,
+    };
+    let mut bytes: Vec<u8> = Vec::new();
+    DictionarySet::write(&parts, &mut bytes).unwrap();
+    let aligned = AlignedBytes::new(&bytes);
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+    assert_eq!(1000, dict_set.get_matrix(2, 1));
+    assert_eq!(0   , dict_set.get_matrix(1, 2));
+
+    // --- --- --- 解析 --- --- ---
+    let mut lattice = Lattice::build(&dict_set, b"ab");
+    // BOS, a, b, ab, EOS と、DEFAULTはINVOKE=1なので1文字の未知語 a, b
+    assert_eq!(7, lattice.node_count());
+    lattice.analyze(&dict_set);
+    let result = lattice.get_result();
+    let surfaces: Vec<&str> = result[1..(result.len() - 1)].iter().rev().map(|node| std::str::from_utf8(node.surface(b"ab")).unwrap()).collect();
+    assert_eq!(vec!["ab"], surfaces);
+    // 5(BOS -> ab) + 100(ab) + 7(ab -> EOS)
+    assert_eq!(112, result[0].total_cost);
+    // 2番目の解は 5(BOS -> a) + 10(a) + 1000(a -> b) + 10(b) + 7(b -> EOS)
+    let nbest = lattice.nbest(&dict_set, 2);
+    assert_eq!(vec![112, 1032], nbest.iter().map(|(cost, _)| *cost).collect::<Vec<i32>>());
+}
+
 
+#[test]
+fn test_lattice_unknown() {
+    // --- --- --- 構築 --- --- ---
+    let char_def_text = "
+DEFAULT  0 1 0
+KATAKANA 1 1 0
+NUMERIC  1 1 0
+KANJI    0 0 2
+SYMBOL   0 0 1
+0x0021..0x002F SYMBOL
+0x0030..0x0039 NUMERIC
+0x30A1..0x30FF KATAKANA
+0x4E00..0x9FA5 KANJI
+";
+    let aligned = common::build_dictionary(
+        &MatrixBuilder::new(1, 1),
+        &[("を", 0, 0, 10, "を,既知語"), ("台", 0, 0, 10, "台,既知語"), ("テ", 0, 0, 10, "テ,既知語")],
+        Some(char_def_text),
+        &[
+            ("DEFAULT" , 0, 0, 1000, "DEFAULT,未知語"),
+            ("KATAKANA", 0, 0, 100 , "KATAKANA,未知語"),
+            ("NUMERIC" , 0, 0, 100 , "NUMERIC,未知語"),
+            ("KANJI"   , 0, 0, 100 , "KANJI,未知語"),
+            ("SYMBOL"  , 0, 0, 100 , "SYMBOL,未知語"),
+        ],
+    );
+
+    // --- --- --- 解析 --- --- ---
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+    let analyze = |query: &str| -> Vec<String> {
+        let mut lattice = Lattice::build(&dict_set, query.as_bytes());
+        lattice.analyze(&dict_set);
+        let result = lattice.get_result();
+        result[1..(result.len() - 1)].iter().rev().map(|node| {
+            format!("{}/{}", std::str::from_utf8(node.surface(query.as_bytes())).unwrap(), node.feature(&dict_set, &[]))
+        }).collect()
+    };
+    // 同じ文字種の文字はまとめて未知語になる。INVOKE=1なら辞書に単語があっても未知語処理が行われる
+    assert_eq!(vec!["テレビ/KATAKANA,未知語", "を/を,既知語", "123/NUMERIC,未知語", "台/台,既知語"], analyze("テレビを123台"));
+    // GROUP=0, LENGTH=2なら2文字までの未知語になる
+    assert_eq!(vec!["形態/KANJI,未知語", "素論/KANJI,未知語"], analyze("形態素論"));
+    // 文字種の定義がない文字はDEFAULT(GROUP=1)なので、まとめて未知語になる
+    assert_eq!(vec!["ab/DEFAULT,未知語"], analyze("ab"));
+    // GROUP=0, LENGTH=1なら1文字ずつの未知語になる
+    assert_eq!(vec!["!/SYMBOL,未知語", "#/SYMBOL,未知語", "%/SYMBOL,未知語"], analyze("!#%"));
 
+    // ラティス構造を使いまわしても結果は同じ
+    let queries = ["テレビを123台", "ab", "形態素論", "!#%", "", "テレビを123台"];
+    let mut lattice = Lattice::new(0);
+    for query in queries.iter() {
+        lattice.clear_and_build(&dict_set, &[], query.as_bytes());
+        lattice.analyze(&dict_set);
+        let result = lattice.get_result();
+        let actual: Vec<String> = result[1..(result.len() - 1)].iter().rev().map(|node| {
+            format!("{}/{}", std::str::from_utf8(node.surface(query.as_bytes())).unwrap(), node.feature(&dict_set, &[]))
+        }).collect();
+        assert_eq!(analyze(query), actual);
+    }
+}
+
+#[test]
+fn test_lattice_nbest() {
+    // --- --- --- 構築 --- --- ---
+    let aligned = common::build_dictionary(
+        &MatrixBuilder::new(1, 1),
+        &[("す", 0, 0, 100, "す"), ("も", 0, 0, 100, "も"), ("すも", 0, 0, 180, "すも"), ("もも", 0, 0, 150, "もも"), ("すもも", 0, 0, 400, "すもも")],
+        None,
+        &[("DEFAULT", 0, 0, 10000, "未知語")],
+    );
+
+    // --- --- --- 解析 --- --- ---
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+    let mut lattice = Lattice::build(&dict_set, "すもも".as_bytes());
+    lattice.analyze(&dict_set);
+    let nbest: Vec<(i32, Vec<String>)> = lattice.nbest(&dict_set, 4).into_iter().map(|(cost, nodes)| {
+        let surfaces = nodes[1..(nodes.len() - 1)].iter().rev().map(|node| std::str::from_utf8(node.surface("すもも".as_bytes())).unwrap().to_string()).collect();
+        (cost, surfaces)
+    }).collect();
+    // コストの小さい順に並ぶ
+    assert_eq!(vec![
+        (250, vec!["す".to_string(), "もも".to_string()]),
+        (280, vec!["すも".to_string(), "も".to_string()]),
+        (300, vec!["す".to_string(), "も".to_string(), "も".to_string()]),
+        (400, vec!["すもも".to_string()]),
+    ], nbest);
+    // 1番目の解析結果はget_resultと一致する
+    let best: Vec<*const _> = lattice.get_result().into_iter().map(|node| node as *const _).collect();
+    let first: Vec<*const _> = lattice.nbest(&dict_set, 1)[0].1.iter().map(|&node| node as *const _).collect();
+    assert_eq!(best, first);
+    // 経路の数より多く求めた場合は、すべての経路を返す(各文字に1文字の未知語もある)
+    assert_eq!(13, lattice.nbest(&dict_set, 100).len());
 }
 
+#[test]
+fn test_lattice_user_dict() {
+    // --- --- --- システム辞書構築 --- --- ---
+    let aligned = common::build_dictionary(
+        &MatrixBuilder::new(3, 3),
+        &[
+            ("東京", 1, 1, 100, "名詞,固有名詞,地域,一般,*,*,東京"),
+            ("大阪", 1, 1, 100, "名詞,固有名詞,地域,一般,*,*,大阪"),
+            ("駅"  , 2, 2, 100, "名詞,接尾,一般,*,*,*,駅"),
+        ],
+        None,
+        &[("DEFAULT", 0, 0, 10000, "未知語")],
+    );
+    let dict_set: DictionarySet<Token> = DictionarySet::new(&aligned).unwrap();
+
+    // --- --- --- ユーザー辞書構築 --- --- ---
+    let mut builder = UserDictBuilder::new(&dict_set);
+    // 文脈IDを省略した場合は品詞からシステム辞書の文脈IDを求める
+    builder.add(&["ミドモジ", "", "", "100", "名詞", "固有名詞", "地域", "一般", "*", "*", "ミドモジ"]).unwrap();
+    builder.add(&["ミドモジ駅", "2", "2", "500", "名詞", "固有名詞", "一般", "*", "*", "*", "ミドモジ駅"]).unwrap();
+    // システム辞書にない品詞
+    assert!(builder.add(&["ミドモジ", "", "", "100", "名詞", "固有名詞", "人名", "一般"]).is_err());
+    // 連接コスト表の範囲外の文脈ID
+    assert!(builder.add(&["ミドモジ", "3", "0", "100", "名詞"]).is_err());
+    // コストが不正
+    assert!(builder.add(&["ミドモジ", "1", "1", "abc", "名詞"]).is_err());
+    let user_bytes_1 = common::build_user_dictionary(builder);
+    let mut builder = UserDictBuilder::new(&dict_set);
+    builder.add(&["クローン", "1", "1", "100", "名詞", "一般", "*", "*", "*", "*", "クローン"]).unwrap();
+    let user_bytes_2 = common::build_user_dictionary(builder);
+
+    // --- --- --- 解析 --- --- ---
+    let user_dicts: Vec<DictionarySet<Token>> = [&user_bytes_1, &user_bytes_2].iter().map(|bytes| DictionarySet::new(bytes).unwrap()).collect();
+    let analyze = |query: &str, user_dicts: &[DictionarySet<Token>]| -> Vec<String> {
+        let mut lattice = Lattice::build_with_user_dicts(&dict_set, user_dicts, query.as_bytes());
+        lattice.analyze(&dict_set);
+        let result = lattice.get_result();
+        result[1..(result.len() - 1)].iter().rev().map(|node| {
+            format!("{}/{}/{}", std::str::from_utf8(node.surface(query.as_bytes())).unwrap(), node.dict_id, node.feature(&dict_set, user_dicts))
+        }).collect()
+    };
+    // ユーザー辞書がなければ1文字ずつの未知語
+    assert_eq!(vec!["東京/0/名詞,固有名詞,地域,一般,*,*,東京", "ミ/0/未知語", "ド/0/未知語", "モ/0/未知語", "ジ/0/未知語"], analyze("東京ミドモジ", &[]));
+    // ユーザー辞書の単語はdict_idとそのユーザー辞書の素性を持つ
+    assert_eq!(vec!["東京/0/名詞,固有名詞,地域,一般,*,*,東京", "ミドモジ/1/名詞,固有名詞,地域,一般,*,*,ミドモジ"], analyze("東京ミドモジ", &user_dicts));
+    assert_eq!(vec!["ミドモジ/1/名詞,固有名詞,地域,一般,*,*,ミドモジ", "クローン/2/名詞,一般,*,*,*,*,クローン"], analyze("ミドモジクローン", &user_dicts));
+    // システム辞書とユーザー辞書の単語はコストで比較される
+    assert_eq!(vec!["ミドモジ/1/名詞,固有名詞,地域,一般,*,*,ミドモジ", "駅/0/名詞,接尾,一般,*,*,*,駅"], analyze("ミドモジ駅", &user_dicts));
+    // ユーザー辞書は連接コスト表を持たない
+    assert_eq!((0, 0), user_dicts[0].get_matrix_size());
+}
@@ -0,0 +1,20 @@
+#!/bin/sh
+# MeCabの解析結果からlattice_testのinput.tsvを生成する
+# 1行1文のファイルを読み込み、「文\t表層形 US 左文脈ID US 右文脈ID US コスト RS ...\t経路の合計コスト」を出力する
+# 表層形には「,」や「;」も現れるので、区切り文字には文に現れない制御文字を使う
+# (US: 0x1F トークン内の区切り, RS: 0x1E トークンの終わり)
+# MeCabの辞書は tests/lattice_test/ipa.dic の構築に使ったものと同じものを指定すること
+#
+# usage: make_input.sh <SENTENCES_PATH> [<MECAB_DIC_DIR>] > tests/lattice_test/input.tsv
+set -eu
+SENTENCES="$1"
+DIC_OPT=""
+if [ $# -ge 2 ]; then
+    DIC_OPT="-d $2"
+fi
+US=$(printf '\037')
+RS=$(printf '\036')
+while IFS= read -r line; do
+    printf '%s\t' "$line"
+    printf '%s\n' "$line" | mecab $DIC_OPT -F "%m${US}%phl${US}%phr${US}%c${RS}" -E '\t%pc\n'
+done < "$SENTENCES"
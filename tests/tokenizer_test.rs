@@ -0,0 +1,95 @@
+extern crate midomoji_clone;
+
+mod common;
+
+use midomoji_clone::dictionary::matrix_builder::MatrixBuilder;
+use midomoji_clone::dictionary::feature_builder::FeatureLayout;
+use midomoji_clone::dictionary::user_dict_builder::UserDictBuilder;
+use midomoji_clone::error::Error;
+use midomoji_clone::tokenizer::{Tokenizer, Morpheme, Worker};
+
+use common::TempPath;
+
+#[test]
+fn test_tokenize() {
+    // --- --- --- 構築 --- --- ---
+    let bytes = common::build_dictionary(
+        &MatrixBuilder::new(2, 2),
+        &[("すもも", 1, 1, 100, "名詞,一般,*,*,*,*,すもも"), ("も", 1, 1, 100, "助詞,係助詞,*,*,*,*,も"), ("もも", 1, 1, 100, "名詞,一般,*,*,*,*,もも")],
+        None,
+        &[("DEFAULT", 0, 0, 10000, "未知語")],
+    );
+    let dict_path = TempPath::write("tokenizer_test.dic", &bytes);
+
+    // --- --- --- 解析 --- --- ---
+    let mut tokenizer = Tokenizer::new(dict_path.as_str()).unwrap();
+    let morphemes = tokenizer.tokenize("すもも🍑もも");
+    // BOS, EOSは含まず、文字列の先頭から順に並ぶ
+    assert_eq!(vec![
+        Morpheme { surface: "すもも", byte_start: 0, byte_end: 9, char_start: 0, char_end: 3, left_id: 1, right_id: 1, cost: 100, total_cost: 100, dict_id: 0, is_unknown: false, feature: "名詞,一般,*,*,*,*,すもも" },
+        Morpheme { surface: "🍑", byte_start: 9, byte_end: 13, char_start: 3, char_end: 4, left_id: 0, right_id: 0, cost: 10000, total_cost: 10100, dict_id: 0, is_unknown: true, feature: "未知語" },
+        Morpheme { surface: "もも", byte_start: 13, byte_end: 19, char_start: 4, char_end: 6, left_id: 1, right_id: 1, cost: 100, total_cost: 10200, dict_id: 0, is_unknown: false, feature: "名詞,一般,*,*,*,*,もも" },
+    ], morphemes);
+    // 素性はカラムに分割して参照できる
+    let feature = morphemes[0].feature_columns(FeatureLayout::IPADIC);
+    assert_eq!(vec!["名詞", "一般", "*", "*"], feature.pos());
+    assert_eq!(Some("すもも"), feature.lemma());
+    assert_eq!(None, feature.reading());
+    assert_eq!(Some("もも"), tokenizer.get_dict_set().get_feature_columns(2, FeatureLayout::IPADIC).lemma());
+    // 空文字列
+    assert_eq!(Vec::<Morpheme>::new(), tokenizer.tokenize(""));
+    // N-Bestの1番目はtokenizeの結果と同じ
+    let nbest = tokenizer.tokenize_nbest("すもも🍑もも", 2);
+    assert_eq!(2, nbest.len());
+    assert_eq!(morphemes, nbest[0].1);
+    assert!(nbest[0].0 <= nbest[1].0);
+    // 作業領域を使いまわしても結果は同じ
+    let mut worker = Worker::new();
+    for text in ["すもも🍑もも", "", "もも", "すもも🍑もも"].iter() {
+        let text = text.to_string();
+        assert_eq!(tokenizer.tokenize(&text), tokenizer.tokenize_with(&mut worker, &text));
+        assert_eq!(tokenizer.tokenize_nbest(&text, 3), tokenizer.tokenize_nbest_with(&mut worker, &text, 3));
+    }
+
+    // ユーザー辞書
+    let mut builder = UserDictBuilder::new(tokenizer.get_dict_set());
+    builder.add(&["🍑", "", "", "100", "名詞", "一般", "*", "*", "*", "*", "もも"]).unwrap();
+    let user_dict_path = TempPath::write("tokenizer_test_user.dic", &common::build_user_dictionary(builder));
+    tokenizer.add_user_dict(user_dict_path.as_str()).unwrap();
+    let morphemes = tokenizer.tokenize("すもも🍑もも");
+    assert_eq!(
+        Morpheme { surface: "🍑", byte_start: 9, byte_end: 13, char_start: 3, char_end: 4, left_id: 1, right_id: 1, cost: 100, total_cost: 200, dict_id: 1, is_unknown: false, feature: "名詞,一般,*,*,*,*,もも" },
+        morphemes[1]
+    );
+
+    // 文脈IDがシステム辞書の連接コスト表の範囲外のユーザー辞書はエラー
+    let bytes = common::build_dictionary(&MatrixBuilder::new(0, 0), &[("🍑", 2, 1, 100, "")], None, &[]);
+    let invalid_path = TempPath::write("tokenizer_test_invalid.dic", &bytes);
+    match tokenizer.add_user_dict(invalid_path.as_str()) {
+        Err(Error::InvalidMatrixId { left_id: 2, right_id: 1, left_max: 2, right_max: 2 }) => {},
+        r => panic!("{:?}", r),
+    }
+    assert_eq!(1, tokenizer.get_user_dicts().len());
+
+    // 辞書ファイルがない場合はエラー
+    assert!(Tokenizer::new(TempPath::new("not_found.dic").as_str()).is_err());
+}
+
+#[test]
+fn test_tokenize_space() {
+    let bytes = common::build_dictionary(
+        &MatrixBuilder::new(2, 2),
+        &[("もも", 1, 1, 100, "名詞,一般,*,*,*,*,もも")],
+        Some("DEFAULT 0 1 0\nSPACE 0 1 0\n0x0020 SPACE\n"),
+        &[("DEFAULT", 0, 0, 10000, "未知語"), ("SPACE", 0, 0, 10000, "未知語")],
+    );
+    let dict_path = TempPath::write("tokenizer_space_test.dic", &bytes);
+    let tokenizer = Tokenizer::new(dict_path.as_str()).unwrap();
+
+    // 形態素の前の空白は読み飛ばされ、位置は空白の後ろから数える
+    let morphemes = tokenizer.tokenize(" もも  もも");
+    assert_eq!(
+        vec![(1, 7, 1, 3), (9, 15, 5, 7)],
+        morphemes.iter().map(|m| (m.byte_start, m.byte_end, m.char_start, m.char_end)).collect::<Vec<_>>()
+    );
+}